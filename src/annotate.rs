@@ -0,0 +1,325 @@
+//! Attributes recording where inlined modules were loaded from.
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use proc_macro2::{Ident, Span};
+use syn::{parse_quote, AttrStyle, Attribute, ItemMod, Lit, LitByteStr, LitStr};
+
+/// The name of the attribute added to inlined modules by `InlinerBuilder::annotate_paths`.
+///
+/// This can be used to remove the annotations from the output, e.g. with
+/// `attrs.retain(|attr| !attr.path().is_ident(SYN_INLINE_MOD_PATH_ATTR))`.
+pub const SYN_INLINE_MOD_PATH_ATTR: &str = "syn_inline_mod_path";
+
+/// The name of the attribute added to inlined modules by
+/// `InlinerBuilder::annotate_module_paths`.
+pub const SYN_INLINE_MOD_MODULE_PATH_ATTR: &str = "syn_inline_mod_module_path";
+
+/// How paths that aren't valid UTF-8 are stored by `InlinerBuilder::annotate_paths`.
+///
+/// Paths that are valid UTF-8 are always stored as string literals.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PathEncoding {
+    /// Store the path as a byte string in the platform's encoding of `OsStr`. The path can
+    /// only be read back on a platform with the same encoding.
+    #[default]
+    Bytes,
+    /// Store the path as a string, replacing invalid sequences with `U+FFFD`. The path can
+    /// be read back on any platform, but may not be the original path.
+    Utf8Lossy,
+    /// Fail to inline the module instead of storing the path.
+    Utf8Strict,
+}
+
+/// Create the attribute recording that a module was loaded from `path`.
+///
+/// Returns `None` if `path` isn't valid UTF-8 and `encoding` is `Utf8Strict`.
+pub(crate) fn path_annotation(path: &Path, encoding: PathEncoding) -> Option<Attribute> {
+    let name = Ident::new(SYN_INLINE_MOD_PATH_ATTR, Span::call_site());
+    let lit = match (path.to_str(), encoding) {
+        (Some(path), _) => Lit::Str(LitStr::new(path, Span::call_site())),
+        (None, PathEncoding::Bytes) => Lit::ByteStr(LitByteStr::new(
+            path.as_os_str().as_encoded_bytes(),
+            Span::call_site(),
+        )),
+        (None, PathEncoding::Utf8Lossy) => {
+            Lit::Str(LitStr::new(&path.to_string_lossy(), Span::call_site()))
+        }
+        (None, PathEncoding::Utf8Strict) => return None,
+    };
+    Some(parse_quote!(#[#name(#lit)]))
+}
+
+/// Create the attribute recording the logical path of a module, e.g. `foo::bar`.
+pub(crate) fn module_path_annotation(module_path: &[String]) -> Attribute {
+    let name = Ident::new(SYN_INLINE_MOD_MODULE_PATH_ATTR, Span::call_site());
+    let lit = LitStr::new(&module_path.join("::"), Span::call_site());
+    parse_quote!(#[#name(#lit)])
+}
+
+/// The path of an inlined module and the attributes around it, as recorded by
+/// `InlinerBuilder::annotate_paths`.
+#[derive(Clone)]
+pub struct InlineModPath {
+    path: PathBuf,
+    module_path: Option<Vec<String>>,
+    outer_attributes: Vec<Attribute>,
+    inner_attributes: Vec<Attribute>,
+}
+
+impl InlineModPath {
+    /// The path of the file the module was loaded from.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The logical path of the module, if it was also annotated by
+    /// `InlinerBuilder::annotate_module_paths`.
+    ///
+    /// This is the only way to recover the module's name from the annotations when it was
+    /// loaded with a `#[path]` attribute, since the file stem may not match the name.
+    pub fn module_path(&self) -> Option<&[String]> {
+        self.module_path.as_deref()
+    }
+
+    /// The attributes written on the `mod` item itself, which appear before the path
+    /// annotation.
+    pub fn outer_attributes(&self) -> &[Attribute] {
+        &self.outer_attributes
+    }
+
+    /// The attributes written at the top of the module's file, which appear after the path
+    /// annotation.
+    ///
+    /// For simplicity, these are returned as they were found on the `mod` item, so they
+    /// retain their `!`. Use `inner_attributes_as_outer` to get them without it.
+    pub fn inner_attributes(&self) -> &[Attribute] {
+        &self.inner_attributes
+    }
+
+    /// The attributes written at the top of the module's file, converted to outer attributes
+    /// so they can be placed on a `mod` item, e.g. `#![allow(dead_code)]` becomes
+    /// `#[allow(dead_code)]`.
+    pub fn inner_attributes_as_outer(&self) -> Vec<Attribute> {
+        self.inner_attributes
+            .iter()
+            .cloned()
+            .map(|mut attr| {
+                attr.style = AttrStyle::Outer;
+                attr
+            })
+            .collect()
+    }
+
+    /// Convert into the path of the file the module was loaded from.
+    pub fn into_path(self) -> PathBuf {
+        self.path
+    }
+}
+
+impl fmt::Debug for InlineModPath {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("InlineModPath")
+            .field("path", &self.path)
+            .field("module_path", &self.module_path)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Find the path annotation added by `InlinerBuilder::annotate_paths` in the attributes of an
+/// inlined module.
+///
+/// Returns `None` if the attributes don't contain a valid path annotation. This accepts
+/// annotations written with the default `PathEncoding::Bytes`; use
+/// `find_mod_path_with_encoding` if another encoding was used.
+pub fn find_mod_path(attrs: &[Attribute]) -> Option<InlineModPath> {
+    find_mod_path_with_encoding(attrs, PathEncoding::Bytes)
+}
+
+/// Find the path annotation added by `InlinerBuilder::annotate_paths` in the attributes of an
+/// inlined module, where the path was written with `encoding`.
+///
+/// Byte string paths are only accepted with `PathEncoding::Bytes`, since the other encodings
+/// never produce them. `InliningResult::path_encoding` returns the encoding used for a result.
+pub fn find_mod_path_with_encoding(
+    attrs: &[Attribute],
+    encoding: PathEncoding,
+) -> Option<InlineModPath> {
+    attrs.iter().enumerate().find_map(|(index, attr)| {
+        if !attr.path().is_ident(SYN_INLINE_MOD_PATH_ATTR) {
+            return None;
+        }
+
+        let path = match attr.parse_args::<Lit>().ok()? {
+            Lit::Str(lit) => PathBuf::from(lit.value()),
+            Lit::ByteStr(lit) if encoding == PathEncoding::Bytes => path_from_bytes(lit.value())?,
+            _ => return None,
+        };
+
+        // Other annotations added by the inliner aren't part of either list.
+        let not_annotation =
+            |attr: &&Attribute| !attr.path().is_ident(SYN_INLINE_MOD_MODULE_PATH_ATTR);
+
+        Some(InlineModPath {
+            path,
+            module_path: find_module_path(attrs),
+            outer_attributes: attrs[..index]
+                .iter()
+                .filter(not_annotation)
+                .cloned()
+                .collect(),
+            inner_attributes: attrs[index + 1..]
+                .iter()
+                .filter(not_annotation)
+                .cloned()
+                .collect(),
+        })
+    })
+}
+
+/// Find the logical module path added by `InlinerBuilder::annotate_module_paths` in the
+/// attributes of an inlined module, e.g. `["foo", "bar"]` for `crate::foo::bar`.
+///
+/// Returns `None` if the attributes don't contain a valid module path annotation.
+pub fn find_module_path(attrs: &[Attribute]) -> Option<Vec<String>> {
+    let attr = attrs
+        .iter()
+        .find(|attr| attr.path().is_ident(SYN_INLINE_MOD_MODULE_PATH_ATTR))?;
+    let lit = attr.parse_args::<LitStr>().ok()?;
+    Some(lit.value().split("::").map(String::from).collect())
+}
+
+/// Turn an inlined module back into the `mod foo;` declaration it was inlined from.
+///
+/// The content of the module is dropped, along with the attributes added by the inliner and
+/// the inner attributes that came from the module's file. The outer attributes written on the
+/// `mod` item are kept in their original order.
+pub fn to_mod_declaration(item: &ItemMod) -> ItemMod {
+    let attrs = item
+        .attrs
+        .iter()
+        .filter(|attr| {
+            matches!(attr.style, AttrStyle::Outer)
+                && !attr.path().is_ident(SYN_INLINE_MOD_PATH_ATTR)
+                && !attr.path().is_ident(SYN_INLINE_MOD_MODULE_PATH_ATTR)
+        })
+        .cloned()
+        .collect();
+
+    ItemMod {
+        attrs,
+        content: None,
+        semi: Some(Default::default()),
+        ..item.clone()
+    }
+}
+
+/// Convert the bytes of a byte string annotation back into a path.
+#[cfg(unix)]
+fn path_from_bytes(bytes: Vec<u8>) -> Option<PathBuf> {
+    use std::os::unix::ffi::OsStringExt;
+    Some(std::ffi::OsString::from_vec(bytes).into())
+}
+
+/// Convert the bytes of a byte string annotation back into a path.
+///
+/// Only UTF-8 can be safely converted back into an `OsString` on this platform.
+#[cfg(not(unix))]
+fn path_from_bytes(bytes: Vec<u8>) -> Option<PathBuf> {
+    String::from_utf8(bytes).ok().map(PathBuf::from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let attrs = vec![
+            parse_quote!(#[cfg(test)]),
+            path_annotation(Path::new("src/foo.rs"), PathEncoding::Bytes).unwrap(),
+            parse_quote!(#![allow(dead_code)]),
+        ];
+
+        let found = find_mod_path(&attrs).unwrap();
+        assert_eq!(found.path(), Path::new("src/foo.rs"));
+        assert_eq!(found.outer_attributes(), &attrs[..1]);
+        assert_eq!(found.inner_attributes(), &attrs[2..]);
+        assert_eq!(
+            found.inner_attributes_as_outer(),
+            vec![parse_quote!(#[allow(dead_code)])] as Vec<Attribute>
+        );
+    }
+
+    #[test]
+    fn module_path_round_trip() {
+        let module_path = vec!["foo".to_string(), "r#bar".to_string()];
+        let attrs = vec![
+            path_annotation(Path::new("src/foo/bar.rs"), PathEncoding::Bytes).unwrap(),
+            module_path_annotation(&module_path),
+        ];
+
+        assert_eq!(find_module_path(&attrs).as_ref(), Some(&module_path));
+        assert_eq!(find_module_path(&attrs[..1]), None);
+        let found = find_mod_path(&attrs).unwrap();
+        assert!(found.inner_attributes().is_empty());
+        assert_eq!(found.module_path(), Some(&module_path[..]));
+    }
+
+    #[test]
+    fn strip_markers() {
+        let mut attrs = vec![
+            parse_quote!(#[cfg(test)]),
+            path_annotation(Path::new("src/foo.rs"), PathEncoding::Bytes).unwrap(),
+            module_path_annotation(&["foo".to_string()]),
+        ];
+
+        attrs.retain(|attr| {
+            !attr.path().is_ident(SYN_INLINE_MOD_PATH_ATTR)
+                && !attr.path().is_ident(SYN_INLINE_MOD_MODULE_PATH_ATTR)
+        });
+        assert_eq!(attrs, vec![parse_quote!(#[cfg(test)])] as Vec<Attribute>);
+    }
+
+    #[test]
+    fn mod_declaration() {
+        let path = path_annotation(Path::new("src/foo.rs"), PathEncoding::Bytes).unwrap();
+        let module_path = module_path_annotation(&["foo".to_string()]);
+        let mut item: ItemMod = parse_quote! {
+            #[cfg(test)]
+            #[path = "foo.rs"]
+            #path
+            #module_path
+            pub mod foo {
+                struct Foo;
+            }
+        };
+        item.attrs.push(parse_quote!(#![allow(dead_code)]));
+
+        let expected: ItemMod = parse_quote! {
+            #[cfg(test)]
+            #[path = "foo.rs"]
+            pub mod foo;
+        };
+        assert_eq!(to_mod_declaration(&item), expected);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn round_trip_non_utf8() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        let path = Path::new(OsStr::from_bytes(b"src/\xff.rs"));
+        let bytes = [path_annotation(path, PathEncoding::Bytes).unwrap()];
+        assert_eq!(find_mod_path(&bytes).unwrap().path(), path);
+        assert!(find_mod_path_with_encoding(&bytes, PathEncoding::Utf8Lossy).is_none());
+
+        let lossy = path_annotation(path, PathEncoding::Utf8Lossy).unwrap();
+        let found = find_mod_path_with_encoding(&[lossy], PathEncoding::Utf8Lossy).unwrap();
+        assert_eq!(found.path(), Path::new("src/\u{fffd}.rs"));
+
+        assert!(path_annotation(path, PathEncoding::Utf8Strict).is_none());
+    }
+}
@@ -0,0 +1,84 @@
+//! A hash of the inlined crate that doesn't depend on where or how it was written.
+
+use std::path::{Component, Path};
+
+use quote::ToTokens;
+
+use crate::ModuleGraph;
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+/// Separates the parts of the hashed input. It can't appear in the UTF-8 text being hashed,
+/// so no two different inputs produce the same bytes.
+const SEPARATOR: u8 = 0xff;
+
+/// A 64-bit FNV-1a hash, which unlike `std`'s hashers is the same on every platform and
+/// Rust version.
+struct Fnv(u64);
+
+impl Fnv {
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= u64::from(*byte);
+            self.0 = self.0.wrapping_mul(FNV_PRIME);
+        }
+    }
+}
+
+pub(crate) fn canonical_hash(output: &syn::File, modules: &ModuleGraph) -> u64 {
+    let root = modules.root_file();
+    let root_dir = root.parent().unwrap_or_else(|| Path::new(""));
+
+    let mut paths = std::iter::once(root)
+        .chain(modules.modules().iter().filter_map(|node| node.file()))
+        .map(|path| canonical_path(path, root_dir))
+        .collect::<Vec<_>>();
+    paths.sort();
+    paths.dedup();
+
+    let mut hasher = Fnv(FNV_OFFSET_BASIS);
+    hasher.write(output.to_token_stream().to_string().as_bytes());
+    for path in paths {
+        hasher.write(&[SEPARATOR]);
+        hasher.write(path.as_bytes());
+    }
+    hasher.0
+}
+
+/// `path` relative to `root_dir` if it's inside it, with `/` between segments. The root and
+/// prefix of an absolute path outside `root_dir` are left out.
+fn canonical_path(path: &Path, root_dir: &Path) -> String {
+    let path = crate::normalize(path);
+    let root_dir = crate::normalize(root_dir);
+    path.strip_prefix(&root_dir)
+        .unwrap_or(&path)
+        .components()
+        .filter(|component| matches!(component, Component::Normal(_) | Component::ParentDir))
+        .map(|component| component.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::canonical_path;
+
+    #[test]
+    fn canonical_paths() {
+        assert_eq!(
+            canonical_path(Path::new("./src/a/../b.rs"), Path::new("src")),
+            "b.rs"
+        );
+        assert_eq!(
+            canonical_path(Path::new("/crate/src/a/b.rs"), Path::new("/crate/src")),
+            "a/b.rs"
+        );
+        assert_eq!(
+            canonical_path(Path::new("other/c.rs"), Path::new("src")),
+            "other/c.rs"
+        );
+    }
+}
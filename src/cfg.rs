@@ -0,0 +1,441 @@
+//! Evaluation of `cfg` predicates and expansion of `cfg_attr` attributes.
+
+use std::collections::HashSet;
+
+use syn::parse::{Parse, ParseStream};
+use syn::punctuated::Punctuated;
+use syn::{braced, Attribute, Expr, ExprLit, Item, ItemMacro, Lit, Meta, Token};
+
+use crate::{ModuleGraph, ModuleNode};
+
+/// The configuration options used to evaluate `cfg` predicates, equivalent to the `--cfg`
+/// flags passed to `rustc`.
+///
+/// Any option that hasn't been set is considered disabled.
+#[derive(Debug, Clone, Default)]
+pub struct CfgOptions {
+    options: HashSet<(String, Option<String>)>,
+}
+
+impl CfgOptions {
+    /// Create a set of options with nothing enabled.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a set of options for inlining a crate as it's seen when documented.
+    ///
+    /// This enables `doc`, which `rustdoc` sets, and `docsrs`, which isn't set by `rustdoc`
+    /// but is passed with `--cfg docsrs` when building on docs.rs, so crates that gate
+    /// documentation-only items on it are inlined as docs.rs would see them.
+    pub fn for_rustdoc() -> Self {
+        let mut options = Self::new();
+        options.set("doc").set("docsrs");
+        options
+    }
+
+    /// Enable a name-only option, such as `unix` or `test`.
+    pub fn set(&mut self, name: impl Into<String>) -> &mut Self {
+        self.options.insert((name.into(), None));
+        self
+    }
+
+    /// Enable a key-value option, such as `feature = "std"`.
+    pub fn set_value(&mut self, name: impl Into<String>, value: impl Into<String>) -> &mut Self {
+        self.options.insert((name.into(), Some(value.into())));
+        self
+    }
+
+    /// Check if a name-only or key-value option is enabled.
+    pub fn is_set(&self, name: &str, value: Option<&str>) -> bool {
+        self.options
+            .contains(&(name.to_string(), value.map(String::from)))
+    }
+
+    /// Check if every `#[cfg]` attribute in `attrs` holds. Attributes that aren't
+    /// well-formed don't hold.
+    pub(crate) fn is_enabled(&self, attrs: &[Attribute]) -> bool {
+        attrs
+            .iter()
+            .filter(|attr| attr.path().is_ident("cfg"))
+            .all(|attr| {
+                attr.parse_args::<Meta>()
+                    .is_ok_and(|predicate| self.eval(&predicate))
+            })
+    }
+
+    /// Evaluate a `cfg` predicate such as `all(unix, feature = "std")`.
+    ///
+    /// Predicates that aren't well-formed evaluate to `false`.
+    pub fn eval(&self, predicate: &Meta) -> bool {
+        match predicate {
+            Meta::Path(path) => path
+                .get_ident()
+                .is_some_and(|ident| self.is_set(&ident.to_string(), None)),
+            Meta::NameValue(name_value) => {
+                match (&name_value.path.get_ident(), &name_value.value) {
+                    (
+                        Some(ident),
+                        Expr::Lit(ExprLit {
+                            lit: Lit::Str(value),
+                            ..
+                        }),
+                    ) => self.is_set(&ident.to_string(), Some(&value.value())),
+                    _ => false,
+                }
+            }
+            Meta::List(list) => {
+                let nested =
+                    match list.parse_args_with(Punctuated::<Meta, Token![,]>::parse_terminated) {
+                        Ok(nested) => nested,
+                        Err(_) => return false,
+                    };
+
+                if list.path.is_ident("all") {
+                    nested.iter().all(|meta| self.eval(meta))
+                } else if list.path.is_ident("any") {
+                    nested.iter().any(|meta| self.eval(meta))
+                } else if list.path.is_ident("not") && nested.len() == 1 {
+                    !self.eval(&nested[0])
+                } else {
+                    false
+                }
+            }
+        }
+    }
+}
+
+/// Evaluate a `cfg` predicate that doesn't depend on the configuration, such as `all()` or
+/// `not(any())`, or return `None` if it does. Names in `never_set`, such as `FALSE`, are
+/// treated as options that are never enabled.
+///
+/// An empty `all()` is true and an empty `any()` is false, as in `rustc`.
+pub(crate) fn eval_const(predicate: &Meta, never_set: &[String]) -> Option<bool> {
+    let list = match predicate {
+        Meta::Path(path) => {
+            return path
+                .get_ident()
+                .is_some_and(|ident| never_set.iter().any(|name| ident == name))
+                .then_some(false);
+        }
+        Meta::List(list) => list,
+        Meta::NameValue(_) => return None,
+    };
+    let nested = list
+        .parse_args_with(Punctuated::<Meta, Token![,]>::parse_terminated)
+        .ok()?;
+
+    if list.path.is_ident("all") || list.path.is_ident("any") {
+        // The value `all` or `any` short-circuits on, which decides the predicate regardless
+        // of any nested predicates that depend on the configuration.
+        let decisive = list.path.is_ident("any");
+        let mut known = true;
+        for meta in &nested {
+            match eval_const(meta, never_set) {
+                Some(value) if value == decisive => return Some(decisive),
+                Some(_) => {}
+                None => known = false,
+            }
+        }
+        known.then_some(!decisive)
+    } else if list.path.is_ident("not") && nested.len() == 1 {
+        eval_const(&nested[0], never_set).map(|value| !value)
+    } else {
+        None
+    }
+}
+
+/// Check if any `#[cfg]` attribute in `attrs` is false in every configuration, such as
+/// `#[cfg(any())]`, treating the names in `never_set` as options that are never enabled.
+pub(crate) fn is_always_disabled(attrs: &[Attribute], never_set: &[String]) -> bool {
+    attrs
+        .iter()
+        .filter(|attr| attr.path().is_ident("cfg"))
+        .any(|attr| {
+            attr.parse_args::<Meta>()
+                .is_ok_and(|predicate| eval_const(&predicate, never_set) == Some(false))
+        })
+}
+
+/// The modules of a crate that are present under each of two configurations, as returned by
+/// `InlinerBuilder::diff_cfgs`.
+///
+/// Modules are identified by their logical path, e.g. `["foo", "bar"]` for `crate::foo::bar`,
+/// and listed in the order they were visited.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CfgDiff {
+    only_a: Vec<Vec<String>>,
+    only_b: Vec<Vec<String>>,
+    both: Vec<Vec<String>>,
+}
+
+impl CfgDiff {
+    /// Compare the modules in the graphs inlined under configurations `a` and `b`.
+    pub(crate) fn new(a: &ModuleGraph, b: &ModuleGraph) -> Self {
+        let in_a = a
+            .modules()
+            .iter()
+            .map(ModuleNode::module_path)
+            .collect::<HashSet<_>>();
+        let in_b = b
+            .modules()
+            .iter()
+            .map(ModuleNode::module_path)
+            .collect::<HashSet<_>>();
+
+        let mut diff = CfgDiff::default();
+        for path in a.modules().iter().map(ModuleNode::module_path) {
+            if in_b.contains(path) {
+                diff.both.push(path.to_vec());
+            } else {
+                diff.only_a.push(path.to_vec());
+            }
+        }
+        diff.only_b = b
+            .modules()
+            .iter()
+            .map(ModuleNode::module_path)
+            .filter(|path| !in_a.contains(path))
+            .map(<[String]>::to_vec)
+            .collect();
+        diff
+    }
+
+    /// The modules that are only present under the first configuration.
+    pub fn only_a(&self) -> &[Vec<String>] {
+        &self.only_a
+    }
+
+    /// The modules that are only present under the second configuration.
+    pub fn only_b(&self) -> &[Vec<String>] {
+        &self.only_b
+    }
+
+    /// The modules that are present under both configurations.
+    pub fn both(&self) -> &[Vec<String>] {
+        &self.both
+    }
+}
+
+/// Split a `cfg_attr(predicate, attrs...)` attribute, or one nested in another `cfg_attr`,
+/// into its predicate and attributes.
+///
+/// Returns `None` if `meta` is not a well-formed `cfg_attr`.
+pub(crate) fn split_cfg_attr(meta: &Meta) -> Option<(Meta, Vec<Meta>)> {
+    if !meta.path().is_ident("cfg_attr") {
+        return None;
+    }
+
+    let list = meta.require_list().ok()?;
+    let mut nested = list
+        .parse_args_with(Punctuated::<Meta, Token![,]>::parse_terminated)
+        .ok()?
+        .into_iter();
+    let predicate = nested.next()?;
+    Some((predicate, nested.collect()))
+}
+
+/// Replace each `cfg_if!` invocation in `items` with the items of the branch it selects,
+/// including in nested invocations.
+///
+/// Without `cfg`, the first branch is selected, matching how `path` attributes inside
+/// `cfg_attr` are treated. Invocations that aren't well-formed are left alone.
+pub(crate) fn expand_cfg_if(items: &mut Vec<Item>, cfg: Option<&CfgOptions>) {
+    if !items.iter().any(is_cfg_if) {
+        return;
+    }
+
+    let mut expanded = Vec::with_capacity(items.len());
+    for item in items.drain(..) {
+        let branches = match &item {
+            Item::Macro(item) if is_cfg_if_macro(item) => item.mac.parse_body::<CfgIf>().ok(),
+            _ => None,
+        };
+
+        match branches {
+            Some(branches) => {
+                if let Some(mut selected) = branches.select(cfg) {
+                    expand_cfg_if(&mut selected, cfg);
+                    expanded.extend(selected);
+                }
+            }
+            None => expanded.push(item),
+        }
+    }
+    *items = expanded;
+}
+
+fn is_cfg_if(item: &Item) -> bool {
+    matches!(item, Item::Macro(item) if is_cfg_if_macro(item))
+}
+
+/// Check if `item` invokes `cfg_if!` by its usual name, such as `cfg_if::cfg_if!`.
+fn is_cfg_if_macro(item: &ItemMacro) -> bool {
+    item.ident.is_none()
+        && item
+            .mac
+            .path
+            .segments
+            .last()
+            .is_some_and(|segment| segment.ident == "cfg_if")
+}
+
+/// The branches of a `cfg_if!` invocation, each with its predicate, or `None` for `else`.
+struct CfgIf(Vec<(Option<Meta>, Vec<Item>)>);
+
+impl CfgIf {
+    /// Get the items of the first branch whose predicate holds.
+    fn select(self, cfg: Option<&CfgOptions>) -> Option<Vec<Item>> {
+        self.0
+            .into_iter()
+            .find(|(predicate, _)| match (predicate, cfg) {
+                (Some(predicate), Some(cfg)) => cfg.eval(predicate),
+                _ => true,
+            })
+            .map(|(_, items)| items)
+    }
+}
+
+impl Parse for CfgIf {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let mut branches = vec![];
+        loop {
+            input.parse::<Token![if]>()?;
+            let attr = Attribute::parse_outer(input)?
+                .pop()
+                .ok_or_else(|| input.error("expected #[cfg(...)]"))?;
+            if !attr.path().is_ident("cfg") {
+                return Err(syn::Error::new_spanned(attr, "expected #[cfg(...)]"));
+            }
+            branches.push((Some(attr.parse_args()?), parse_branch(input)?));
+
+            if input.is_empty() {
+                break;
+            }
+            input.parse::<Token![else]>()?;
+            if !input.peek(Token![if]) {
+                branches.push((None, parse_branch(input)?));
+                break;
+            }
+        }
+        Ok(CfgIf(branches))
+    }
+}
+
+/// Parse the items in the braces of a `cfg_if!` branch.
+fn parse_branch(input: ParseStream) -> syn::Result<Vec<Item>> {
+    let content;
+    braced!(content in input);
+    let mut items = vec![];
+    while !content.is_empty() {
+        items.push(content.parse()?);
+    }
+    Ok(items)
+}
+
+#[cfg(test)]
+mod tests {
+    use syn::parse_quote;
+
+    use super::*;
+
+    #[test]
+    fn eval() {
+        let mut options = CfgOptions::new();
+        options.set("unix").set_value("feature", "std");
+
+        assert!(options.eval(&parse_quote!(unix)));
+        assert!(!options.eval(&parse_quote!(windows)));
+        assert!(options.eval(&parse_quote!(feature = "std")));
+        assert!(!options.eval(&parse_quote!(feature = "alloc")));
+        assert!(options.eval(&parse_quote!(all(unix, not(windows)))));
+        assert!(options.eval(&parse_quote!(any(windows, feature = "std"))));
+        assert!(options.eval(&parse_quote!(all())));
+        assert!(!options.eval(&parse_quote!(any())));
+        assert!(!options.eval(&parse_quote!(not(unix, windows))));
+    }
+
+    #[test]
+    fn eval_const() {
+        use super::eval_const;
+
+        let none: &[String] = &[];
+        assert_eq!(eval_const(&parse_quote!(all()), none), Some(true));
+        assert_eq!(eval_const(&parse_quote!(any()), none), Some(false));
+        assert_eq!(eval_const(&parse_quote!(not(all())), none), Some(false));
+        assert_eq!(eval_const(&parse_quote!(not(any())), none), Some(true));
+        assert_eq!(
+            eval_const(&parse_quote!(all(unix, any())), none),
+            Some(false)
+        );
+        assert_eq!(
+            eval_const(&parse_quote!(any(unix, all())), none),
+            Some(true)
+        );
+        assert_eq!(eval_const(&parse_quote!(all(unix, all())), none), None);
+        assert_eq!(eval_const(&parse_quote!(unix), none), None);
+
+        let never_set = &["FALSE".to_string()];
+        assert_eq!(eval_const(&parse_quote!(FALSE), never_set), Some(false));
+        assert_eq!(eval_const(&parse_quote!(not(FALSE)), never_set), Some(true));
+        assert_eq!(eval_const(&parse_quote!(any(FALSE, unix)), never_set), None);
+    }
+
+    #[test]
+    fn is_enabled() {
+        let options = CfgOptions::for_rustdoc();
+        let attrs: Vec<Attribute> =
+            vec![parse_quote!(#[cfg(doc)]), parse_quote!(#[allow(dead_code)])];
+        assert!(options.is_enabled(&attrs));
+        assert!(!CfgOptions::new().is_enabled(&attrs));
+        assert!(!options.is_enabled(&[parse_quote!(#[cfg(doc, test)])]));
+    }
+
+    #[test]
+    fn split() {
+        let attr: Attribute = parse_quote!(#[cfg_attr(unix, path = "unix.rs", allow(dead_code))]);
+        let (predicate, attrs) = split_cfg_attr(&attr.meta).unwrap();
+        assert!(predicate.path().is_ident("unix"));
+        assert_eq!(attrs.len(), 2);
+
+        assert!(split_cfg_attr(&parse_quote!(cfg(unix))).is_none());
+    }
+
+    #[test]
+    fn cfg_if() {
+        let file: syn::File = parse_quote! {
+            cfg_if::cfg_if! {
+                if #[cfg(unix)] {
+                    mod unix;
+                } else if #[cfg(windows)] {
+                    mod windows;
+                    cfg_if! {
+                        if #[cfg(target_pointer_width = "64")] { mod win64; }
+                    }
+                } else {
+                    mod other;
+                }
+            }
+            cfg_if! { not valid }
+        };
+        let names = |cfg: Option<&CfgOptions>| {
+            let mut items = file.items.clone();
+            expand_cfg_if(&mut items, cfg);
+            items
+                .iter()
+                .map(|item| match item {
+                    Item::Mod(item) => item.ident.to_string(),
+                    _ => "macro".to_string(),
+                })
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(names(None), vec!["unix", "macro"]);
+        assert_eq!(names(Some(&CfgOptions::new())), vec!["other", "macro"]);
+        let mut windows = CfgOptions::new();
+        windows
+            .set("windows")
+            .set_value("target_pointer_width", "64");
+        assert_eq!(names(Some(&windows)), vec!["windows", "win64", "macro"]);
+    }
+}
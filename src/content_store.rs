@@ -0,0 +1,129 @@
+//! A resolver backed by a content-addressed store.
+
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::{normalize, Error, FileResolver};
+
+/// A resolver that reads files from a content-addressed store, such as a build cache.
+///
+/// A manifest maps the path of each file to the hash of its content, and `fetch` reads the
+/// content with a given hash. Paths are normalized before they are looked up, so a candidate
+/// such as `src/a/../b.rs` finds the entry for `src/b.rs`. A directory exists if any path in
+/// the manifest is inside it.
+pub struct ContentStoreResolver<H, F> {
+    manifest: HashMap<PathBuf, H>,
+    fetch: F,
+}
+
+impl<H, F> ContentStoreResolver<H, F>
+where
+    F: FnMut(&H) -> io::Result<String>,
+{
+    /// Create a resolver for the files in `manifest`, reading their content with `fetch`.
+    pub fn new(manifest: HashMap<PathBuf, H>, fetch: F) -> Self {
+        Self {
+            manifest: manifest
+                .into_iter()
+                .map(|(path, hash)| (normalize(&path), hash))
+                .collect(),
+            fetch,
+        }
+    }
+}
+
+impl<H: fmt::Debug, F> fmt::Debug for ContentStoreResolver<H, F> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ContentStoreResolver")
+            .field("manifest", &self.manifest)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<H, F> FileResolver for ContentStoreResolver<H, F>
+where
+    F: FnMut(&H) -> io::Result<String>,
+{
+    fn path_exists(&self, path: &Path) -> bool {
+        let path = normalize(path);
+        self.manifest.contains_key(&path)
+            || self.manifest.keys().any(|file| file.starts_with(&path))
+    }
+
+    fn resolve(&mut self, path: &Path) -> Result<syn::File, Error> {
+        let src = self.load(path).expect("load is implemented")?;
+        self.parse(&src)
+    }
+
+    fn load(&mut self, path: &Path) -> Option<Result<String, Error>> {
+        Some(match self.manifest.get(&normalize(path)) {
+            Some(hash) => (self.fetch)(hash).map_err(Error::Io),
+            None => Err(Error::Io(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{} is not in the manifest", path.display()),
+            ))),
+        })
+    }
+
+    fn read_dir(&self, dir: &Path) -> io::Result<Vec<PathBuf>> {
+        let normalized = normalize(dir);
+        let entries = self
+            .manifest
+            .keys()
+            .filter(|file| **file != normalized)
+            .filter_map(|file| file.strip_prefix(&normalized).ok()?.iter().next())
+            .collect::<BTreeSet<_>>();
+
+        if entries.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{} is not a directory in the manifest", dir.display()),
+            ));
+        }
+
+        Ok(entries.into_iter().map(|entry| dir.join(entry)).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::io;
+    use std::path::{Path, PathBuf};
+
+    use super::ContentStoreResolver;
+    use crate::{FileResolver, InlinerBuilder};
+
+    #[test]
+    fn inline_from_store() {
+        let blobs = HashMap::from([(1, "mod a;"), (2, "mod b;"), (3, "struct B;")]);
+        let manifest = HashMap::from([
+            (PathBuf::from("src/lib.rs"), 1),
+            (PathBuf::from("./src/a/mod.rs"), 2),
+            (PathBuf::from("src/a/b.rs"), 3),
+        ]);
+        let mut resolver = ContentStoreResolver::new(manifest, |hash: &u32| {
+            blobs
+                .get(hash)
+                .map(|src| src.to_string())
+                .ok_or_else(|| io::Error::from(io::ErrorKind::NotFound))
+        });
+
+        assert!(resolver.path_exists(Path::new("src/a/../lib.rs")));
+        assert!(resolver.path_exists(Path::new("src/a")));
+        assert!(!resolver.path_exists(Path::new("src/missing.rs")));
+        assert_eq!(
+            resolver.read_dir(Path::new("src/a")).unwrap(),
+            vec![Path::new("src/a/b.rs"), Path::new("src/a/mod.rs")]
+        );
+        assert!(resolver.read_dir(Path::new("src/lib.rs")).is_err());
+
+        let result = InlinerBuilder::default()
+            .parse_internal(Path::new("src/lib.rs"), &mut resolver)
+            .unwrap();
+        assert!(!result.has_errors());
+        assert_eq!(result.module_graph().len(), 2);
+    }
+}
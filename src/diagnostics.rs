@@ -0,0 +1,96 @@
+//! Conversion of inlining errors into `codespan-reporting` diagnostics.
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use codespan::diagnostic::{Diagnostic, Label};
+use codespan::files::SimpleFiles;
+
+use crate::{InlineError, InliningResult};
+
+impl InliningResult {
+    /// Convert the errors into `codespan-reporting` diagnostics, with the files they refer to.
+    ///
+    /// Each diagnostic points at the `mod` item that couldn't be inlined. Only the source of
+    /// files loaded with `FileResolver::load` is kept, so errors in files from other resolvers
+    /// are reported without a label.
+    pub fn codespan_diagnostics(&self) -> (SimpleFiles<String, String>, Vec<Diagnostic<usize>>) {
+        let mut files = SimpleFiles::new();
+        let mut ids = HashMap::new();
+        let diagnostics = self
+            .errors
+            .iter()
+            .map(|error| {
+                let id = self.sources.get(error.src_path()).map(|src| {
+                    *ids.entry(error.src_path())
+                        .or_insert_with(|| files.add(display(error.src_path()), src.clone()))
+                });
+                diagnostic(error, id)
+            })
+            .collect();
+        (files, diagnostics)
+    }
+}
+
+/// Create the diagnostic for `error`, labelled in the file with `id` if its source was kept.
+fn diagnostic(error: &InlineError, id: Option<usize>) -> Diagnostic<usize> {
+    let mut diagnostic = Diagnostic::error().with_message(format!(
+        "error while including module `{}`",
+        error.module_path().join("::")
+    ));
+
+    let reason = format!("{} from {}", error.kind().detail(), display(error.path()));
+    diagnostic = match id {
+        Some(id) => diagnostic.with_labels(vec![
+            Label::primary(id, error.src_span().byte_range()).with_message(reason)
+        ]),
+        None => diagnostic.with_notes(vec![reason]),
+    };
+
+    match error.suggestion() {
+        Some(suggestion) => {
+            diagnostic.with_notes(vec![format!("did you mean {}?", display(suggestion))])
+        }
+        None => diagnostic,
+    }
+}
+
+fn display(path: &Path) -> String {
+    path.display().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use codespan::diagnostic::Severity;
+
+    use crate::{InlinerBuilder, TestResolver};
+
+    #[test]
+    fn codespan_diagnostics() {
+        let mut env = TestResolver::default();
+        env.register("src/lib.rs", "mod a;\nmod missing;");
+        env.register("src/a.rs", "struct A");
+
+        let result = InlinerBuilder::default()
+            .parse_internal(Path::new("src/lib.rs"), &mut env)
+            .unwrap();
+        let (files, diagnostics) = result.codespan_diagnostics();
+
+        assert_eq!(diagnostics.len(), 2);
+        assert_eq!(diagnostics[0].severity, Severity::Error);
+        assert_eq!(
+            diagnostics[1].message,
+            "error while including module `missing`"
+        );
+
+        let label = &diagnostics[1].labels[0];
+        assert_eq!(label.range, 7..19);
+        assert_eq!(
+            label.message,
+            "path not in test resolver hashmap from src/missing/mod.rs"
+        );
+        assert_eq!(files.get(label.file_id).unwrap().name(), "src/lib.rs");
+    }
+}
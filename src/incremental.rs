@@ -0,0 +1,135 @@
+//! Reusing the modules of an earlier result whose files haven't changed.
+
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+use syn::visit::Visit;
+use syn::ItemMod;
+
+use crate::verify::ModCollector;
+use crate::{normalize, ModuleGraph};
+
+/// The modules of an earlier result that can be spliced into a new one unchanged.
+///
+/// A module is reused along with every module declared inside it, so it can only be reused
+/// if none of the files in its subtree changed, no file above it changed, and no module in
+/// its subtree failed to inline, since the missing file may have been created since.
+pub(crate) struct Reuse<'a> {
+    prev: &'a ModuleGraph,
+    /// The `mod` item in the earlier output of each module that can be reused, by index in
+    /// `prev`.
+    items: HashMap<usize, &'a ItemMod>,
+}
+
+impl<'a> Reuse<'a> {
+    pub fn new(prev: &'a ModuleGraph, changed: &HashSet<PathBuf>) -> Self {
+        let mut reuse = Reuse {
+            prev,
+            items: HashMap::new(),
+        };
+        let output = match prev.output() {
+            Some(output) => output,
+            None => return reuse,
+        };
+
+        let changed = changed
+            .iter()
+            .map(|path| normalize(path))
+            .collect::<HashSet<_>>();
+        let is_changed = |path: &Path| changed.contains(&normalize(path));
+
+        // Modules are recorded before the modules declared inside them, so each module's
+        // parent is marked before the module itself, and its children after.
+        let nodes = prev.modules();
+        let mut above = vec![false; nodes.len()];
+        for (index, node) in nodes.iter().enumerate() {
+            let failed = !node.inline && node.file.is_none();
+            let parent = match node.parent {
+                Some(parent) => above[parent],
+                None => is_changed(prev.root_file()),
+            };
+            above[index] = parent || failed || node.file.as_deref().is_some_and(is_changed);
+        }
+        let mut below = vec![false; nodes.len()];
+        for (index, node) in nodes.iter().enumerate().rev() {
+            if let Some(parent) = node.parent {
+                below[parent] |= above[index] || below[index];
+            }
+        }
+
+        let mut collector = ModCollector::default();
+        collector.visit_file(output);
+
+        // Modules with the same path, such as alternatives selected by `cfg`, are matched in order.
+        let mut seen = HashMap::<&[String], usize>::new();
+        for (index, node) in nodes.iter().enumerate() {
+            let occurrence = seen.entry(node.module_path()).or_default();
+            let nth = *occurrence;
+            *occurrence += 1;
+
+            if node.file.is_none() || above[index] || below[index] {
+                continue;
+            }
+
+            let item = collector
+                .mods
+                .iter()
+                .filter(|(module_path, _)| module_path == node.module_path())
+                .nth(nth);
+            if let Some((_, item)) = item {
+                reuse.items.insert(index, *item);
+            }
+        }
+
+        reuse
+    }
+
+    /// The index in the earlier result of the reusable module at `module_path`, if it was
+    /// loaded from `file`.
+    pub fn find(&self, module_path: &[String], file: &Path) -> Option<usize> {
+        let file = normalize(file);
+        self.prev
+            .modules()
+            .iter()
+            .enumerate()
+            .find(|(index, node)| {
+                self.items.contains_key(index)
+                    && node.module_path() == module_path
+                    && node.file().is_some_and(|path| normalize(path) == file)
+            })
+            .map(|(index, _)| index)
+    }
+
+    /// Replace `i` with the earlier result's module at `prev_index`, and record that module and
+    /// the modules declared inside it in `graph`, as the module at `index` and its children.
+    pub fn splice(
+        &self,
+        prev_index: usize,
+        i: &mut ItemMod,
+        graph: &mut ModuleGraph,
+        index: usize,
+    ) {
+        *i = self.items[&prev_index].clone();
+
+        let nodes = self.prev.modules();
+        let prev = &nodes[prev_index];
+        let node = graph.get_mut(index);
+        node.file = prev.file.clone();
+        node.line_count = prev.line_count;
+        node.duration = prev.duration;
+        node.content_line = prev.content_line;
+
+        // The modules inside a module are recorded right after it, so the subtree ends at the
+        // first module whose parent isn't part of it.
+        let mut indices = HashMap::from([(prev_index, index)]);
+        for (child_index, child) in nodes.iter().enumerate().skip(prev_index + 1) {
+            let parent = match child.parent.and_then(|parent| indices.get(&parent)) {
+                Some(parent) => *parent,
+                None => break,
+            };
+            let mut node = child.clone();
+            node.parent = Some(parent);
+            indices.insert(child_index, graph.push(node));
+        }
+    }
+}
@@ -0,0 +1,230 @@
+//! A reusable inliner that caches files across calls.
+
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::{Error, FileResolver, InlinerBuilder, InliningResult};
+
+/// An inliner with a fixed configuration, created by `InlinerBuilder::build`.
+///
+/// The source of each file and the entries of each directory are read from the file system
+/// once and then cached, so inlining many root files that share modules, or inlining the
+/// same file repeatedly, avoids reading them again. Changes made to the files after they
+/// were first read aren't seen until they are passed to `invalidate` or `clear_cache` is
+/// called.
+#[derive(Debug)]
+pub struct Inliner {
+    options: InlinerBuilder,
+    cache: Mutex<FileCache>,
+}
+
+impl Inliner {
+    pub(crate) fn new(options: InlinerBuilder) -> Self {
+        Self {
+            options,
+            cache: Default::default(),
+        }
+    }
+
+    /// The configuration used to inline files.
+    pub fn options(&self) -> &InlinerBuilder {
+        &self.options
+    }
+
+    /// Parse the source code in `src_file` and return an `InliningResult` that has all modules
+    /// recursively inlined, reading files from the cache where possible.
+    ///
+    /// This behaves like `InlinerBuilder::parse_and_inline_modules`.
+    pub fn inline(&self, src_file: &Path) -> Result<InliningResult, Error> {
+        self.options.parse_internal(
+            src_file,
+            &mut CachingResolver {
+                inner: self.options.fs_resolver(|_: &Path, _| {}),
+                cache: &self.cache,
+            },
+        )
+    }
+
+    /// Forget what was read about the `changed` files, so later calls read them again while
+    /// other files still come from the cache.
+    ///
+    /// `changed` should contain every file that was modified, created or deleted since the
+    /// cache was filled, using the same base (relative or absolute) as the root files passed to
+    /// `inline`. This only invalidates the cache: the next call to `inline` still parses and
+    /// visits every file, but reads only the changed ones from the file system.
+    pub fn invalidate<P: AsRef<Path>>(&self, changed: impl IntoIterator<Item = P>) {
+        let mut cache = self.cache.lock().unwrap();
+        for path in changed {
+            cache.invalidate(path.as_ref());
+        }
+    }
+
+    /// Forget every file and directory read so far, so later calls read them again.
+    pub fn clear_cache(&self) {
+        *self.cache.lock().unwrap() = Default::default();
+    }
+}
+
+/// The files and directories read by an `Inliner`.
+#[derive(Debug, Default)]
+struct FileCache {
+    exists: HashMap<PathBuf, bool>,
+    sources: HashMap<PathBuf, String>,
+    dirs: HashMap<PathBuf, Vec<PathBuf>>,
+}
+
+impl FileCache {
+    /// Forget what is known about the file at `path`, including the entries of the directory
+    /// containing it, since the file may have been created or deleted.
+    fn invalidate(&mut self, path: &Path) {
+        self.sources.remove(path);
+        self.exists.remove(path);
+        self.dirs.remove(path);
+        for ancestor in path.ancestors().skip(1) {
+            self.exists.remove(ancestor);
+            self.dirs.remove(ancestor);
+        }
+    }
+}
+
+/// A resolver that reads through a shared `FileCache`.
+///
+/// Only successful reads are cached, so errors are reported again on later calls.
+struct CachingResolver<'a, R> {
+    inner: R,
+    cache: &'a Mutex<FileCache>,
+}
+
+impl<R: FileResolver> FileResolver for CachingResolver<'_, R> {
+    fn path_exists(&self, path: &Path) -> bool {
+        if let Some(exists) = self.cache.lock().unwrap().exists.get(path) {
+            return *exists;
+        }
+
+        let exists = self.inner.path_exists(path);
+        self.cache
+            .lock()
+            .unwrap()
+            .exists
+            .insert(path.to_path_buf(), exists);
+        exists
+    }
+
+    fn resolve(&mut self, path: &Path) -> Result<syn::File, Error> {
+        match self.load(path) {
+            Some(src) => self.inner.parse(&src?),
+            None => self.inner.resolve(path),
+        }
+    }
+
+    fn load(&mut self, path: &Path) -> Option<Result<String, Error>> {
+        if let Some(src) = self.cache.lock().unwrap().sources.get(path) {
+            return Some(Ok(src.clone()));
+        }
+
+        let src = self.inner.load(path)?;
+        if let Ok(src) = &src {
+            self.cache
+                .lock()
+                .unwrap()
+                .sources
+                .insert(path.to_path_buf(), src.clone());
+        }
+        Some(src)
+    }
+
+    fn parse(&self, src: &str) -> Result<syn::File, Error> {
+        self.inner.parse(src)
+    }
+
+    fn canonicalize(&self, path: &Path) -> PathBuf {
+        self.inner.canonicalize(path)
+    }
+
+    fn read_dir(&self, dir: &Path) -> io::Result<Vec<PathBuf>> {
+        if let Some(entries) = self.cache.lock().unwrap().dirs.get(dir) {
+            return Ok(entries.clone());
+        }
+
+        let entries = self.inner.read_dir(dir)?;
+        self.cache
+            .lock()
+            .unwrap()
+            .dirs
+            .insert(dir.to_path_buf(), entries.clone());
+        Ok(entries)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use std::sync::Mutex;
+
+    use super::{CachingResolver, FileCache};
+    use crate::{FileResolver, InlinerBuilder, TestResolver};
+
+    #[test]
+    fn cached_sources() {
+        let cache = Mutex::new(FileCache::default());
+        let mut env = TestResolver::default();
+        env.register("src/lib.rs", "mod a;");
+        env.register("src/a.rs", "struct A;");
+
+        let options = InlinerBuilder::default();
+        let result = options
+            .parse_internal(
+                Path::new("src/lib.rs"),
+                &mut CachingResolver {
+                    inner: env,
+                    cache: &cache,
+                },
+            )
+            .unwrap();
+        assert!(!result.has_errors());
+
+        // The files are no longer available from the inner resolver, but are still cached.
+        let mut resolver = CachingResolver {
+            inner: TestResolver::default(),
+            cache: &cache,
+        };
+        assert!(resolver.path_exists(Path::new("src/a.rs")));
+        let result = options
+            .parse_internal(Path::new("src/lib.rs"), &mut resolver)
+            .unwrap();
+        assert!(!result.has_errors());
+        assert_eq!(result.module_graph().len(), 1);
+    }
+
+    #[test]
+    fn invalidate() {
+        let cache = Mutex::new(FileCache::default());
+        let options = InlinerBuilder::default();
+        let mut env = TestResolver::default();
+        env.register("src/lib.rs", "mod a;");
+        env.register("src/a.rs", "struct A;");
+        let inline = |env: &TestResolver| {
+            options
+                .parse_internal(
+                    Path::new("src/lib.rs"),
+                    &mut CachingResolver {
+                        inner: env.clone(),
+                        cache: &cache,
+                    },
+                )
+                .unwrap()
+        };
+        assert!(!inline(&env).has_errors());
+
+        env.register("src/lib.rs", "mod a; mod b;");
+        env.register("src/b.rs", "struct B;");
+        assert_eq!(inline(&env).module_graph().len(), 1);
+
+        cache.lock().unwrap().invalidate(Path::new("src/lib.rs"));
+        let result = inline(&env);
+        assert!(!result.has_errors());
+        assert_eq!(result.module_graph().len(), 2);
+    }
+}
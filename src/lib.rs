@@ -3,19 +3,63 @@
 
 use proc_macro2::Span;
 use std::{
+    cmp::Ordering,
+    collections::{HashMap, HashSet},
     error, fmt, io,
     path::{Path, PathBuf},
+    rc::Rc,
+    sync::{Arc, Mutex},
 };
 use syn::spanned::Spanned;
 use syn::ItemMod;
 
+mod annotate;
+mod canonical;
+mod cfg;
+#[cfg(feature = "content_store")]
+mod content_store;
+#[cfg(feature = "codespan")]
+mod diagnostics;
+mod incremental;
+mod inliner;
 mod mod_path;
+mod module_graph;
+mod module_iter;
+mod plan;
 mod resolver;
+mod source_map;
+mod stats;
+mod trace;
+mod verify;
+#[cfg(feature = "vfs")]
+mod vfs_resolver;
 mod visitor;
+mod warning;
 
+pub use annotate::{
+    find_mod_path, find_mod_path_with_encoding, find_module_path, to_mod_declaration,
+    InlineModPath, PathEncoding, SYN_INLINE_MOD_MODULE_PATH_ATTR, SYN_INLINE_MOD_PATH_ATTR,
+};
+pub use cfg::{CfgDiff, CfgOptions};
+#[cfg(feature = "content_store")]
+pub use content_store::ContentStoreResolver;
+use incremental::Reuse;
+pub use inliner::Inliner;
 pub(crate) use mod_path::*;
+pub use mod_path::{ModContext, ModSegment};
+use module_graph::Snapshot;
+pub use module_graph::{ModuleGraph, ModuleNode, ResolutionKind};
+pub use plan::InlinePlan;
 pub(crate) use resolver::*;
-pub(crate) use visitor::Visitor;
+pub use resolver::{parse_source, ChainResolver, FileResolver};
+pub use source_map::{ProvenanceMap, SourceMap, SpanEntry, SpanTable, SpanTableError};
+pub use stats::{FileStats, InlineStats};
+pub use trace::{ResolveTrace, TraceNode, TraceOutcome};
+pub use verify::{VerificationError, VerificationErrorKind};
+#[cfg(feature = "vfs")]
+pub use vfs_resolver::VfsResolver;
+pub(crate) use visitor::{Log, Visitor};
+pub use warning::{InlineWarning, WarningKind};
 
 /// Parse the source code in `src_file` and return a `syn::File` that has all modules
 /// recursively inlined.
@@ -42,15 +86,117 @@ pub fn parse_and_inline_modules(src_file: &Path) -> syn::File {
 ///
 /// After creating a builder, set configuration options using the methods
 /// taking `&mut self`, then parse and inline one or more files using
-/// `parse_and_inline_modules`.
-#[derive(Debug)]
+/// `parse_and_inline_modules`, or create an `Inliner` with `build` to reuse files read by
+/// earlier calls.
+#[derive(Debug, Clone)]
 pub struct InlinerBuilder {
     root: bool,
+    ignore_path_on_inline_mods: bool,
+    recursive: bool,
+    annotate_paths: bool,
+    path_encoding: PathEncoding,
+    absolute_paths: bool,
+    annotate_module_paths: bool,
+    prefer_mod_rs: bool,
+    drop_test_modules: bool,
+    suggest_on_missing: bool,
+    error_stubs: bool,
+    cfg_options: Option<CfgOptions>,
+    never_set_cfgs: Vec<String>,
+    expand_cfg_if: bool,
+    expand_includes: bool,
+    strip_resolved_cfg_attr: bool,
+    replace_resolved_cfg_attr: bool,
+    candidate_selector: Option<Callback<CandidateSelector>>,
+    on_candidate: Option<Callback<Mutex<OnCandidate>>>,
+    transform_child: Option<Callback<Mutex<TransformChild>>>,
+    sort_items: Option<Callback<ItemOrder>>,
+    attribute_filter: Option<Callback<AttributeFilter>>,
+    workspace_root: Option<PathBuf>,
+    workspace_sentinel: Option<String>,
+    base_dir: Option<PathBuf>,
+    qualified_paths: bool,
+    search_roots: Vec<PathBuf>,
+    extra_modules: Vec<(Vec<String>, PathBuf)>,
+    collect_warnings: bool,
+    warn_shadowing: bool,
+    warn_path_mismatch: bool,
+    tolerant_parse: bool,
+    time: bool,
+    retain_sources: bool,
+    implicit_dir_modules: bool,
+    max_output_items: Option<usize>,
+    max_depth: Option<usize>,
+    max_file_bytes: Option<usize>,
+    #[cfg(feature = "encoding_rs")]
+    encoding: Option<&'static encoding_rs::Encoding>,
+}
+
+type CandidateSelector = dyn Fn(&[PathBuf]) -> Option<PathBuf> + Send + Sync;
+type OnCandidate = dyn FnMut(&Path, bool) + Send;
+type TransformChild = dyn FnMut(&Path, &mut Vec<syn::Item>) + Send;
+type ItemOrder = dyn Fn(&syn::Item, &syn::Item) -> Ordering + Send + Sync;
+type AttributeFilter = dyn Fn(&syn::Attribute) -> bool + Send + Sync;
+
+/// A user-supplied function stored in an `InlinerBuilder`.
+struct Callback<F: ?Sized>(Arc<F>);
+
+impl<F: ?Sized> Clone for Callback<F> {
+    fn clone(&self) -> Self {
+        Callback(Arc::clone(&self.0))
+    }
+}
+
+impl<F: ?Sized> fmt::Debug for Callback<F> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Callback")
+    }
 }
 
 impl Default for InlinerBuilder {
     fn default() -> Self {
-        InlinerBuilder { root: true }
+        InlinerBuilder {
+            root: true,
+            ignore_path_on_inline_mods: false,
+            recursive: true,
+            annotate_paths: false,
+            path_encoding: PathEncoding::Bytes,
+            absolute_paths: false,
+            annotate_module_paths: false,
+            prefer_mod_rs: false,
+            drop_test_modules: false,
+            suggest_on_missing: false,
+            error_stubs: false,
+            cfg_options: None,
+            never_set_cfgs: vec![],
+            expand_cfg_if: false,
+            expand_includes: false,
+            strip_resolved_cfg_attr: false,
+            replace_resolved_cfg_attr: false,
+            candidate_selector: None,
+            on_candidate: None,
+            transform_child: None,
+            sort_items: None,
+            attribute_filter: None,
+            workspace_root: None,
+            workspace_sentinel: None,
+            base_dir: None,
+            qualified_paths: false,
+            search_roots: vec![],
+            extra_modules: vec![],
+            collect_warnings: false,
+            warn_shadowing: false,
+            warn_path_mismatch: false,
+            tolerant_parse: false,
+            time: false,
+            retain_sources: false,
+            implicit_dir_modules: false,
+            max_output_items: None,
+            max_depth: None,
+            max_file_bytes: None,
+            #[cfg(feature = "encoding_rs")]
+            encoding: None,
+        }
     }
 }
 
@@ -71,337 +217,4417 @@ impl InlinerBuilder {
         self
     }
 
-    /// Parse the source code in `src_file` and return an `InliningResult` that has all modules
-    /// recursively inlined.
-    pub fn parse_and_inline_modules(&self, src_file: &Path) -> Result<InliningResult, Error> {
-        self.parse_internal(src_file, &mut FsResolver::new(|_: &Path, _| {}))
+    /// Configures how a `#[path]` attribute on a module that already has content is treated.
+    ///
+    /// `rustc` uses the path on an inline module such as `#[path = "x"] mod foo { mod bar; }`
+    /// as the directory in which `bar` is found. When this is set to `true`, the `#[path]`
+    /// is ignored and `bar` is resolved as though it was declared in `mod foo { ... }`.
+    ///
+    /// Default: `false`.
+    pub fn ignore_path_on_inline_mods(&mut self, ignore: bool) -> &mut Self {
+        self.ignore_path_on_inline_mods = ignore;
+        self
     }
 
-    /// Parse the source code in `src_file` and return an `InliningResult` that has all modules
-    /// recursively inlined. Call the given callback whenever a file is loaded from disk (regardless
-    /// of if it parsed successfully).
-    pub fn inline_with_callback(
-        &self,
-        src_file: &Path,
-        on_load: impl FnMut(&Path, String),
-    ) -> Result<InliningResult, Error> {
-        self.parse_internal(src_file, &mut FsResolver::new(on_load))
+    /// Configures whether modules loaded from other files have their own modules inlined.
+    ///
+    /// When `false`, each `mod foo;` in the file being parsed, including those nested in
+    /// inline modules, is replaced by the items of its file, but the `mod bar;` items within
+    /// that file are left as declarations. Only one level of files is inlined.
+    ///
+    /// Default: `true`.
+    pub fn recursive(&mut self, recursive: bool) -> &mut Self {
+        self.recursive = recursive;
+        self
     }
 
-    fn parse_internal<R: FileResolver>(
-        &self,
-        src_file: &Path,
-        resolver: &mut R,
-    ) -> Result<InliningResult, Error> {
-        // XXX There is no way for library callers to disable error tracking,
-        // but until we're sure that there's no performance impact of enabling it
-        // we'll let downstream code think that error tracking is optional.
-        let mut errors = Some(vec![]);
-        let result = Visitor::<R>::new(src_file, self.root, errors.as_mut(), resolver).visit()?;
-        Ok(InliningResult::new(result, errors.unwrap_or_default()))
+    /// Configures whether each inlined module is annotated with the path of the file it was
+    /// loaded from.
+    ///
+    /// The path is added as a `#[syn_inline_mod_path("...")]` attribute. Paths use the same
+    /// base (relative or absolute) as the original path passed in. Use `find_mod_path` to
+    /// read the annotation back.
+    ///
+    /// The attributes of an inlined module are always in this order:
+    ///
+    /// 1. The attributes of the `mod` item, including `#[cfg]`, `#[cfg_attr]` and `#[path]`,
+    ///    in the order they were written.
+    /// 2. The path annotation.
+    /// 3. The module path annotation, if `annotate_module_paths` is enabled.
+    /// 4. The inner attributes of the loaded file, in the order they were written.
+    ///
+    /// Default: `false`.
+    pub fn annotate_paths(&mut self, annotate: bool) -> &mut Self {
+        self.annotate_paths = annotate;
+        self
     }
-}
-
-/// An error that was encountered while reading, parsing or inlining a module.
-///
-/// Errors block further progress on inlining, but do not invalidate other progress.
-/// Therefore, only an error on the initially-passed-in-file is fatal to inlining.
-#[derive(Debug)]
-pub enum Error {
-    /// An error happened while opening or reading the file.
-    Io(io::Error),
 
-    /// Errors happened while using `syn` to parse the file.
-    Parse(syn::Error),
-}
+    /// Sets how `annotate_paths` stores paths that aren't valid UTF-8.
+    ///
+    /// With `PathEncoding::Utf8Strict`, a module loaded from such a path is reported as an
+    /// error instead of being inlined. Use `find_mod_path_with_encoding` with
+    /// `InliningResult::path_encoding` to read the annotations back.
+    ///
+    /// Default: `PathEncoding::Bytes`.
+    pub fn path_encoding(&mut self, encoding: PathEncoding) -> &mut Self {
+        self.path_encoding = encoding;
+        self
+    }
 
-impl error::Error for Error {
-    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
-        match self {
-            Error::Io(err) => Some(err),
-            Error::Parse(err) => Some(err),
+    /// Configures whether `annotate_paths` fails to inline a module loaded from a path that
+    /// isn't valid UTF-8, rather than storing the path as a byte string.
+    ///
+    /// This is shorthand for `path_encoding(PathEncoding::Utf8Strict)`. Passing `false` goes
+    /// back to the default `PathEncoding::Bytes` if strict UTF-8 was required, and otherwise
+    /// leaves the encoding alone.
+    ///
+    /// Default: `false`.
+    pub fn require_utf8_paths(&mut self, require: bool) -> &mut Self {
+        if require {
+            self.path_encoding = PathEncoding::Utf8Strict;
+        } else if self.path_encoding == PathEncoding::Utf8Strict {
+            self.path_encoding = PathEncoding::Bytes;
         }
+        self
     }
-}
 
-impl From<io::Error> for Error {
-    fn from(err: io::Error) -> Self {
-        Error::Io(err)
+    /// Configures whether the paths in annotations and errors are made absolute.
+    ///
+    /// When `true`, the paths recorded by `annotate_paths` and the paths reported by
+    /// `InlineError` are joined to the current directory if they are relative, and `.` and `..`
+    /// components are removed without following symbolic links. This makes results from
+    /// different working directories comparable. Other paths, such as those in the module
+    /// graph, keep the base of the path passed in.
+    ///
+    /// Default: `false`.
+    pub fn absolute_paths(&mut self, absolute: bool) -> &mut Self {
+        self.absolute_paths = absolute;
+        self
     }
-}
 
-impl From<syn::Error> for Error {
-    fn from(err: syn::Error) -> Self {
-        Error::Parse(err)
+    /// Configures whether each inlined module is annotated with its logical path relative to
+    /// the crate root.
+    ///
+    /// The path is added as a `#[syn_inline_mod_module_path("foo::bar")]` attribute after the
+    /// path annotation, if any, and before the inner attributes of the loaded file. Use
+    /// `find_module_path` to read the annotation back.
+    ///
+    /// Default: `false`.
+    pub fn annotate_module_paths(&mut self, annotate: bool) -> &mut Self {
+        self.annotate_module_paths = annotate;
+        self
     }
-}
 
-impl fmt::Display for Error {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match self {
-            Error::Io(_) => write!(f, "IO error"),
-            Error::Parse(_) => write!(f, "parse error"),
-        }
+    /// Configures whether `foo/mod.rs` is checked before `foo.rs` when looking for the file
+    /// of `mod foo;`.
+    ///
+    /// This only matters when both files exist. For more control, use `candidate_selector`.
+    ///
+    /// Default: `false`.
+    pub fn prefer_mod_rs(&mut self, prefer: bool) -> &mut Self {
+        self.prefer_mod_rs = prefer;
+        self
     }
-}
 
-/// The result of a best-effort attempt at inlining.
-///
-/// This struct guarantees that the origin file was readable and valid Rust source code, but
-/// `errors` must be inspected to check if everything was inlined successfully.
-pub struct InliningResult {
-    output: syn::File,
-    errors: Vec<InlineError>,
-}
+    /// Configures whether modules gated by `#[cfg(test)]` are removed from the output.
+    ///
+    /// When `true`, any `mod` item with a `#[cfg(test)]` attribute, whether it is declared
+    /// inline or in another file, is removed entirely rather than inlined. Removed modules
+    /// are not loaded and do not appear in the module graph.
+    ///
+    /// Default: `false`.
+    pub fn drop_test_modules(&mut self, drop: bool) -> &mut Self {
+        self.drop_test_modules = drop;
+        self
+    }
 
-impl InliningResult {
-    /// Create a new `InliningResult` with the best-effort output and any errors encountered
-    /// during the inlining process.
-    pub(crate) fn new(output: syn::File, errors: Vec<InlineError>) -> Self {
-        InliningResult { output, errors }
+    /// Configures whether a module whose file can't be found looks for a likely replacement.
+    ///
+    /// When `true`, the directory of the missing file and the directories next to it are
+    /// searched for a file with the same stem, which is reported by `InlineError::suggestion`.
+    /// The directories are listed with `FileResolver::read_dir`.
+    ///
+    /// Default: `false`.
+    pub fn suggest_on_missing(&mut self, suggest: bool) -> &mut Self {
+        self.suggest_on_missing = suggest;
+        self
     }
 
-    /// The best-effort result of inlining.
-    pub fn output(&self) -> &syn::File {
-        &self.output
+    /// Configures whether a module that couldn't be inlined is replaced by a stub that fails
+    /// to compile.
+    ///
+    /// When `true`, a failed `mod foo;` becomes `mod foo { compile_error!("..."); }` with a
+    /// message giving the reason, so compiling the output reports the failure. When `false`,
+    /// the module is left as it was in the source. The error is reported by `InliningResult`
+    /// either way.
+    ///
+    /// Default: `false`.
+    pub fn error_stubs(&mut self, stubs: bool) -> &mut Self {
+        self.error_stubs = stubs;
+        self
     }
 
-    /// The errors that kept the inlining from completing. May be empty if there were no errors.
-    pub fn errors(&self) -> &[InlineError] {
-        &self.errors
+    /// Sets the configuration used to evaluate `#[cfg]` and `#[cfg_attr]` attributes on
+    /// modules.
+    ///
+    /// Without configuration, every module is inlined, and the first `cfg_attr` containing a
+    /// `path` is used regardless of its predicate. With configuration, modules whose `#[cfg]`
+    /// predicates don't hold are removed from the output, and the first `cfg_attr` whose
+    /// predicate holds is used, falling back to the module's ident if there is none.
+    pub fn cfg_options(&mut self, options: CfgOptions) -> &mut Self {
+        self.cfg_options = Some(options);
+        self
     }
 
-    /// Whether the result has any errors. `false` implies that all inlining operations completed
-    /// successfully.
-    pub fn has_errors(&self) -> bool {
-        !self.errors.is_empty()
+    /// Sets `cfg` names that are never enabled, such as `FALSE` for code disabled with
+    /// `#[cfg(FALSE)]`.
+    ///
+    /// When no `cfg_options` are set, a `mod` item with a `#[cfg]` that is false in every
+    /// configuration, such as `#[cfg(any())]` or `#[cfg(not(all()))]`, is removed from the
+    /// output without looking for its file, so it isn't recorded as an error. These names are
+    /// treated as false while checking for such a `#[cfg]`. When `cfg_options` are set, they
+    /// decide which modules are removed instead, and these names are ignored.
+    ///
+    /// Default: no names, so only predicates such as `any()` are treated as always false.
+    pub fn never_set_cfgs(
+        &mut self,
+        names: impl IntoIterator<Item = impl Into<String>>,
+    ) -> &mut Self {
+        self.never_set_cfgs = names.into_iter().map(Into::into).collect();
+        self
     }
 
-    /// Break an incomplete inlining into the best-effort parsed result and the errors encountered.
+    /// Configures whether `cfg_if!` invocations in item position are replaced by the items
+    /// of the branch they select, so the modules declared in that branch are inlined.
     ///
-    /// # Usage
+    /// Branches are selected with the configuration from `cfg_options`. Without
+    /// configuration, the first branch is always selected. Invocations that don't follow the
+    /// `cfg_if!` syntax are left alone.
     ///
-    /// ```rust,ignore
-    /// # #![allow(unused_variables)]
-    /// # use std::path::Path;
-    /// # use syn_inline_mod::InlinerBuilder;
-    /// let result = InlinerBuilder::default().parse_and_inline_modules(Path::new("foo.rs"));
-    /// match result {
-    ///     Err(e) => unimplemented!(),
-    ///     Ok(r) if r.has_errors() => {
-    ///         let (best_effort, errors) = r.into_output_and_errors();
-    ///         // do things with the partial output and the errors
-    ///     },
-    ///     Ok(r) => {
-    ///         let (complete, _) = r.into_output_and_errors();
-    ///         // do things with the completed output
-    ///     }
-    /// }
-    /// ```
-    pub fn into_output_and_errors(self) -> (syn::File, Vec<InlineError>) {
-        (self.output, self.errors)
+    /// Default: `false`.
+    pub fn expand_cfg_if(&mut self, expand: bool) -> &mut Self {
+        self.expand_cfg_if = expand;
+        self
     }
-}
 
-impl fmt::Debug for InliningResult {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        self.errors.fmt(f)
+    /// Configures whether `include!("file.rs")` invocations in item position are replaced by
+    /// the items of the file they name, so the output contains generated items and the
+    /// modules they declare are inlined.
+    ///
+    /// As with `rustc`, the path is relative to the directory of the file containing the
+    /// invocation. Included files may include other files. An invocation whose file can't be
+    /// read or parsed is left alone and reported with `WarningKind::UnresolvedInclude`.
+    ///
+    /// Default: `false`.
+    pub fn expand_includes(&mut self, expand: bool) -> &mut Self {
+        self.expand_includes = expand;
+        self
+    }
+
+    /// Configures whether the `path` inside a `#[cfg_attr]` that was used to find a module is
+    /// removed from the inlined module, since it no longer has any effect and may confuse
+    /// other tools.
+    ///
+    /// Other attributes in the same `cfg_attr` are kept, and a plain `#[path]` attribute is
+    /// never removed. Use `replace_resolved_cfg_attr` to keep the condition that selected the
+    /// path.
+    ///
+    /// Default: `false`.
+    pub fn strip_resolved_cfg_attr(&mut self, strip: bool) -> &mut Self {
+        self.strip_resolved_cfg_attr = strip;
+        self
+    }
+
+    /// Configures whether a `path` removed by `strip_resolved_cfg_attr` is replaced by a
+    /// `#[cfg]` of the condition that selected it, e.g. `#[cfg_attr(unix, path = "unix.rs")]`
+    /// becomes `#[cfg(unix)]`, since the inlined content only applies under that condition.
+    ///
+    /// Has no effect unless `strip_resolved_cfg_attr` is enabled.
+    ///
+    /// Default: `false`.
+    pub fn replace_resolved_cfg_attr(&mut self, replace: bool) -> &mut Self {
+        self.replace_resolved_cfg_attr = replace;
+        self
+    }
+
+    /// Sets a function that picks which file to load when more than one candidate file
+    /// for a module exists, such as when both `foo.rs` and `foo/mod.rs` are present.
+    ///
+    /// The function receives the existing candidates in search order. If it returns `None`,
+    /// the first existing candidate is used.
+    pub fn candidate_selector(
+        &mut self,
+        selector: impl Fn(&[PathBuf]) -> Option<PathBuf> + Send + Sync + 'static,
+    ) -> &mut Self {
+        self.candidate_selector = Some(Callback(Arc::new(selector)));
+        self
+    }
+
+    /// Sets a function that is called with each candidate file checked while looking for
+    /// a module's file, and whether that file exists.
+    ///
+    /// Candidates are checked in order until one exists, so later candidates may not be
+    /// reported. If a `candidate_selector` is set, every candidate is checked.
+    pub fn on_candidate(
+        &mut self,
+        on_candidate: impl FnMut(&Path, bool) + Send + 'static,
+    ) -> &mut Self {
+        self.on_candidate = Some(Callback(Arc::new(Mutex::new(on_candidate))));
+        self
+    }
+
+    /// Sets a function that can change the items of each module loaded from a file before
+    /// they replace the module's declaration.
+    ///
+    /// The function receives the path of the loaded file and its items, after any modules
+    /// within it have been inlined. Changes made here will be reported by
+    /// `InliningResult::verify`.
+    pub fn transform_child(
+        &mut self,
+        transform: impl FnMut(&Path, &mut Vec<syn::Item>) + Send + 'static,
+    ) -> &mut Self {
+        self.transform_child = Some(Callback(Arc::new(Mutex::new(transform))));
+        self
+    }
+
+    /// Sets a function that orders the items of the root file and of every module, for output
+    /// that is stable when items are moved around.
+    ///
+    /// Items are sorted with a stable sort after the modules among them are inlined, so a
+    /// `mod foo;` item is compared with its content filled in, along with any items added by
+    /// `expand_includes` or `extra_modules`. The module graph keeps the modules in the order
+    /// they were visited, which is source order.
+    ///
+    /// Default: items are kept in source order.
+    pub fn sort_items(
+        &mut self,
+        order: impl Fn(&syn::Item, &syn::Item) -> Ordering + Send + Sync + 'static,
+    ) -> &mut Self {
+        self.sort_items = Some(Callback(Arc::new(order)));
+        self
+    }
+
+    /// Sets a function that decides which attributes are kept on modules inlined from files,
+    /// such as to drop `#[rustfmt::skip]` or custom attributes that don't make sense once the
+    /// module is flattened.
+    ///
+    /// The function is called with each outer attribute of the `mod` item and each inner
+    /// attribute of the loaded file, and attributes for which it returns `false` are dropped.
+    /// The annotations added by `annotate_paths` and `annotate_module_paths` are always kept.
+    ///
+    /// Default: every attribute is kept.
+    pub fn attribute_filter(
+        &mut self,
+        filter: impl Fn(&syn::Attribute) -> bool + Send + Sync + 'static,
+    ) -> &mut Self {
+        self.attribute_filter = Some(Callback(Arc::new(filter)));
+        self
+    }
+
+    /// Sets the workspace root used to resolve `#[path]` attributes that start with the
+    /// workspace sentinel, which is `//` unless changed with `workspace_sentinel`.
+    ///
+    /// For example, with a workspace root of `/repo`, `#[path = "//shared/util.rs"]` is loaded
+    /// from `/repo/shared/util.rs` regardless of the file that declares the module. This is not
+    /// supported by `rustc`.
+    pub fn workspace_root(&mut self, root: impl Into<PathBuf>) -> &mut Self {
+        self.workspace_root = Some(root.into());
+        self
+    }
+
+    /// Sets the prefix that marks a `#[path]` attribute as relative to the workspace root.
+    ///
+    /// Setting a sentinel without a `workspace_root` causes modules using it to fail with
+    /// `Error::MissingWorkspaceRoot`.
+    pub fn workspace_sentinel(&mut self, sentinel: impl Into<String>) -> &mut Self {
+        self.workspace_sentinel = Some(sentinel.into());
+        self
+    }
+
+    /// Sets the directory the modules of the root file are found in, in place of the
+    /// directory containing the root file.
+    ///
+    /// The root file is still read from its own path, and its file name is still used to find
+    /// its modules, so with a base directory of `/real/src`, the modules of a non-root file
+    /// `/virtual/runner.rs` are found in `/real/src/runner/`. Modules of other files are found
+    /// relative to those files as usual.
+    ///
+    /// Default: `None`.
+    pub fn base_dir(&mut self, dir: Option<PathBuf>) -> &mut Self {
+        self.base_dir = dir;
+        self
+    }
+
+    /// Configures whether a `#[path]` attribute holding a module path that starts with `self`,
+    /// `super` or `crate`, such as `#[path = "super::shared"]`, is resolved as that module
+    /// rather than as a file name.
+    ///
+    /// Each `super` moves up one directory from the directory the modules declared alongside
+    /// the module would be found in, and `crate` moves up to the root file's directory. The
+    /// remaining segments are then found like nested `mod` items, so `super::shared` is loaded
+    /// from `../shared.rs` or `../shared/mod.rs`. This assumes the directories mirror the
+    /// module tree. This is not supported by `rustc`.
+    ///
+    /// Default: `false`.
+    pub fn qualified_paths(&mut self, enabled: bool) -> &mut Self {
+        self.qualified_paths = enabled;
+        self
+    }
+
+    /// Sets directories that each hold a layer of the source tree, such as a base layer and
+    /// an overlay layer that adds or replaces files.
+    ///
+    /// Each candidate file for a module is made relative to whichever root contains it, then
+    /// tried under every root in order. For `mod foo;`, this checks `foo.rs` in every root
+    /// before checking `foo/mod.rs` in any of them. The first file that exists is used.
+    ///
+    /// Default: no search roots, so candidates are only checked where they are found.
+    pub fn search_roots(
+        &mut self,
+        roots: impl IntoIterator<Item = impl Into<PathBuf>>,
+    ) -> &mut Self {
+        self.search_roots = roots.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Sets modules to add to the output that aren't declared in the source, such as
+    /// generated code, as the logical path of each module and the file to load it from.
+    ///
+    /// Each module is appended as a `mod` item to the end of its parent module, which must be
+    /// in the output, and is then inlined like any other module. For `(vec!["generated"],
+    /// "src/generated.rs")`, `mod generated;` is added to the crate root and loaded from
+    /// `src/generated.rs`. If the module is already declared, it is loaded from the given file
+    /// instead of being added again. Modules whose name isn't a valid identifier are ignored.
+    ///
+    /// Default: no extra modules.
+    pub fn extra_modules(&mut self, modules: Vec<(Vec<String>, PathBuf)>) -> &mut Self {
+        self.extra_modules = modules;
+        self
+    }
+
+    /// Configures whether code that may make the output incomplete is reported by
+    /// `InliningResult::warnings`.
+    ///
+    /// Currently this reports macro invocations in item position, such as `cfg_if! { ... }`,
+    /// which may expand to `mod` items that can't be inlined.
+    ///
+    /// Default: `false`.
+    pub fn collect_warnings(&mut self, collect: bool) -> &mut Self {
+        self.collect_warnings = collect;
+        self
+    }
+
+    /// Configures whether a module at the crate root named after a crate that is always
+    /// available, such as `mod std;` or `mod core;`, is reported by `InliningResult::warnings`.
+    ///
+    /// Such a module shadows the crate, which is almost always a mistake. A
+    /// `WarningKind::ShadowedCrate` warning is recorded whether or not `collect_warnings` is
+    /// enabled.
+    ///
+    /// Default: `false`.
+    pub fn warn_shadowing(&mut self, warn: bool) -> &mut Self {
+        self.warn_shadowing = warn;
+        self
+    }
+
+    /// Configures whether a module loaded using a `#[path]` attribute is reported by
+    /// `InliningResult::warnings` when a different file exists where the module would be
+    /// found by its name.
+    ///
+    /// This helps find `#[path]` attributes that should be removed when moving a crate to the
+    /// conventional layout. A `WarningKind::PathMismatch` warning is recorded whether or not
+    /// `collect_warnings` is enabled.
+    ///
+    /// Default: `false`.
+    pub fn warn_path_mismatch(&mut self, warn: bool) -> &mut Self {
+        self.warn_path_mismatch = warn;
+        self
+    }
+
+    /// Configures whether a file that fails to parse is inlined up to its last valid line.
+    ///
+    /// When `true`, a file with a syntax error is cut off at the end of progressively earlier
+    /// lines, starting before the error, until what remains parses. A
+    /// `WarningKind::TruncatedParse` warning is recorded whether or not `collect_warnings` is
+    /// enabled. This is a heuristic meant for analyzing code that is being edited, and only
+    /// works with resolvers that implement `FileResolver::load`.
+    ///
+    /// Default: `false`.
+    pub fn tolerant_parse(&mut self, tolerant: bool) -> &mut Self {
+        self.tolerant_parse = tolerant;
+        self
+    }
+
+    /// Configures whether the source of each file is kept, so it can be read back with
+    /// `InliningResult::source_of`, such as to compare edits to the output against the
+    /// original files.
+    ///
+    /// Only the source of files loaded with `FileResolver::load` can be kept. Sources are
+    /// always kept when the `codespan` feature is enabled.
+    ///
+    /// Default: `false`.
+    pub fn retain_sources(&mut self, retain: bool) -> &mut Self {
+        self.retain_sources = retain;
+        self
+    }
+
+    /// Configures whether the time taken to load and parse each file is recorded.
+    ///
+    /// Durations are available from the module graph and from `InliningResult::stats`.
+    ///
+    /// Default: `false`.
+    pub fn time(&mut self, time: bool) -> &mut Self {
+        self.time = time;
+        self
+    }
+
+    /// Configures whether `mod foo;` becomes an empty module when neither `foo.rs` nor
+    /// `foo/mod.rs` exists but a directory `foo/` does.
+    ///
+    /// `rustc` reports an error in this case, but some code generators create a directory of
+    /// files before the module's own file. The module is left empty and isn't reported as an
+    /// error.
+    ///
+    /// Default: `false`.
+    pub fn implicit_dir_modules(&mut self, implicit: bool) -> &mut Self {
+        self.implicit_dir_modules = implicit;
+        self
+    }
+
+    /// Sets the maximum number of items in the output, to bound the memory used by inlining
+    /// very large crates.
+    ///
+    /// Every item declared directly in a module counts towards the limit, including the items
+    /// of the root file and of inline modules but not items inside function bodies. A module
+    /// whose file would take the output over the limit is left alone and reported with
+    /// `Error::OutputLimit`. The root file is always included.
+    ///
+    /// Default: no limit.
+    pub fn max_output_items(&mut self, max: usize) -> &mut Self {
+        self.max_output_items = Some(max);
+        self
+    }
+
+    /// Sets how deeply modules may be nested, to bound the recursion of inlining untrusted or
+    /// adversarial module trees.
+    ///
+    /// Modules declared at the top level of the root file have depth 1, and inline modules
+    /// count towards the depth like modules loaded from files. A module deeper than `depth`
+    /// isn't loaded from its file and is reported with `Error::DepthLimitExceeded`. Inline
+    /// modules are always visited, as they can't nest more deeply than their file allows.
+    ///
+    /// Default: no limit.
+    pub fn max_depth(&mut self, depth: usize) -> &mut Self {
+        self.max_depth = Some(depth);
+        self
+    }
+
+    /// Sets the largest file, in bytes, that will be read from the file system, to avoid
+    /// reading very large generated files into memory.
+    ///
+    /// The size of each file is checked before it is read. A module whose file is larger is
+    /// left alone and reported with `Error::FileTooLarge`, and a root file that is larger
+    /// fails inlining with that error. Files from other resolvers aren't checked.
+    ///
+    /// Default: no limit.
+    pub fn max_file_bytes(&mut self, max: usize) -> &mut Self {
+        self.max_file_bytes = Some(max);
+        self
+    }
+
+    /// Sets the encoding used to read files from the file system.
+    ///
+    /// Files are decoded to UTF-8 before they are parsed. A file that isn't valid in the
+    /// encoding produces an `Error::Io` naming the file and the encoding.
+    ///
+    /// Default: UTF-8.
+    #[cfg(feature = "encoding_rs")]
+    pub fn encoding(&mut self, encoding: &'static encoding_rs::Encoding) -> &mut Self {
+        self.encoding = Some(encoding);
+        self
+    }
+
+    /// Create a resolver that reads from the file system using these options.
+    fn fs_resolver<F: FnMut(&Path, String)>(&self, on_load: F) -> FsResolver<F> {
+        let resolver = FsResolver::new(on_load).with_max_bytes(self.max_file_bytes);
+        #[cfg(feature = "encoding_rs")]
+        let resolver = resolver.with_encoding(self.encoding);
+        resolver
+    }
+
+    /// Get the part of `path` following the workspace sentinel, if `path` starts with it.
+    pub(crate) fn strip_workspace_sentinel<'p>(&self, path: &'p Path) -> Option<&'p str> {
+        let sentinel = match (&self.workspace_sentinel, &self.workspace_root) {
+            (Some(sentinel), _) => sentinel.as_str(),
+            (None, Some(_)) => "//",
+            (None, None) => return None,
+        };
+
+        path.to_str()?.strip_prefix(sentinel)
+    }
+
+    /// Get `path` as it should appear in annotations and errors, which is absolute if
+    /// `absolute_paths` is enabled.
+    pub(crate) fn output_path(&self, path: &Path) -> PathBuf {
+        if self.absolute_paths {
+            if let Ok(absolute) = std::path::absolute(path) {
+                return normalize(&absolute);
+            }
+        }
+
+        path.to_path_buf()
+    }
+
+    /// Create an `Inliner` with a copy of this configuration, which caches the files it reads
+    /// so they can be reused by later calls.
+    pub fn build(&self) -> Inliner {
+        Inliner::new(self.clone())
+    }
+
+    /// Parse the source code in `src_file` and return an `InliningResult` that has all modules
+    /// recursively inlined.
+    pub fn parse_and_inline_modules(&self, src_file: &Path) -> Result<InliningResult, Error> {
+        self.parse_internal(src_file, &mut self.fs_resolver(|_: &Path, _| {}))
+    }
+
+    /// Parse the source code in `root` and return an `InliningResult` that has all modules
+    /// recursively inlined, reusing the modules of an earlier result whose files haven't
+    /// changed.
+    ///
+    /// `prev` is the module graph of an earlier result for `root`, inlined with the same
+    /// options, and `changed` should contain every file that was modified, created or deleted
+    /// since, using the same base (relative or absolute) as `root`. A module is inlined again
+    /// if its file, the file of a module above it, or the file of a module inside it changed,
+    /// or if a module inside it couldn't be inlined; otherwise its inlined items are copied
+    /// from `prev` without reading or parsing its files. Errors, warnings and macro
+    /// invocations are only reported for the modules that were inlined again.
+    pub fn inline_incremental(
+        &self,
+        root: &Path,
+        changed: &HashSet<PathBuf>,
+        prev: &ModuleGraph,
+    ) -> Result<InliningResult, Error> {
+        self.inline_incremental_internal(
+            root,
+            changed,
+            prev,
+            &mut self.fs_resolver(|_: &Path, _| {}),
+        )
+    }
+
+    /// Parse the source code in `src_file` and return an `InliningResult` that has all modules
+    /// recursively inlined, reading every file through `resolver` instead of the file system.
+    ///
+    /// Candidate paths for each module are found as they would be on disk, then checked and
+    /// loaded with `resolver`, so files can come from memory, an archive or a version control
+    /// system. Options that read the file system directly, such as `max_file_bytes` and
+    /// `encoding`, have no effect unless `resolver` implements them.
+    pub fn parse_and_inline_modules_with_resolver<R: FileResolver>(
+        &self,
+        src_file: &Path,
+        resolver: &mut R,
+    ) -> Result<InliningResult, Error> {
+        self.parse_internal(src_file, resolver)
+    }
+
+    /// Parse `src` as the source code of a root file and return an `InliningResult` that has
+    /// all modules recursively inlined, finding the files of its modules in `base_dir`.
+    ///
+    /// This avoids writing source that is already in memory, such as from standard input, to
+    /// a temporary file. Errors and `InliningResult::source_of` refer to `src` as `<memory>`,
+    /// and every other file is read from the file system.
+    ///
+    /// If `root` is enabled, `src` is treated as a root file in `base_dir`, so its modules are
+    /// found in `base_dir` itself. Otherwise it is treated as a non-root file named after
+    /// `base_dir`, such as `src/foo.rs` for `src/foo`: its modules are still found in
+    /// `base_dir`, but a `#[path]` on one of them is relative to the parent of `base_dir`.
+    pub fn inline_modules_from_str(
+        &self,
+        src: &str,
+        base_dir: &Path,
+    ) -> Result<InliningResult, Error> {
+        self.inline_from_str_internal(src, base_dir, self.fs_resolver(|_: &Path, _| {}))
+    }
+
+    /// Parse the source code in `src_file` and return an `InliningResult` that has all modules
+    /// recursively inlined. Call the given callback whenever a file is loaded from disk (regardless
+    /// of if it parsed successfully).
+    ///
+    /// Files are reported in the order they are loaded, starting with `src_file` itself.
+    pub fn inline_with_callback(
+        &self,
+        src_file: &Path,
+        on_load: impl FnMut(&Path, String),
+    ) -> Result<InliningResult, Error> {
+        self.parse_internal(src_file, &mut self.fs_resolver(on_load))
+    }
+
+    /// Parse the source code in `src_file` and report whether all of its modules could be
+    /// recursively inlined, without returning the inlined output.
+    ///
+    /// Returns `Ok(false)` if any referenced module could not be inlined. As with
+    /// `parse_and_inline_modules`, an `Err` is only returned if `src_file` itself could not
+    /// be read or parsed.
+    pub fn check(&self, src_file: &Path) -> Result<bool, Error> {
+        self.lint(src_file).map(|errors| errors.is_empty())
+    }
+
+    /// Parse the source code in `src_file` and return the best-effort output with the file and
+    /// module path that each of its items came from.
+    ///
+    /// Modules that couldn't be inlined are left alone without being reported. Use
+    /// `parse_and_inline_modules` and `InliningResult::provenance_map` to also get the errors.
+    pub fn inline_with_provenance(
+        &self,
+        src_file: &Path,
+    ) -> Result<(syn::File, ProvenanceMap), Error> {
+        let result = self.parse_and_inline_modules(src_file)?;
+        let provenance = result.provenance_map();
+        Ok((result.output, provenance))
+    }
+
+    /// Parse the source code in `root` and return an `InliningResult` along with a trace of
+    /// the candidate files considered for each module, which one was chosen, and the outcome.
+    ///
+    /// Print the trace to see why a module didn't resolve as expected.
+    pub fn resolve_and_trace(&self, root: &Path) -> Result<(InliningResult, ResolveTrace), Error> {
+        self.trace_internal(root, &mut self.fs_resolver(|_: &Path, _| {}))
+    }
+
+    /// Parse the source code in `src_file` and return the modules that could not be inlined,
+    /// without building the inlined output.
+    ///
+    /// Every module is still loaded and visited, but the content of each file is dropped once
+    /// its modules have been visited, so this is cheaper than `parse_and_inline_modules` on
+    /// large crates. As with `parse_and_inline_modules`, an `Err` is only returned if
+    /// `src_file` itself could not be read or parsed.
+    pub fn lint(&self, src_file: &Path) -> Result<Vec<InlineError>, Error> {
+        self.lint_internal(src_file, &mut self.fs_resolver(|_: &Path, _| {}))
+    }
+
+    /// Load the file of each module of the crate at `root`, one module at a time, without
+    /// building the inlined output.
+    ///
+    /// Files are returned depth-first in source order, starting with `root`, with the `mod`
+    /// items leading to each module from the crate root. Each file is parsed when it is
+    /// reached, and its `mod` items without content are left as they are, so only one file is
+    /// held in memory at a time unless the caller keeps them. Modules written inline as
+    /// `mod foo { ... }` stay in the file that declares them.
+    ///
+    /// An `Err` is returned in place of a module whose file couldn't be loaded, and after a
+    /// file that declares a module whose file couldn't be found. If `root` itself can't be
+    /// loaded, that is the only item.
+    pub fn iter_modules(
+        &self,
+        root: &Path,
+    ) -> impl Iterator<Item = Result<(ModContext, syn::File), Error>> + '_ {
+        module_iter::ModuleIter::new(self, root, self.fs_resolver(|_: &Path, _| {}))
+    }
+
+    /// Parse the source code in `root` and report every `mod` item that would be replaced by
+    /// the content of a file, including those inside inline `mod foo { ... }` blocks, without
+    /// building the inlined output.
+    ///
+    /// Files are loaded as by `lint`, so the plan reflects the other options of this builder,
+    /// such as `cfg_options`. Modules that couldn't be inlined aren't planned; use `lint` to
+    /// find them. An `Err` is only returned if `root` itself could not be read or parsed.
+    pub fn plan(&self, root: &Path) -> Result<Vec<InlinePlan>, Error> {
+        self.plan_internal(root, &mut self.fs_resolver(|_: &Path, _| {}))
+    }
+
+    /// Inline `root` under configurations `a` and `b`, and report which modules are present
+    /// under only one of them or under both.
+    ///
+    /// The other options of this builder are used for both configurations, replacing any
+    /// configuration set with `cfg_options`. Modules that couldn't be inlined are still
+    /// compared, but the modules declared in their files are missing from the comparison.
+    pub fn diff_cfgs(&self, root: &Path, a: CfgOptions, b: CfgOptions) -> Result<CfgDiff, Error> {
+        self.diff_cfgs_internal(root, a, b, &mut self.fs_resolver(|_: &Path, _| {}))
+    }
+
+    /// Find the `.rs` files in `dir` and its subdirectories that are not loaded when inlining
+    /// `root`, such as files whose `mod` declaration was removed.
+    ///
+    /// Files are compared by their canonical paths. The returned paths are sorted and start
+    /// with `dir`. Returns an error if `root` or `dir` could not be read.
+    pub fn unreferenced_files(&self, root: &Path, dir: &Path) -> Result<Vec<PathBuf>, Error> {
+        let mut reached = HashSet::new();
+        self.inline_with_callback(root, |path, _| {
+            reached.insert(path.canonicalize().unwrap_or_else(|_| path.to_path_buf()));
+        })?;
+
+        let mut files = vec![];
+        let mut visited = HashSet::new();
+        find_rs_files(&self.fs_resolver(|_, _| {}), dir, &mut visited, &mut files)?;
+        files.retain(|file| {
+            !reached.contains(&file.canonicalize().unwrap_or_else(|_| file.clone()))
+        });
+        files.sort();
+        Ok(files)
+    }
+
+    /// Parse the source code in `root` and return the content of the module at `module_path`,
+    /// such as `&["foo", "bar"]` for `crate::foo::bar`, with its own modules inlined.
+    ///
+    /// Only the modules on the way to the target are inlined, so errors in other modules are
+    /// ignored. The returned file has the inner attributes and items of the target module.
+    ///
+    /// Returns `Error::ModuleNotFound` if no module exists at `module_path`, or the error
+    /// encountered while inlining the target or one of its ancestors.
+    pub fn resolve_module(&self, root: &Path, module_path: &[&str]) -> Result<syn::File, Error> {
+        self.resolve_module_internal(root, module_path, &mut self.fs_resolver(|_: &Path, _| {}))
+    }
+
+    /// Find the file that defines the module at `module_path`, such as `&["foo", "bar"]` for
+    /// `crate::foo::bar`, starting from the crate root file `crate_root`.
+    ///
+    /// Only the files on the way to the module are read, and nothing is inlined. For a module
+    /// written inline as `mod bar { ... }`, this is the file containing it. An empty
+    /// `module_path` returns `crate_root`.
+    ///
+    /// Returns `Error::ModuleNotFound` if no module exists at `module_path`, or the error
+    /// encountered while loading the module or one of its ancestors.
+    pub fn resolve_logical(
+        &self,
+        crate_root: &Path,
+        module_path: &[&str],
+    ) -> Result<PathBuf, Error> {
+        self.resolve_logical_internal(
+            crate_root,
+            module_path,
+            &mut self.fs_resolver(|_: &Path, _| {}),
+        )
+    }
+
+    fn resolve_logical_internal<R: FileResolver>(
+        &self,
+        crate_root: &Path,
+        module_path: &[&str],
+        resolver: &mut R,
+    ) -> Result<PathBuf, Error> {
+        let target = module_path
+            .iter()
+            .map(|segment| segment.to_string())
+            .collect::<Vec<_>>();
+        let mut log = Log::default();
+        let mut visitor = Visitor::<R>::new(crate_root, self.root, self, &mut log, resolver);
+        visitor.target = Some(&target);
+        visitor.stop_at_target = true;
+        visitor.discard_content = true;
+        visitor.visit()?;
+
+        if target.is_empty() {
+            return Ok(crate_root.to_path_buf());
+        }
+
+        if let Some(error) = log
+            .errors
+            .into_iter()
+            .find(|error| target.starts_with(&error.module_path))
+        {
+            return Err(error.kind);
+        }
+
+        let node = log
+            .modules
+            .modules()
+            .iter()
+            .find(|node| node.module_path() == target)
+            .ok_or(Error::ModuleNotFound)?;
+        match node.file() {
+            Some(file) => Ok(file.to_path_buf()),
+            None if node.is_inline() => Ok(node.declared_in().to_path_buf()),
+            None => Err(Error::ModuleNotFound),
+        }
+    }
+
+    fn resolve_module_internal<R: FileResolver>(
+        &self,
+        root: &Path,
+        module_path: &[&str],
+        resolver: &mut R,
+    ) -> Result<syn::File, Error> {
+        let target = module_path
+            .iter()
+            .map(|segment| segment.to_string())
+            .collect::<Vec<_>>();
+        let mut log = Log::default();
+        let mut visitor = Visitor::<R>::new(root, self.root, self, &mut log, resolver);
+        visitor.target = Some(&target);
+        let output = visitor.visit()?;
+
+        if let Some(error) = log
+            .errors
+            .into_iter()
+            .find(|error| target.starts_with(&error.module_path))
+        {
+            return Err(error.kind);
+        }
+
+        let mut items = output.items;
+        let mut attrs = output.attrs;
+        for segment in &target {
+            let (module_attrs, module_items) = items
+                .into_iter()
+                .find_map(|item| match item {
+                    syn::Item::Mod(ItemMod {
+                        attrs,
+                        ident,
+                        content: Some((_, items)),
+                        ..
+                    }) if ident == segment => Some((attrs, items)),
+                    _ => None,
+                })
+                .ok_or(Error::ModuleNotFound)?;
+            attrs = module_attrs;
+            items = module_items;
+        }
+
+        attrs.retain(|attr| matches!(attr.style, syn::AttrStyle::Inner(_)));
+        Ok(syn::File {
+            shebang: None,
+            attrs,
+            items,
+        })
+    }
+
+    fn diff_cfgs_internal<R: FileResolver>(
+        &self,
+        root: &Path,
+        a: CfgOptions,
+        b: CfgOptions,
+        resolver: &mut R,
+    ) -> Result<CfgDiff, Error> {
+        let mut options = self.clone();
+        let a = options.cfg_options(a).parse_internal(root, resolver)?;
+        let b = options.cfg_options(b).parse_internal(root, resolver)?;
+        Ok(CfgDiff::new(a.module_graph(), b.module_graph()))
+    }
+
+    fn trace_internal<R: FileResolver>(
+        &self,
+        root: &Path,
+        resolver: &mut R,
+    ) -> Result<(InliningResult, ResolveTrace), Error> {
+        let mut log = Log::default();
+        let mut visitor = Visitor::<R>::new(root, self.root, self, &mut log, resolver);
+        visitor.trace = true;
+        let output = visitor.visit()?;
+
+        let candidates = std::mem::take(&mut log.candidates);
+        let result = InliningResult::new(output, log, self.path_encoding);
+        let trace = ResolveTrace::new(&result, candidates);
+        Ok((result, trace))
+    }
+
+    fn lint_internal<R: FileResolver>(
+        &self,
+        src_file: &Path,
+        resolver: &mut R,
+    ) -> Result<Vec<InlineError>, Error> {
+        let mut log = Log::default();
+        let mut visitor = Visitor::<R>::new(src_file, self.root, self, &mut log, resolver);
+        visitor.discard_content = true;
+        visitor.visit()?;
+        Ok(log.errors)
+    }
+
+    fn inline_from_str_internal<R: FileResolver>(
+        &self,
+        src: &str,
+        base_dir: &Path,
+        resolver: R,
+    ) -> Result<InliningResult, Error> {
+        let path = Path::new(MEMORY_SOURCE);
+        // A root file's modules are found in its directory, and a non-root file's in the
+        // directory named after it, so `src` is placed in `base_dir` or named after it.
+        let named = if self.root {
+            None
+        } else {
+            file_named_after(base_dir)
+        };
+        let (root, base) = match named {
+            Some(file) => (false, file),
+            None => (true, base_dir.join(MEMORY_SOURCE)),
+        };
+
+        let mut resolver = SourceOverlay::new(path.to_path_buf(), src, resolver);
+        let mut log = Log::default();
+        let mut visitor = Visitor::new(path, root, self, &mut log, &mut resolver);
+        visitor.root_base = Some(&base);
+        let result = visitor.visit()?;
+        Ok(InliningResult::new(result, log, self.path_encoding))
+    }
+
+    fn plan_internal<R: FileResolver>(
+        &self,
+        root: &Path,
+        resolver: &mut R,
+    ) -> Result<Vec<InlinePlan>, Error> {
+        let mut log = Log::default();
+        let mut visitor = Visitor::<R>::new(root, self.root, self, &mut log, resolver);
+        visitor.discard_content = true;
+        visitor.visit()?;
+        Ok(InlinePlan::from_graph(&log.modules))
+    }
+
+    fn inline_incremental_internal<R: FileResolver>(
+        &self,
+        root: &Path,
+        changed: &HashSet<PathBuf>,
+        prev: &ModuleGraph,
+        resolver: &mut R,
+    ) -> Result<InliningResult, Error> {
+        let reuse = Reuse::new(prev, changed);
+        let mut log = Log::default();
+        let mut visitor = Visitor::<R>::new(root, self.root, self, &mut log, resolver);
+        visitor.reuse = Some(&reuse);
+        let result = visitor.visit()?;
+        // Included files are matched by span, so the entries for files included by the reused
+        // modules still identify their items.
+        log.modules.includes.extend(prev.includes.iter().cloned());
+        Ok(InliningResult::new(result, log, self.path_encoding))
+    }
+
+    fn parse_internal<R: FileResolver>(
+        &self,
+        src_file: &Path,
+        resolver: &mut R,
+    ) -> Result<InliningResult, Error> {
+        let mut log = Log::default();
+        let result = Visitor::<R>::new(src_file, self.root, self, &mut log, resolver).visit()?;
+        Ok(InliningResult::new(result, log, self.path_encoding))
+    }
+}
+
+/// The path reported for source passed to `InlinerBuilder::inline_modules_from_str`.
+const MEMORY_SOURCE: &str = "<memory>";
+
+/// The non-`mod.rs` file whose modules are found in `dir`, such as `src/foo.rs` for `src/foo`,
+/// or `None` if `dir` has no name even once made absolute.
+fn file_named_after(dir: &Path) -> Option<PathBuf> {
+    let dir = match dir.file_name() {
+        Some(_) => dir.to_path_buf(),
+        None => normalize(&std::path::absolute(dir).ok()?),
+    };
+    dir.file_name()?;
+    let mut file = dir.into_os_string();
+    file.push(".rs");
+    Some(file.into())
+}
+
+/// Add every `.rs` file in `dir` and its subdirectories, as listed by `resolver`, to `files`.
+///
+/// `visited` holds the canonical paths of the directories already searched, so symbolic links
+/// that lead back to one of them aren't followed forever.
+fn find_rs_files<R: FileResolver>(
+    resolver: &R,
+    dir: &Path,
+    visited: &mut HashSet<PathBuf>,
+    files: &mut Vec<PathBuf>,
+) -> io::Result<()> {
+    if !visited.insert(resolver.canonicalize(dir)) {
+        return Ok(());
+    }
+
+    for path in resolver.read_dir(dir)? {
+        match resolver.read_dir(&path) {
+            Ok(_) => find_rs_files(resolver, &path, visited, files)?,
+            Err(_) if path.extension().is_some_and(|ext| ext == "rs") => files.push(path),
+            Err(_) => {}
+        }
+    }
+
+    Ok(())
+}
+
+/// An error that was encountered while reading, parsing or inlining a module.
+///
+/// Errors block further progress on inlining, but do not invalidate other progress.
+/// Therefore, only an error on the initially-passed-in-file is fatal to inlining.
+#[derive(Debug)]
+pub enum Error {
+    /// An error happened while opening or reading the file.
+    Io(io::Error),
+
+    /// Errors happened while using `syn` to parse the file.
+    Parse(syn::Error),
+
+    /// A `#[path]` attribute was relative to the workspace root, but no workspace root was
+    /// configured.
+    MissingWorkspaceRoot,
+
+    /// A module's `#[path]` attribute pointed at the file that declares the module.
+    SelfInclude,
+
+    /// A module's file is already being inlined further up the module tree, such as when two
+    /// files use `#[path]` attributes that point at each other. Contains the files that form
+    /// the cycle, starting and ending with the repeated file.
+    Cycle(Vec<PathBuf>),
+
+    /// The module requested from `InlinerBuilder::resolve_module` is not declared.
+    ModuleNotFound,
+
+    /// Inlining the module would exceed `InlinerBuilder::max_output_items`.
+    OutputLimit,
+
+    /// The module is nested more deeply than `InlinerBuilder::max_depth`.
+    DepthLimitExceeded,
+
+    /// The file is larger than `InlinerBuilder::max_file_bytes`, so it wasn't read.
+    FileTooLarge {
+        /// The size of the file, in bytes.
+        size: u64,
+        /// The largest size allowed, in bytes.
+        max: u64,
+    },
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::Io(err) => Some(err),
+            Error::Parse(err) => Some(err),
+            Error::MissingWorkspaceRoot
+            | Error::SelfInclude
+            | Error::Cycle(_)
+            | Error::ModuleNotFound
+            | Error::OutputLimit
+            | Error::DepthLimitExceeded
+            | Error::FileTooLarge { .. } => None,
+        }
+    }
+}
+
+impl Clone for Error {
+    /// Clone the error. `io::Error` isn't `Clone`, so an `Io` error is copied as a new error
+    /// with the same kind and message, without the original's source or OS error code.
+    fn clone(&self) -> Self {
+        match self {
+            Error::Io(err) => Error::Io(io::Error::new(err.kind(), err.to_string())),
+            Error::Parse(err) => Error::Parse(err.clone()),
+            Error::MissingWorkspaceRoot => Error::MissingWorkspaceRoot,
+            Error::SelfInclude => Error::SelfInclude,
+            Error::Cycle(files) => Error::Cycle(files.clone()),
+            Error::ModuleNotFound => Error::ModuleNotFound,
+            Error::OutputLimit => Error::OutputLimit,
+            Error::DepthLimitExceeded => Error::DepthLimitExceeded,
+            Error::FileTooLarge { size, max } => Error::FileTooLarge {
+                size: *size,
+                max: *max,
+            },
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+impl From<syn::Error> for Error {
+    fn from(err: syn::Error) -> Self {
+        Error::Parse(err)
+    }
+}
+
+impl Error {
+    /// Check if `other` is the same variant as this error, and for `Error::Io`, has the same
+    /// `io::ErrorKind`.
+    ///
+    /// The underlying I/O and parse errors can't be compared, so this is a coarse comparison
+    /// meant for checking which error happened, such as in tests.
+    pub fn same_kind(&self, other: &Error) -> bool {
+        match (self, other) {
+            (Error::Io(a), Error::Io(b)) => a.kind() == b.kind(),
+            _ => std::mem::discriminant(self) == std::mem::discriminant(other),
+        }
+    }
+
+    /// Describe the error, including the message of the underlying I/O or parse error.
+    pub(crate) fn detail(&self) -> String {
+        match self {
+            Error::Io(err) => err.to_string(),
+            Error::Parse(err) => format!("parse error: {}", err),
+            other => other.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io(_) => write!(f, "IO error"),
+            Error::Parse(_) => write!(f, "parse error"),
+            Error::MissingWorkspaceRoot => write!(f, "no workspace root configured"),
+            Error::SelfInclude => write!(f, "module includes its own file"),
+            Error::Cycle(chain) => {
+                write!(f, "module includes a file that is already being inlined: ")?;
+                for (i, path) in chain.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, " -> ")?;
+                    }
+                    write!(f, "{}", path.display())?;
+                }
+                Ok(())
+            }
+            Error::ModuleNotFound => write!(f, "module not found"),
+            Error::OutputLimit => write!(f, "output item limit reached"),
+            Error::DepthLimitExceeded => write!(f, "module depth limit reached"),
+            Error::FileTooLarge { size, max } => {
+                write!(f, "file is {} bytes, more than the limit of {}", size, max)
+            }
+        }
+    }
+}
+
+/// The result of a best-effort attempt at inlining.
+///
+/// This struct guarantees that the origin file was readable and valid Rust source code, but
+/// `errors` must be inspected to check if everything was inlined successfully.
+#[derive(Clone)]
+pub struct InliningResult {
+    output: syn::File,
+    errors: Vec<InlineError>,
+    warnings: Vec<InlineWarning>,
+    modules: ModuleGraph,
+    inlined_paths: Vec<PathBuf>,
+    macro_invocations: HashMap<PathBuf, Vec<Span>>,
+    path_encoding: PathEncoding,
+    sources: HashMap<PathBuf, String>,
+}
+
+impl InliningResult {
+    /// Create a new `InliningResult` with the best-effort output and the information
+    /// gathered during the inlining process.
+    pub(crate) fn new(output: syn::File, mut log: Log, path_encoding: PathEncoding) -> Self {
+        log.modules.output = Some(Snapshot(Rc::new(output.clone())));
+        let inlined_paths = std::iter::once(log.modules.root_file())
+            .chain(log.modules.modules().iter().filter_map(ModuleNode::file))
+            .map(Path::to_path_buf)
+            .collect();
+        InliningResult {
+            output,
+            errors: log.errors,
+            warnings: log.warnings,
+            modules: log.modules,
+            inlined_paths,
+            macro_invocations: log.macro_invocations,
+            path_encoding,
+            sources: log.sources,
+        }
+    }
+
+    /// The best-effort result of inlining.
+    pub fn output(&self) -> &syn::File {
+        &self.output
+    }
+
+    /// The errors that kept the inlining from completing. May be empty if there were no errors.
+    pub fn errors(&self) -> &[InlineError] {
+        &self.errors
+    }
+
+    /// The problems found while inlining that may make the output incomplete. Always empty
+    /// unless `InlinerBuilder::collect_warnings` or `InlinerBuilder::tolerant_parse` is
+    /// enabled.
+    pub fn warnings(&self) -> &[InlineWarning] {
+        &self.warnings
+    }
+
+    /// The modules encountered while inlining, including inline modules and modules that
+    /// could not be inlined.
+    pub fn module_graph(&self) -> &ModuleGraph {
+        &self.modules
+    }
+
+    /// Every file whose content is in the output, starting with the root file, followed by
+    /// the file of each module that was inlined, in the order the modules were visited.
+    ///
+    /// Files that couldn't be inlined are left out; they are listed in `errors`. This is the
+    /// set of files to watch for changes to the output, except for files expanded by
+    /// `InlinerBuilder::expand_includes`.
+    pub fn inlined_paths(&self) -> &[PathBuf] {
+        &self.inlined_paths
+    }
+
+    /// The span of every macro invocation found while inlining, in item, statement,
+    /// expression or any other position, by the file it appears in.
+    ///
+    /// The inliner can't see modules declared by macros, so these are the places where the
+    /// output may be incomplete. Invocations in modules removed from the output, such as by
+    /// `InlinerBuilder::drop_test_modules`, aren't included, and neither are `macro_rules!`
+    /// definitions.
+    pub fn macro_invocations(&self) -> &HashMap<PathBuf, Vec<Span>> {
+        &self.macro_invocations
+    }
+
+    /// The module graph in Graphviz DOT format, for visualizing which modules were inlined.
+    ///
+    /// Each node is labeled with the module's logical path and, for modules loaded from a
+    /// file, the file's path. Modules loaded from files are drawn as boxes, inline modules as
+    /// ellipses, and modules that couldn't be inlined as dashed red boxes. Each module has an
+    /// edge to every module declared inside it.
+    pub fn to_dot(&self) -> String {
+        self.modules.to_dot()
+    }
+
+    /// Information about each file that was loaded, such as its line count and, if
+    /// `InlinerBuilder::time` was enabled, how long it took to load.
+    pub fn stats(&self) -> InlineStats {
+        InlineStats::new(&self.modules)
+    }
+
+    /// Every module that was inlined from a file, as its logical path (e.g. `foo::bar`) and
+    /// the file it was loaded from, in the order the modules were visited.
+    pub fn module_index(&self) -> Vec<(String, PathBuf)> {
+        self.modules
+            .modules()
+            .iter()
+            .filter_map(|module| {
+                let file = module.file()?;
+                Some((module.module_path().join("::"), file.to_path_buf()))
+            })
+            .collect()
+    }
+
+    /// Whether the root file was treated like a `mod.rs` file, so the modules declared in it
+    /// were resolved from its directory rather than from a directory named after it.
+    ///
+    /// This is always `true` when `InlinerBuilder::root` is enabled, and otherwise `true` only
+    /// if the root file is named `mod.rs`.
+    pub fn root_is_mod_file(&self) -> bool {
+        self.modules.root_is_mod_file
+    }
+
+    /// The original source of the file at `path`, if `InlinerBuilder::retain_sources` was
+    /// enabled and the file was loaded while inlining.
+    ///
+    /// `path` is the path the file was loaded from, made absolute if
+    /// `InlinerBuilder::absolute_paths` is enabled.
+    pub fn source_of(&self, path: &Path) -> Option<&str> {
+        self.sources.get(path).map(String::as_str)
+    }
+
+    /// How `InlinerBuilder::annotate_paths` stored paths that aren't valid UTF-8 in the output.
+    pub fn path_encoding(&self) -> PathEncoding {
+        self.path_encoding
+    }
+
+    /// The paths recorded on every inlined module when `InlinerBuilder::annotate_paths` is
+    /// enabled. Empty if paths weren't annotated.
+    pub fn annotated_paths(&self) -> HashSet<PathBuf> {
+        struct Collector(HashSet<PathBuf>, PathEncoding);
+
+        impl<'ast> syn::visit::Visit<'ast> for Collector {
+            fn visit_item_mod(&mut self, i: &'ast ItemMod) {
+                if let Some(path) = find_mod_path_with_encoding(&i.attrs, self.1) {
+                    self.0.insert(path.into_path());
+                }
+                syn::visit::visit_item_mod(self, i);
+            }
+        }
+
+        let mut collector = Collector(HashSet::new(), self.path_encoding);
+        syn::visit::Visit::visit_file(&mut collector, &self.output);
+        collector.0
+    }
+
+    /// The attributes of the `mod` item for the module at `module_path` in the output, such as
+    /// `&["foo", "bar"]` for `crate::foo::bar`, including any added while inlining.
+    ///
+    /// Returns `None` if the output has no such module, or an empty slice if `module_path` is
+    /// empty, as the crate root has no `mod` item. If a module is declared more than once,
+    /// such as under different `#[cfg]`s, the first declaration is used.
+    pub fn module_attrs(&self, module_path: &[&str]) -> Option<&[syn::Attribute]> {
+        let Some((last, parents)) = module_path.split_last() else {
+            return Some(&[]);
+        };
+
+        fn find<'a>(items: &'a [syn::Item], name: &str) -> Option<&'a ItemMod> {
+            items.iter().find_map(|item| match item {
+                syn::Item::Mod(item) if item.ident == name => Some(item),
+                _ => None,
+            })
+        }
+
+        let mut items = &self.output.items[..];
+        for segment in parents {
+            items = &find(items, segment)?.content.as_ref()?.1;
+        }
+        Some(&find(items, last)?.attrs)
+    }
+
+    /// Every `use` declaration in the output, including private ones and those inside function
+    /// bodies, with the logical path of the module it appears in, in source order.
+    pub fn use_declarations(&self) -> Vec<(Vec<String>, syn::ItemUse)> {
+        #[derive(Default)]
+        struct Collector {
+            module_path: Vec<String>,
+            uses: Vec<(Vec<String>, syn::ItemUse)>,
+        }
+
+        impl<'ast> syn::visit::Visit<'ast> for Collector {
+            fn visit_item_mod(&mut self, i: &'ast ItemMod) {
+                self.module_path.push(i.ident.to_string());
+                syn::visit::visit_item_mod(self, i);
+                self.module_path.pop();
+            }
+
+            fn visit_item_use(&mut self, i: &'ast syn::ItemUse) {
+                self.uses.push((self.module_path.clone(), i.clone()));
+            }
+        }
+
+        let mut collector = Collector::default();
+        syn::visit::Visit::visit_file(&mut collector, &self.output);
+        collector.uses
+    }
+
+    /// A hash of the output and the files it was inlined from that only changes when the
+    /// inlined crate does, for use as a build cache key.
+    ///
+    /// The hash covers:
+    ///
+    /// - the output's tokens as printed by `TokenStream`'s `Display`, so spans, comments and
+    ///   formatting of the original files don't affect it, but attributes added by options
+    ///   such as `InlinerBuilder::annotate_paths` do;
+    /// - the sorted paths of the root file and every file inlined into it, relative to the
+    ///   root file's directory, with `.` and `..` segments resolved and `/` between segments.
+    ///
+    /// It's computed with 64-bit FNV-1a, so the same crate gives the same hash on every
+    /// machine, as long as the same version of `proc-macro2` prints the tokens.
+    pub fn canonical_hash(&self) -> u64 {
+        canonical::canonical_hash(&self.output, &self.modules)
+    }
+
+    /// The span of every item declared directly in a module of the output, including the
+    /// root, with the file the item came from and the item's byte offset within that file.
+    ///
+    /// Items are listed in source order. A position in the output can be mapped back to its
+    /// file by finding the innermost item whose span contains it.
+    ///
+    /// Items spliced in by `InlinerBuilder::expand_includes` are listed with the file they were
+    /// included from.
+    pub fn offset_map(&self) -> Vec<(Span, PathBuf, usize)> {
+        source_map::offset_map(&self.output, &self.modules)
+    }
+
+    /// The file and module path of every item declared directly in a module of the output,
+    /// looked up by the item's index or span.
+    pub fn provenance_map(&self) -> ProvenanceMap {
+        source_map::provenance_map(&self.output, &self.modules)
+    }
+
+    /// Translate positions in the items of the output back to positions in the files they
+    /// were written in, built from the same item origins as `provenance_map`.
+    pub fn source_map(&self) -> SourceMap {
+        source_map::source_map(&self.output, &self.modules)
+    }
+
+    /// The file, module path and position of every item declared directly in a module of the
+    /// output, as data that can be saved alongside the output and still used after the
+    /// output has been printed and parsed again, when its spans no longer point to the
+    /// original files.
+    pub fn span_table(&self) -> SpanTable {
+        source_map::span_table(&self.output, &self.modules)
+    }
+
+    /// Check that each module inlined from a file still matches that file, loading each file
+    /// again with `resolver`.
+    ///
+    /// A module matches its file if the file's inner attributes are the last attributes on
+    /// the module and the module's items are the same as the file's items, ignoring the
+    /// content of modules that were themselves inlined. Items added by the inliner, from
+    /// `InlinerBuilder::expand_includes` or `InlinerBuilder::extra_modules`, are not compared.
+    pub fn verify<R: FileResolver>(&self, resolver: &mut R) -> Result<(), Vec<VerificationError>> {
+        verify::verify(self, resolver)
+    }
+
+    /// Check that each module inlined from a file still matches that file, loading each file
+    /// from disk again. See `verify` for how modules are compared.
+    pub fn verify_on_disk(&self) -> Result<(), Vec<VerificationError>> {
+        self.verify(&mut FsResolver::new(|_: &Path, _| {}))
+    }
+
+    /// Write the output to the file at `out`, replacing the file if it exists.
+    ///
+    /// With the `pretty` feature, the output is formatted by `prettyplease`. Otherwise,
+    /// the tokens of the output are written on a single line.
+    pub fn write_to(&self, out: &Path) -> io::Result<()> {
+        std::fs::write(out, self.source())
+    }
+
+    /// Print the output as formatted source code with `prettyplease`, which `syn::parse_file`
+    /// parses back into a file with the same tokens as the output.
+    ///
+    /// Requires the `pretty` feature, which enables the `prettyplease` dependency.
+    #[cfg(feature = "pretty")]
+    pub fn to_pretty_string(&self) -> String {
+        prettyplease::unparse(&self.output)
+    }
+
+    /// Print the output as formatted source code.
+    #[cfg(feature = "pretty")]
+    fn source(&self) -> String {
+        self.to_pretty_string()
+    }
+
+    /// Print the output as unformatted source code.
+    #[cfg(not(feature = "pretty"))]
+    fn source(&self) -> String {
+        quote::ToTokens::to_token_stream(&self.output).to_string()
+    }
+
+    /// Whether the result has any errors. `false` implies that all inlining operations completed
+    /// successfully.
+    pub fn has_errors(&self) -> bool {
+        !self.errors.is_empty()
+    }
+
+    /// Break an incomplete inlining into the best-effort parsed result and the errors encountered.
+    ///
+    /// # Usage
+    ///
+    /// ```rust,ignore
+    /// # #![allow(unused_variables)]
+    /// # use std::path::Path;
+    /// # use syn_inline_mod::InlinerBuilder;
+    /// let result = InlinerBuilder::default().parse_and_inline_modules(Path::new("foo.rs"));
+    /// match result {
+    ///     Err(e) => unimplemented!(),
+    ///     Ok(r) if r.has_errors() => {
+    ///         let (best_effort, errors) = r.into_output_and_errors();
+    ///         // do things with the partial output and the errors
+    ///     },
+    ///     Ok(r) => {
+    ///         let (complete, _) = r.into_output_and_errors();
+    ///         // do things with the completed output
+    ///     }
+    /// }
+    /// ```
+    pub fn into_output_and_errors(self) -> (syn::File, Vec<InlineError>) {
+        (self.output, self.errors)
+    }
+}
+
+impl fmt::Debug for InliningResult {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.errors.fmt(f)
+    }
+}
+
+impl fmt::Display for InliningResult {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Inlining partially completed before errors:")?;
+        for error in &self.errors {
+            writeln!(f, " * {}", error)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// An error that happened while attempting to inline a module.
+#[derive(Debug, Clone)]
+pub struct InlineError {
+    src_path: PathBuf,
+    module_name: String,
+    module_path: Vec<String>,
+    src_span: Span,
+    path: PathBuf,
+    kind: Error,
+    suggestion: Option<PathBuf>,
+}
+
+impl InlineError {
+    pub(crate) fn new(
+        src_path: impl Into<PathBuf>,
+        item_mod: &ItemMod,
+        module_path: &[String],
+        path: impl Into<PathBuf>,
+        kind: Error,
+    ) -> Self {
+        Self {
+            src_path: src_path.into(),
+            module_name: item_mod.ident.to_string(),
+            module_path: module_path.to_vec(),
+            src_span: item_mod.span(),
+            path: path.into(),
+            kind,
+            suggestion: None,
+        }
+    }
+
+    pub(crate) fn with_suggestion(mut self, suggestion: Option<PathBuf>) -> Self {
+        self.suggestion = suggestion;
+        self
+    }
+
+    /// Returns the source path where the error originated.
+    ///
+    /// The file at this path parsed correctly, but it caused the file at `self.path()` to be read.
+    pub fn src_path(&self) -> &Path {
+        &self.src_path
+    }
+
+    /// Returns the name of the module that was attempted to be inlined.
+    pub fn module_name(&self) -> &str {
+        &self.module_name
+    }
+
+    /// Returns the logical path of the module that was attempted to be inlined, relative to
+    /// the crate root, e.g. `["foo", "bar"]` for `crate::foo::bar`.
+    pub fn module_path(&self) -> &[String] {
+        &self.module_path
+    }
+
+    /// Returns the `Span` (including line and column information) in the source path that caused
+    /// `self.path()` to be included.
+    pub fn src_span(&self) -> proc_macro2::Span {
+        self.src_span
+    }
+
+    /// Returns the path where the error happened.
+    ///
+    /// Reading and parsing this file failed for the reason listed in `self.kind()`.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns the reason for this error happening.
+    pub fn kind(&self) -> &Error {
+        &self.kind
+    }
+
+    /// Returns an existing file with the same stem as the missing file, if one was found.
+    ///
+    /// This is only populated when `InlinerBuilder::suggest_on_missing` is enabled and the file
+    /// at `self.path()` was not found.
+    pub fn suggestion(&self) -> Option<&Path> {
+        self.suggestion.as_deref()
+    }
+}
+
+impl fmt::Display for InlineError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let start = self.src_span.start();
+        write!(
+            f,
+            "{}:{}:{}: error while including module `{}` from {}: {}",
+            self.src_path.display(),
+            start.line,
+            start.column,
+            self.module_path.join("::"),
+            self.path.display(),
+            self.kind
+        )?;
+
+        if let Some(suggestion) = &self.suggestion {
+            write!(f, " (did you mean {}?)", suggestion.display())?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use quote::{quote, ToTokens};
+
+    use super::*;
+
+    fn make_test_env() -> TestResolver {
+        let mut env = TestResolver::default();
+        env.register("src/lib.rs", "mod first;");
+        env.register("src/first/mod.rs", "mod second;");
+        env.register(
+            "src/first/second.rs",
+            r#"
+            #[doc = " Documentation"]
+            mod third {
+                mod fourth;
+            }
+
+            pub fn sample() -> usize { 4 }
+            "#,
+        );
+        env.register(
+            "src/first/second/third/fourth.rs",
+            "pub fn another_fn() -> bool { true }",
+        );
+        env
+    }
+
+    /// Run a full test, exercising the entirety of the functionality in this crate.
+    #[test]
+    fn happy_path() {
+        let result = InlinerBuilder::default()
+            .parse_internal(Path::new("src/lib.rs"), &mut make_test_env())
+            .unwrap()
+            .output;
+
+        assert_eq!(
+            result.into_token_stream().to_string(),
+            quote! {
+                mod first {
+                    mod second {
+                        #[doc = " Documentation"]
+                        mod third {
+                            mod fourth {
+                                pub fn another_fn() -> bool {
+                                    true
+                                }
+                            }
+                        }
+
+                        pub fn sample() -> usize {
+                            4
+                        }
+                    }
+                }
+            }
+            .to_string()
+        );
+    }
+
+    /// Test case involving missing and invalid modules
+    #[test]
+    fn missing_module() {
+        let mut env = TestResolver::default();
+        env.register("src/lib.rs", "mod missing;\nmod invalid;");
+        env.register("src/invalid.rs", "this-is-not-valid-rust!");
+
+        let result = InlinerBuilder::default().parse_internal(Path::new("src/lib.rs"), &mut env);
+
+        if let Ok(r) = result {
+            let errors = &r.errors;
+            assert_eq!(errors.len(), 2, "expected 2 errors");
+
+            let error = &errors[0];
+            assert_eq!(
+                error.src_path(),
+                Path::new("src/lib.rs"),
+                "correct source path"
+            );
+            assert_eq!(error.module_name(), "missing");
+            assert_eq!(error.src_span().start().line, 1);
+            assert_eq!(error.src_span().start().column, 0);
+            assert_eq!(error.src_span().end().line, 1);
+            assert_eq!(error.src_span().end().column, 12);
+            assert_eq!(error.path(), Path::new("src/missing/mod.rs"));
+            assert!(error
+                .kind()
+                .same_kind(&io::Error::from(io::ErrorKind::NotFound).into()));
+
+            let error = &errors[1];
+            assert_eq!(
+                error.src_path(),
+                Path::new("src/lib.rs"),
+                "correct source path"
+            );
+            assert_eq!(error.module_name(), "invalid");
+            assert_eq!(error.src_span().start().line, 2);
+            assert_eq!(error.src_span().start().column, 0);
+            assert_eq!(error.src_span().end().line, 2);
+            assert_eq!(error.src_span().end().column, 12);
+            assert_eq!(error.path(), Path::new("src/invalid.rs"));
+            match error.kind() {
+                Error::Parse(_) => {}
+                _ => panic!("expected ErrorKind::Parse, found {}", error.kind()),
+            }
+        } else {
+            unreachable!();
+        }
+    }
+
+    /// A `#[path]` on an inline module sets the directory for the modules declared inside it.
+    #[test]
+    fn path_on_inline_mod() {
+        let mut env = TestResolver::default();
+        env.register("src/lib.rs", r#"#[path = "x.rs"] mod foo { mod bar; }"#);
+        env.register("src/x.rs/bar.rs", "struct InDir;");
+        env.register("src/foo/bar.rs", "struct ByIdent;");
+
+        let result = InlinerBuilder::default()
+            .parse_internal(Path::new("src/lib.rs"), &mut env)
+            .unwrap();
+
+        assert!(!result.has_errors());
+        assert_eq!(
+            result.output.into_token_stream().to_string(),
+            quote! {
+                #[path = "x.rs"]
+                mod foo {
+                    mod bar {
+                        struct InDir;
+                    }
+                }
+            }
+            .to_string()
+        );
+    }
+
+    /// When configured to ignore `#[path]` on inline modules, nested modules resolve by ident.
+    #[test]
+    fn ignore_path_on_inline_mod() {
+        let mut env = TestResolver::default();
+        env.register("src/lib.rs", r#"#[path = "x.rs"] mod foo { mod bar; }"#);
+        env.register("src/foo/bar.rs", "struct ByIdent;");
+
+        let result = InlinerBuilder::default()
+            .ignore_path_on_inline_mods(true)
+            .parse_internal(Path::new("src/lib.rs"), &mut env)
+            .unwrap();
+
+        assert!(!result.has_errors());
+        assert_eq!(
+            result.output.into_token_stream().to_string(),
+            quote! {
+                #[path = "x.rs"]
+                mod foo {
+                    mod bar {
+                        struct ByIdent;
+                    }
+                }
+            }
+            .to_string()
+        );
+    }
+
+    /// When both candidate files exist, the first is used unless `mod.rs` is preferred or a
+    /// selector picks another.
+    #[test]
+    fn candidate_selector() {
+        let mut env = TestResolver::default();
+        env.register("src/lib.rs", "mod first;");
+        env.register("src/first.rs", "struct Named;");
+        env.register("src/first/mod.rs", "struct ModRs;");
+
+        let result = InlinerBuilder::default()
+            .parse_internal(Path::new("src/lib.rs"), &mut env)
+            .unwrap();
+        assert_eq!(
+            result.output.into_token_stream().to_string(),
+            quote! { mod first { struct Named; } }.to_string()
+        );
+
+        let result = InlinerBuilder::default()
+            .prefer_mod_rs(true)
+            .parse_internal(Path::new("src/lib.rs"), &mut env)
+            .unwrap();
+        assert_eq!(
+            result.output.into_token_stream().to_string(),
+            quote! { mod first { struct ModRs; } }.to_string()
+        );
+
+        let result = InlinerBuilder::default()
+            .candidate_selector(|candidates| {
+                candidates
+                    .iter()
+                    .find(|path| path.ends_with("mod.rs"))
+                    .cloned()
+            })
+            .parse_internal(Path::new("src/lib.rs"), &mut env)
+            .unwrap();
+        assert_eq!(
+            result.output.into_token_stream().to_string(),
+            quote! { mod first { struct ModRs; } }.to_string()
+        );
+    }
+
+    #[test]
+    fn annotate_module_paths() {
+        let result = InlinerBuilder::default()
+            .annotate_paths(true)
+            .annotate_module_paths(true)
+            .parse_internal(Path::new("src/lib.rs"), &mut make_test_env())
+            .unwrap();
+
+        let first = match &result.output().items[0] {
+            syn::Item::Mod(first) => first,
+            _ => unreachable!(),
+        };
+        let second = match &first.content.as_ref().unwrap().1[0] {
+            syn::Item::Mod(second) => second,
+            _ => unreachable!(),
+        };
+
+        assert_eq!(
+            second.attrs[1].to_token_stream().to_string(),
+            quote!(#[syn_inline_mod_module_path("first::second")]).to_string()
+        );
+        assert_eq!(
+            find_module_path(&second.attrs),
+            Some(vec!["first".to_string(), "second".to_string()])
+        );
+        assert_eq!(
+            find_mod_path(&second.attrs).unwrap().path(),
+            Path::new("src/first/second.rs")
+        );
+    }
+
+    #[test]
+    fn module_index() {
+        let result = InlinerBuilder::default()
+            .parse_internal(Path::new("src/lib.rs"), &mut make_test_env())
+            .unwrap();
+
+        assert_eq!(
+            result.module_index(),
+            vec![
+                ("first".to_string(), PathBuf::from("src/first/mod.rs")),
+                (
+                    "first::second".to_string(),
+                    PathBuf::from("src/first/second.rs")
+                ),
+                (
+                    "first::second::third::fourth".to_string(),
+                    PathBuf::from("src/first/second/third/fourth.rs")
+                ),
+            ]
+        );
+    }
+
+    /// Annotated paths are placed between the `mod` item's attributes and the file's.
+    #[test]
+    fn annotate_paths() {
+        let mut env = make_test_env();
+        env.register(
+            "src/first/second/third/fourth.rs",
+            "#![allow(dead_code)] pub fn another_fn() -> bool { true }",
+        );
+        let result = InlinerBuilder::default()
+            .annotate_paths(true)
+            .parse_internal(Path::new("src/lib.rs"), &mut env)
+            .unwrap();
+
+        assert_eq!(
+            result.output().to_token_stream().to_string(),
+            quote! {
+                #[syn_inline_mod_path("src/first/mod.rs")]
+                mod first {
+                    #[syn_inline_mod_path("src/first/second.rs")]
+                    mod second {
+                        #[doc = " Documentation"]
+                        mod third {
+                            #[syn_inline_mod_path("src/first/second/third/fourth.rs")]
+                            mod fourth {
+                                #![allow(dead_code)]
+                                pub fn another_fn() -> bool {
+                                    true
+                                }
+                            }
+                        }
+
+                        pub fn sample() -> usize {
+                            4
+                        }
+                    }
+                }
+            }
+            .to_string()
+        );
+
+        assert_eq!(
+            result.annotated_paths(),
+            [
+                "src/first/mod.rs",
+                "src/first/second.rs",
+                "src/first/second/third/fourth.rs"
+            ]
+            .iter()
+            .map(PathBuf::from)
+            .collect()
+        );
+    }
+
+    /// Inlined modules match the files they were loaded from, and changes are detected.
+    #[test]
+    fn verify() {
+        let mut env = make_test_env();
+        let mut result = InlinerBuilder::default()
+            .parse_internal(Path::new("src/lib.rs"), &mut env)
+            .unwrap();
+
+        result.verify(&mut env).unwrap();
+
+        env.register("src/first/second.rs", "pub fn sample() -> usize { 5 }");
+        let errors = result.verify(&mut env).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].module_path(), ["first", "second"]);
+        assert!(matches!(
+            errors[0].kind(),
+            VerificationErrorKind::ItemCount {
+                expected: 1,
+                found: 2
+            }
+        ));
+
+        env = make_test_env();
+        result.output.items.push(syn::parse_quote!(
+            struct Extra;
+        ));
+        result.verify(&mut env).unwrap();
+        if let syn::Item::Mod(first) = &mut result.output.items[0] {
+            first.content.as_mut().unwrap().1.push(syn::parse_quote!(
+                struct Extra;
+            ));
+        }
+        let errors = result.verify(&mut env).unwrap_err();
+        assert_eq!(errors[0].module_path(), ["first"]);
+    }
+
+    /// Items added by `expand_includes` and `extra_modules` aren't reported as changes.
+    #[test]
+    fn verify_added_items() {
+        let mut env = TestResolver::default();
+        env.register(
+            "src/lib.rs",
+            "mod a; mod b { include!(\"gen.rs\"); } mod c;",
+        );
+        env.register("src/a.rs", "struct A; include!(\"gen.rs\");");
+        env.register("src/gen.rs", "struct Generated; mod generated;");
+        env.register("src/a/generated.rs", "struct InA;");
+        env.register("src/b/generated.rs", "struct InB;");
+        env.register("src/c.rs", "struct C;");
+        env.register("src/extra.rs", "struct Extra;");
+        env.register("src/c/nested.rs", "struct Nested;");
+
+        let result = InlinerBuilder::default()
+            .expand_includes(true)
+            .extra_modules(vec![
+                (vec!["extra".into()], "src/extra.rs".into()),
+                (vec!["c".into(), "nested".into()], "src/c/nested.rs".into()),
+            ])
+            .parse_internal(Path::new("src/lib.rs"), &mut env)
+            .unwrap();
+        assert!(!result.has_errors());
+        result.verify(&mut env).unwrap();
+
+        env.register("src/c.rs", "struct Changed;");
+        let errors = result.verify(&mut env).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].module_path(), ["c"]);
+    }
+
+    /// Modules removed by `transform_child` are reported as missing without affecting how
+    /// the other modules are matched.
+    #[test]
+    fn verify_transformed() {
+        let mut env = TestResolver::default();
+        env.register("src/lib.rs", "mod a; mod b;");
+        env.register("src/a.rs", "mod removed; struct A;");
+        env.register("src/a/removed.rs", "struct Removed;");
+        env.register("src/b.rs", "mod kept;");
+        env.register("src/b/kept.rs", "struct Kept;");
+
+        let result = InlinerBuilder::default()
+            .transform_child(|path, items| {
+                if path == Path::new("src/a.rs") {
+                    items.remove(0);
+                }
+            })
+            .parse_internal(Path::new("src/lib.rs"), &mut env)
+            .unwrap();
+
+        let errors = result
+            .verify(&mut env)
+            .unwrap_err()
+            .into_iter()
+            .map(|error| (error.module_path().join("::"), error.kind().to_string()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            errors,
+            vec![
+                ("a".to_string(), "expected 2 items, found 1".to_string()),
+                (
+                    "a::removed".to_string(),
+                    "module missing from output".to_string()
+                ),
+            ]
+        );
+    }
+
+    /// Inline and file-backed modules keep their source order, which is recorded in the
+    /// module graph.
+    #[test]
+    fn mixed_module_order() {
+        let mut env = TestResolver::default();
+        env.register(
+            "src/lib.rs",
+            "mod a { mod inner; } mod b; mod c { struct C; }",
+        );
+        env.register("src/a/inner.rs", "struct Inner;");
+        env.register("src/b.rs", "struct B;");
+
+        let result = InlinerBuilder::default()
+            .parse_internal(Path::new("src/lib.rs"), &mut env)
+            .unwrap();
+
+        assert!(!result.has_errors());
+        assert_eq!(
+            result.output().to_token_stream().to_string(),
+            quote! {
+                mod a {
+                    mod inner {
+                        struct Inner;
+                    }
+                }
+                mod b {
+                    struct B;
+                }
+                mod c {
+                    struct C;
+                }
+            }
+            .to_string()
+        );
+
+        let modules = result.module_graph().modules();
+        let summary = modules
+            .iter()
+            .map(|m| {
+                (
+                    m.module_path().join("::"),
+                    m.parent(),
+                    m.declaration_index(),
+                    m.is_inline(),
+                    m.file().map(Path::to_path_buf),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            summary,
+            vec![
+                ("a".to_string(), None, 0, true, None),
+                (
+                    "a::inner".to_string(),
+                    Some(0),
+                    0,
+                    false,
+                    Some(PathBuf::from("src/a/inner.rs"))
+                ),
+                (
+                    "b".to_string(),
+                    None,
+                    1,
+                    false,
+                    Some(PathBuf::from("src/b.rs"))
+                ),
+                ("c".to_string(), None, 2, true, None),
+            ]
+        );
+    }
+
+    /// The candidate callback sees each candidate that was checked.
+    #[test]
+    fn on_candidate() {
+        let mut env = TestResolver::default();
+        env.register("src/lib.rs", "mod named; mod dir; mod missing;");
+        env.register("src/named.rs", "");
+        env.register("src/dir/mod.rs", "");
+
+        let checked = Arc::new(Mutex::new(vec![]));
+        let log = checked.clone();
+        InlinerBuilder::default()
+            .on_candidate(move |path, exists| {
+                log.lock().unwrap().push((path.to_path_buf(), exists));
+            })
+            .parse_internal(Path::new("src/lib.rs"), &mut env)
+            .unwrap();
+
+        assert_eq!(
+            *checked.lock().unwrap(),
+            vec![
+                (PathBuf::from("src/named.rs"), true),
+                (PathBuf::from("src/dir.rs"), false),
+                (PathBuf::from("src/dir/mod.rs"), true),
+                (PathBuf::from("src/missing.rs"), false),
+                (PathBuf::from("src/missing/mod.rs"), false),
+            ]
+        );
+    }
+
+    /// A file loaded with `#[path]` is treated like a `mod.rs` file, so the modules it
+    /// declares are found next to it.
+    #[test]
+    fn path_then_ident() {
+        let mut env = TestResolver::default();
+        env.register("src/lib.rs", "mod outer;");
+        env.register("src/outer.rs", r#"#[path = "impls/middle.rs"] mod middle;"#);
+        env.register("src/impls/middle.rs", "mod leaf;");
+        env.register("src/impls/leaf.rs", "struct Leaf;");
+
+        let result = InlinerBuilder::default()
+            .parse_internal(Path::new("src/lib.rs"), &mut env)
+            .unwrap();
+
+        assert!(!result.has_errors(), "{}", result);
+        assert_eq!(
+            result.output.into_token_stream().to_string(),
+            quote! {
+                mod outer {
+                    #[path = "impls/middle.rs"]
+                    mod middle {
+                        mod leaf {
+                            struct Leaf;
+                        }
+                    }
+                }
+            }
+            .to_string()
+        );
+    }
+
+    /// A module pointing at its own file is reported instead of recursing forever.
+    #[test]
+    fn self_include() {
+        let mut env = TestResolver::default();
+        env.register(
+            "src/lib.rs",
+            r#"#[path = "lib.rs"] mod me; #[path = "./other/../lib.rs"] mod also_me;"#,
+        );
+
+        let result = InlinerBuilder::default()
+            .parse_internal(Path::new("src/lib.rs"), &mut env)
+            .unwrap();
+
+        assert_eq!(result.errors().len(), 2);
+        for error in result.errors() {
+            assert!(matches!(error.kind(), Error::SelfInclude));
+        }
+    }
+
+    #[test]
+    fn cfg_attr_path_pair() {
+        let mut env = TestResolver::default();
+        env.register(
+            "src/lib.rs",
+            r#"
+            #[cfg_attr(unix, path = "unix.rs")]
+            #[cfg_attr(windows, path = "windows.rs")]
+            mod sys;
+            "#,
+        );
+        env.register("src/unix.rs", "struct Unix;");
+        env.register("src/windows.rs", "struct Windows;");
+
+        let first = InlinerBuilder::default()
+            .parse_internal(Path::new("src/lib.rs"), &mut env)
+            .unwrap();
+        assert_eq!(
+            first.module_graph().modules()[0].file(),
+            Some(Path::new("src/unix.rs"))
+        );
+
+        let mut cfg = CfgOptions::new();
+        cfg.set("windows");
+        let active = InlinerBuilder::default()
+            .cfg_options(cfg)
+            .parse_internal(Path::new("src/lib.rs"), &mut env)
+            .unwrap();
+        assert_eq!(
+            active.module_graph().modules()[0].file(),
+            Some(Path::new("src/windows.rs"))
+        );
+    }
+
+    #[test]
+    fn line_counts() {
+        let mut env = make_test_env();
+        env.register("src/lib.rs", "mod first;\n\nfn main() {}\n");
+
+        let result = InlinerBuilder::default()
+            .parse_internal(Path::new("src/lib.rs"), &mut env)
+            .unwrap();
+
+        let graph = result.module_graph();
+        assert_eq!(graph.root_line_count(), Some(3));
+        assert_eq!(graph.modules()[0].line_count(), Some(1));
+        assert_eq!(graph.modules()[1].line_count(), Some(8));
+        assert_eq!(graph.modules()[2].line_count(), None);
+        assert_eq!(graph.modules()[3].line_count(), Some(1));
+    }
+
+    #[test]
+    fn transform_child() {
+        let mut env = make_test_env();
+        let seen = Arc::new(Mutex::new(vec![]));
+        let seen_by_transform = seen.clone();
+
+        let result = InlinerBuilder::default()
+            .transform_child(move |path, items| {
+                seen_by_transform.lock().unwrap().push(path.to_path_buf());
+                items.insert(
+                    0,
+                    syn::parse_quote!(
+                        use super::*;
+                    ),
+                );
+            })
+            .parse_internal(Path::new("src/lib.rs"), &mut env)
+            .unwrap();
+
+        // Children are transformed before their parents are spliced.
+        assert_eq!(
+            *seen.lock().unwrap(),
+            vec![
+                PathBuf::from("src/first/second/third/fourth.rs"),
+                PathBuf::from("src/first/second.rs"),
+                PathBuf::from("src/first/mod.rs"),
+            ]
+        );
+
+        let first = match &result.output().items[0] {
+            syn::Item::Mod(first) => first,
+            _ => panic!("expected a module"),
+        };
+        assert_eq!(
+            first.content.as_ref().unwrap().1[0],
+            syn::parse_quote!(
+                use super::*;
+            )
+        );
+    }
+
+    #[test]
+    fn search_roots() {
+        let mut env = TestResolver::default();
+        env.register("base/src/lib.rs", "mod common; mod extra;");
+        env.register("base/src/common.rs", "mod nested;");
+        env.register("overlay/src/common/nested.rs", "");
+        env.register("overlay/src/extra.rs", "");
+
+        let result = InlinerBuilder::default()
+            .search_roots(vec!["overlay", "base"])
+            .parse_internal(Path::new("base/src/lib.rs"), &mut env)
+            .unwrap();
+
+        assert!(!result.has_errors());
+        assert_eq!(
+            result
+                .module_graph()
+                .modules()
+                .iter()
+                .map(|m| m.file().unwrap())
+                .collect::<Vec<_>>(),
+            vec![
+                Path::new("base/src/common.rs"),
+                Path::new("overlay/src/common/nested.rs"),
+                Path::new("overlay/src/extra.rs"),
+            ]
+        );
+    }
+
+    #[test]
+    fn error_module_path() {
+        let mut env = make_test_env();
+        env.register("src/first/second/third/fourth.rs", "mod fifth;");
+
+        let result = InlinerBuilder::default()
+            .parse_internal(Path::new("src/lib.rs"), &mut env)
+            .unwrap();
+
+        let error = &result.errors()[0];
+        assert_eq!(
+            error.module_path(),
+            &["first", "second", "third", "fourth", "fifth"]
+        );
+        assert!(error
+            .to_string()
+            .contains("error while including module `first::second::third::fourth::fifth` from"));
+    }
+
+    #[test]
+    fn resolve_module() {
+        let mut env = make_test_env();
+        env.register("src/lib.rs", "mod broken; mod first;");
+        env.register(
+            "src/first/second/third/fourth.rs",
+            "#![allow(dead_code)] mod fifth { struct Fifth; }",
+        );
+
+        let fourth = InlinerBuilder::default()
+            .resolve_module_internal(
+                Path::new("src/lib.rs"),
+                &["first", "second", "third", "fourth"],
+                &mut env,
+            )
+            .unwrap();
+        assert_eq!(
+            fourth.into_token_stream().to_string(),
+            quote! {
+                #![allow(dead_code)]
+                mod fifth { struct Fifth; }
+            }
+            .to_string()
+        );
+
+        let fifth = InlinerBuilder::default()
+            .resolve_module_internal(
+                Path::new("src/lib.rs"),
+                &["first", "second", "third", "fourth", "fifth"],
+                &mut env,
+            )
+            .unwrap();
+        assert_eq!(fifth.items.len(), 1);
+
+        let missing = InlinerBuilder::default().resolve_module_internal(
+            Path::new("src/lib.rs"),
+            &["first", "nope"],
+            &mut env,
+        );
+        assert!(matches!(missing, Err(Error::ModuleNotFound)));
+
+        let broken = InlinerBuilder::default().resolve_module_internal(
+            Path::new("src/lib.rs"),
+            &["broken"],
+            &mut env,
+        );
+        assert!(matches!(broken, Err(Error::Io(_))));
+    }
+
+    #[test]
+    fn same_kind() {
+        let not_found = Error::from(io::Error::from(io::ErrorKind::NotFound));
+        assert!(not_found.same_kind(&io::Error::new(io::ErrorKind::NotFound, "other").into()));
+        assert!(!not_found.same_kind(&io::Error::from(io::ErrorKind::InvalidData).into()));
+        assert!(!not_found.same_kind(&Error::ModuleNotFound));
+        assert!(Error::FileTooLarge { size: 2, max: 1 }
+            .same_kind(&Error::FileTooLarge { size: 3, max: 1 }));
+    }
+
+    #[test]
+    fn cfg_doc() {
+        let mut env = TestResolver::default();
+        env.register(
+            "src/lib.rs",
+            "#[cfg(doc)] mod docs; #[cfg(not(doc))] mod imp; mod always;",
+        );
+        env.register("src/docs.rs", "");
+        env.register("src/imp.rs", "");
+        env.register("src/always.rs", "");
+
+        let modules = |cfg: CfgOptions| {
+            InlinerBuilder::default()
+                .cfg_options(cfg)
+                .parse_internal(Path::new("src/lib.rs"), &mut env.clone())
+                .unwrap()
+                .module_graph()
+                .modules()
+                .iter()
+                .map(|m| m.module_path().join("::"))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(modules(CfgOptions::for_rustdoc()), vec!["docs", "always"]);
+        assert_eq!(modules(CfgOptions::new()), vec!["imp", "always"]);
+    }
+
+    #[test]
+    fn collect_warnings() {
+        let mut env = make_test_env();
+        env.register(
+            "src/first/second/third/fourth.rs",
+            "macro_rules! declare { () => { mod hidden; } } declare!(); cfg_if::cfg_if! {}",
+        );
+
+        let result = InlinerBuilder::default()
+            .collect_warnings(true)
+            .parse_internal(Path::new("src/lib.rs"), &mut env)
+            .unwrap();
+
+        let kinds = result
+            .warnings()
+            .iter()
+            .map(|warning| warning.kind().clone())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            kinds,
+            vec![
+                WarningKind::ItemMacro("declare".into()),
+                WarningKind::ItemMacro("cfg_if::cfg_if".into()),
+            ]
+        );
+        assert_eq!(
+            result.warnings()[0].module_path(),
+            &["first", "second", "third", "fourth"]
+        );
+
+        let result = InlinerBuilder::default()
+            .parse_internal(Path::new("src/lib.rs"), &mut env)
+            .unwrap();
+        assert!(result.warnings().is_empty());
+    }
+
+    /// A module renamed by `#[path]` keeps its ident, and both annotations are retrievable.
+    #[test]
+    fn annotate_renamed_module() {
+        let mut env = TestResolver::default();
+        env.register("src/lib.rs", r#"#[path = "impl.rs"] mod thing;"#);
+        env.register("src/impl.rs", "struct Thing;");
+
+        let result = InlinerBuilder::default()
+            .annotate_paths(true)
+            .annotate_module_paths(true)
+            .parse_internal(Path::new("src/lib.rs"), &mut env)
+            .unwrap();
+
+        let item = match &result.output().items[0] {
+            syn::Item::Mod(item) => item,
+            _ => panic!("expected a module"),
+        };
+        assert_eq!(item.ident, "thing");
+
+        let annotation = find_mod_path(&item.attrs).unwrap();
+        assert_eq!(annotation.path(), Path::new("src/impl.rs"));
+        assert_eq!(annotation.module_path(), Some(&["thing".to_string()][..]));
+        assert_eq!(
+            result.module_index(),
+            vec![("thing".to_string(), PathBuf::from("src/impl.rs"))]
+        );
+    }
+
+    /// A non-root entry file resolves its modules from the directory named after it, as its
+    /// children do, unless it is a `mod.rs` file.
+    #[test]
+    fn non_root_entry() {
+        let mut env = TestResolver::default();
+        env.register("src/runner.rs", "mod threads;");
+        env.register("src/runner/threads.rs", "");
+        env.register("src/threads.rs", "");
+        env.register("src/pool/mod.rs", "mod threads;");
+        env.register("src/pool/threads.rs", "");
+
+        let file = |result: InliningResult| {
+            assert!(!result.has_errors());
+            result.module_graph().modules()[0]
+                .file()
+                .map(Path::to_path_buf)
+        };
+
+        let runner = InlinerBuilder::default()
+            .root(false)
+            .parse_internal(Path::new("src/runner.rs"), &mut env)
+            .unwrap();
+        assert!(!runner.root_is_mod_file());
+        assert_eq!(file(runner), Some("src/runner/threads.rs".into()));
+
+        let runner_as_root = InlinerBuilder::default()
+            .parse_internal(Path::new("src/runner.rs"), &mut env)
+            .unwrap();
+        assert!(runner_as_root.root_is_mod_file());
+        assert_eq!(file(runner_as_root), Some("src/threads.rs".into()));
+
+        let pool = InlinerBuilder::default()
+            .root(false)
+            .parse_internal(Path::new("src/pool/mod.rs"), &mut env)
+            .unwrap();
+        assert!(pool.root_is_mod_file());
+        assert_eq!(file(pool), Some("src/pool/threads.rs".into()));
+    }
+
+    #[test]
+    fn tolerant_parse() {
+        let mut env = make_test_env();
+        env.register(
+            "src/first/second/third/fourth.rs",
+            "pub fn valid() {}\nstruct Valid;\nfn broken( {\n",
+        );
+
+        let result = InlinerBuilder::default()
+            .tolerant_parse(true)
+            .parse_internal(Path::new("src/lib.rs"), &mut env)
+            .unwrap();
+
+        assert!(!result.has_errors());
+        assert_eq!(result.warnings().len(), 1);
+        assert_eq!(result.warnings()[0].kind(), &WarningKind::TruncatedParse(2));
+        assert_eq!(
+            result.warnings()[0].src_path(),
+            Path::new("src/first/second/third/fourth.rs")
+        );
+
+        let result = InlinerBuilder::default()
+            .parse_internal(Path::new("src/lib.rs"), &mut env)
+            .unwrap();
+        assert!(result.has_errors());
+    }
+
+    #[test]
+    fn time() {
+        let mut env = make_test_env();
+
+        let stats = InlinerBuilder::default()
+            .time(true)
+            .parse_internal(Path::new("src/lib.rs"), &mut env)
+            .unwrap()
+            .stats();
+
+        assert_eq!(stats.files().len(), 4);
+        assert_eq!(stats.files()[0].path(), Path::new("src/lib.rs"));
+        assert!(stats.files().iter().all(|file| file.duration().is_some()));
+        assert_eq!(stats.slowest(2).len(), 2);
+        assert!(stats.slowest(2)[0].duration() >= stats.slowest(2)[1].duration());
+
+        let stats = InlinerBuilder::default()
+            .parse_internal(Path::new("src/lib.rs"), &mut env)
+            .unwrap()
+            .stats();
+        assert!(stats.slowest(2).is_empty());
+        assert_eq!(stats.total_line_count(), 1 + 1 + 8 + 1);
+    }
+
+    #[test]
+    fn implicit_dir_modules() {
+        let mut env = TestResolver::with_dirs();
+        env.register("src/lib.rs", "mod generated; mod missing;");
+        env.register("src/generated/types.rs", "struct Generated;");
+
+        let result = InlinerBuilder::default()
+            .implicit_dir_modules(true)
+            .parse_internal(Path::new("src/lib.rs"), &mut env)
+            .unwrap();
+
+        assert_eq!(result.errors().len(), 1);
+        assert_eq!(result.errors()[0].module_name(), "missing");
+        assert_eq!(
+            result.output().to_token_stream().to_string(),
+            quote! {
+                mod generated {}
+                mod missing;
+            }
+            .to_string()
+        );
+
+        let result = InlinerBuilder::default()
+            .parse_internal(Path::new("src/lib.rs"), &mut env)
+            .unwrap();
+        assert_eq!(result.errors().len(), 2);
+    }
+
+    #[test]
+    fn offset_map() {
+        let mut env = TestResolver::default();
+        env.register("src/lib.rs", "mod a;\nfn main() { struct InBlock; }\n");
+        env.register(
+            "src/a.rs",
+            "// comment\nstruct A;\nmod inline { struct B; }\n",
+        );
+
+        let result = InlinerBuilder::default()
+            .parse_internal(Path::new("src/lib.rs"), &mut env)
+            .unwrap();
+
+        let offsets = result
+            .offset_map()
+            .into_iter()
+            .map(|(_, path, offset)| (path, offset))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            offsets,
+            vec![
+                (PathBuf::from("src/lib.rs"), 0),
+                (PathBuf::from("src/a.rs"), 11),
+                (PathBuf::from("src/a.rs"), 21),
+                (PathBuf::from("src/a.rs"), 34),
+                (PathBuf::from("src/lib.rs"), 7),
+            ]
+        );
+    }
+
+    #[test]
+    fn span_table() {
+        let mut env = TestResolver::default();
+        env.register("src/lib.rs", "mod a;\nfn main() { struct InBlock; }\n");
+        env.register(
+            "src/a.rs",
+            "// comment\nstruct A;\nmod inline {\n    struct B;\n}\n",
+        );
+
+        let result = InlinerBuilder::default()
+            .parse_internal(Path::new("src/lib.rs"), &mut env)
+            .unwrap();
+
+        let table = result.span_table();
+        let positions = table
+            .entries()
+            .iter()
+            .map(|entry| {
+                (
+                    entry.file().to_path_buf(),
+                    entry.module_path().join("::"),
+                    entry.line(),
+                    entry.column(),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            positions,
+            vec![
+                (PathBuf::from("src/lib.rs"), String::new(), 1, 0),
+                (PathBuf::from("src/a.rs"), "a".to_string(), 2, 0),
+                (PathBuf::from("src/a.rs"), "a".to_string(), 3, 0),
+                (PathBuf::from("src/a.rs"), "a::inline".to_string(), 4, 4),
+                (PathBuf::from("src/lib.rs"), String::new(), 2, 0),
+            ]
+        );
+        assert_eq!(table.for_file(Path::new("src/a.rs")).count(), 3);
+
+        let serialized = table.to_string();
+        assert_eq!(serialized.parse::<SpanTable>(), Ok(table));
+        assert_eq!(
+            "1:0\tmissing file".parse::<SpanTable>().unwrap_err().line(),
+            1
+        );
+    }
+
+    #[test]
+    fn provenance_map() {
+        let result = InlinerBuilder::default()
+            .parse_internal(Path::new("src/lib.rs"), &mut make_test_env())
+            .unwrap();
+        let provenance = result.provenance_map();
+
+        let origins = (0..provenance.len())
+            .map(|index| {
+                (
+                    provenance.file_of(index).unwrap().to_str().unwrap(),
+                    provenance.module_path_of(index).unwrap().join("::"),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            origins,
+            vec![
+                ("src/lib.rs", "".to_string()),
+                ("src/first/mod.rs", "first".to_string()),
+                ("src/first/second.rs", "first::second".to_string()),
+                ("src/first/second.rs", "first::second::third".to_string()),
+                (
+                    "src/first/second/third/fourth.rs",
+                    "first::second::third::fourth".to_string()
+                ),
+                ("src/first/second.rs", "first::second".to_string()),
+            ]
+        );
+        assert!(provenance.file_of(6).is_none());
+
+        let first = match &result.output().items[0] {
+            syn::Item::Mod(item) => item,
+            _ => panic!("expected a module"),
+        };
+        let second = match &first.content.as_ref().unwrap().1[0] {
+            syn::Item::Mod(item) => item,
+            _ => panic!("expected a module"),
+        };
+        let sample = match &second.content.as_ref().unwrap().1[1] {
+            syn::Item::Fn(item) => item,
+            _ => panic!("expected a function"),
+        };
+        assert_eq!(provenance.index_of(sample.sig.ident.span()), Some(5));
+        assert_eq!(provenance.index_of(sample.block.span()), Some(5));
+    }
+
+    #[test]
+    fn source_map() {
+        let mut env = make_test_env();
+        env.register("src/first/mod.rs", "mod second;\ninclude!(\"gen.rs\");");
+        env.register("src/first/gen.rs", "\n\n\nconst GENERATED: usize = 1;");
+        let result = InlinerBuilder::default()
+            .expand_includes(true)
+            .parse_internal(Path::new("src/lib.rs"), &mut env)
+            .unwrap();
+        let map = result.source_map();
+        let path = |path: &str| path.split("::").map(String::from).collect::<Vec<_>>();
+
+        let first = match &result.output().items[0] {
+            syn::Item::Mod(item) => item,
+            _ => panic!("expected a module"),
+        };
+        let second = match &first.content.as_ref().unwrap().1[0] {
+            syn::Item::Mod(item) => item,
+            _ => panic!("expected a module"),
+        };
+        let generated = match &first.content.as_ref().unwrap().1[1] {
+            syn::Item::Const(item) => item,
+            _ => panic!("expected a constant"),
+        };
+        let sample = match &second.content.as_ref().unwrap().1[1] {
+            syn::Item::Fn(item) => item,
+            _ => panic!("expected a function"),
+        };
+
+        let (file, position) = map
+            .translate(&path("first::second"), sample.sig.ident.span().start())
+            .unwrap();
+        assert_eq!(file, Path::new("src/first/second.rs"));
+        assert_eq!((position.line, position.column), (7, 19));
+
+        let (file, position) = map
+            .translate(&path("first"), generated.ident.span().start())
+            .unwrap();
+        assert_eq!(file, Path::new("src/first/gen.rs"));
+        assert_eq!((position.line, position.column), (4, 6));
+
+        let position = sample.sig.ident.span().start();
+        assert!(map
+            .translate(&path("first::second::third"), position)
+            .is_none());
+        assert!(map.translate(&[], position).is_none());
+    }
+
+    #[test]
+    fn include_provenance() {
+        let mut env = TestResolver::default();
+        env.register("src/lib.rs", "mod keywords;");
+        env.register(
+            "src/keywords.rs",
+            "struct Before;\ninclude!(\"gen.rs\");\nstruct After;",
+        );
+        env.register("src/gen.rs", "\n\nconst KEYWORDS: &[&str] = &[];");
+
+        let result = InlinerBuilder::default()
+            .expand_includes(true)
+            .parse_internal(Path::new("src/lib.rs"), &mut env)
+            .unwrap();
+
+        let provenance = result.provenance_map();
+        let files = (0..provenance.len())
+            .map(|index| provenance.file_of(index).unwrap().to_str().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            files,
+            vec![
+                "src/lib.rs",
+                "src/keywords.rs",
+                "src/gen.rs",
+                "src/keywords.rs"
+            ]
+        );
+
+        let table = result.span_table();
+        let entry = &table.entries()[2];
+        assert_eq!(entry.file(), Path::new("src/gen.rs"));
+        assert_eq!((entry.line(), entry.column()), (3, 0));
+        assert_eq!(result.offset_map()[2].1, PathBuf::from("src/gen.rs"),);
+        assert_eq!(result.offset_map()[2].2, 2);
+    }
+
+    #[test]
+    fn sort_items() {
+        let mut env = TestResolver::default();
+        env.register(
+            "src/lib.rs",
+            "mod b; fn z() {} mod a { fn y() {} fn x() {} }",
+        );
+        env.register("src/b.rs", "fn w() {} fn v() {}");
+
+        fn name(item: &syn::Item) -> String {
+            match item {
+                syn::Item::Mod(item) => item.ident.to_string(),
+                syn::Item::Fn(item) => item.sig.ident.to_string(),
+                _ => String::new(),
+            }
+        }
+
+        let result = InlinerBuilder::default()
+            .sort_items(|a, b| name(a).cmp(&name(b)))
+            .parse_internal(Path::new("src/lib.rs"), &mut env)
+            .unwrap();
+
+        assert_eq!(
+            result.output().to_token_stream().to_string(),
+            quote! {
+                mod a { fn x() {} fn y() {} }
+                mod b { fn v() {} fn w() {} }
+                fn z() {}
+            }
+            .to_string()
+        );
+        assert_eq!(
+            result.module_index(),
+            vec![("b".to_string(), PathBuf::from("src/b.rs"))]
+        );
+        assert_eq!(result.module_graph().modules()[0].module_path(), &["b"]);
+    }
+
+    /// The comparator sees the content of modules inlined from files.
+    #[test]
+    fn sort_items_inlined() {
+        let mut env = TestResolver::default();
+        env.register("src/lib.rs", "mod big; mod small;");
+        env.register("src/big.rs", "fn a() {} fn b() {}");
+        env.register("src/small.rs", "fn c() {}");
+
+        fn len(item: &syn::Item) -> usize {
+            match item {
+                syn::Item::Mod(item) => item.content.as_ref().map_or(0, |(_, items)| items.len()),
+                _ => 0,
+            }
+        }
+
+        let result = InlinerBuilder::default()
+            .sort_items(|a, b| len(a).cmp(&len(b)))
+            .parse_internal(Path::new("src/lib.rs"), &mut env)
+            .unwrap();
+
+        assert_eq!(
+            result.output().to_token_stream().to_string(),
+            quote! {
+                mod small { fn c() {} }
+                mod big { fn a() {} fn b() {} }
+            }
+            .to_string()
+        );
+        let files = result
+            .span_table()
+            .entries()
+            .iter()
+            .map(|entry| entry.file().to_str().unwrap().to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            files,
+            vec![
+                "src/lib.rs",
+                "src/small.rs",
+                "src/lib.rs",
+                "src/big.rs",
+                "src/big.rs"
+            ]
+        );
+    }
+
+    #[test]
+    fn error_stubs() {
+        let mut env = TestResolver::default();
+        env.register("src/lib.rs", "mod a; mod b;");
+        env.register("src/b.rs", "fn b() {}");
+
+        let result = InlinerBuilder::default()
+            .error_stubs(true)
+            .parse_internal(Path::new("src/lib.rs"), &mut env)
+            .unwrap();
+
+        assert_eq!(result.errors().len(), 1);
+        assert_eq!(
+            result.output().to_token_stream().to_string(),
+            quote! {
+                mod a {
+                    compile_error!("could not inline: src/a/mod.rs: path not in test resolver hashmap");
+                }
+                mod b { fn b() {} }
+            }
+            .to_string()
+        );
+    }
+
+    #[test]
+    fn outer_and_inner_docs() {
+        let mut env = TestResolver::default();
+        env.register("src/lib.rs", "/// Outer doc\n#[allow(dead_code)]\nmod foo;");
+        env.register(
+            "src/foo.rs",
+            "//! Inner doc\n#![allow(unused)]\nstruct Foo;",
+        );
+
+        let result = InlinerBuilder::default()
+            .parse_internal(Path::new("src/lib.rs"), &mut env)
+            .unwrap();
+
+        let item = match &result.output().items[0] {
+            syn::Item::Mod(item) => item,
+            _ => panic!("expected a module"),
+        };
+        let styles = item
+            .attrs
+            .iter()
+            .map(|attr| attr.style == syn::AttrStyle::Outer)
+            .collect::<Vec<_>>();
+        assert_eq!(styles, vec![true, true, false, false]);
+        assert_eq!(
+            result.output().to_token_stream().to_string(),
+            quote! {
+                #[doc = " Outer doc"]
+                #[allow(dead_code)]
+                mod foo {
+                    #![doc = " Inner doc"]
+                    #![allow(unused)]
+                    struct Foo;
+                }
+            }
+            .to_string()
+        );
+    }
+
+    #[test]
+    fn lint() {
+        let mut env = make_test_env();
+        env.register("src/lib.rs", "mod first; mod missing;");
+
+        let errors = InlinerBuilder::default()
+            .lint_internal(Path::new("src/lib.rs"), &mut env)
+            .unwrap();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].module_path(), &["missing"]);
+
+        env.register("src/first/second/third/fourth.rs", "mod fifth;");
+        let errors = InlinerBuilder::default()
+            .lint_internal(Path::new("src/lib.rs"), &mut env)
+            .unwrap();
+        let paths = errors
+            .iter()
+            .map(|error| error.module_path().join("::"))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            paths,
+            vec!["first::second::third::fourth::fifth", "missing"]
+        );
+    }
+
+    #[test]
+    fn path_on_inline_module() {
+        let mut env = TestResolver::default();
+        env.register(
+            "src/lib.rs",
+            r#"#[path = "custom"] mod outer { mod inner; }"#,
+        );
+        env.register("src/custom/inner.rs", "struct Inner;");
+
+        let result = InlinerBuilder::default()
+            .parse_internal(Path::new("src/lib.rs"), &mut env)
+            .unwrap();
+        assert!(!result.has_errors());
+        assert_eq!(
+            result.module_index(),
+            vec![(
+                "outer::inner".to_string(),
+                PathBuf::from("src/custom/inner.rs")
+            )]
+        );
+    }
+
+    #[test]
+    fn macro_invocations() {
+        let mut env = TestResolver::default();
+        env.register("src/lib.rs", "mod a;\nlazy_static! {}\n");
+        env.register(
+            "src/a.rs",
+            "fn f() {\n    println!();\n    let v = vec![1];\n}\nmacro_rules! m { () => {} }\n",
+        );
+
+        let result = InlinerBuilder::default()
+            .parse_internal(Path::new("src/lib.rs"), &mut env)
+            .unwrap();
+
+        let lines = |path: &str| {
+            result.macro_invocations()[Path::new(path)]
+                .iter()
+                .map(|span| span.start().line)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(lines("src/lib.rs"), vec![2]);
+        assert_eq!(lines("src/a.rs"), vec![2, 3]);
+    }
+
+    #[test]
+    fn warn_shadowing() {
+        let mut env = TestResolver::default();
+        env.register("src/lib.rs", "mod core { mod std {} }\nmod alloc;");
+        env.register("src/alloc.rs", "");
+
+        let result = InlinerBuilder::default()
+            .warn_shadowing(true)
+            .parse_internal(Path::new("src/lib.rs"), &mut env)
+            .unwrap();
+
+        let kinds = result
+            .warnings()
+            .iter()
+            .map(|warning| warning.kind().clone())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            kinds,
+            vec![
+                WarningKind::ShadowedCrate("core".to_string()),
+                WarningKind::ShadowedCrate("alloc".to_string()),
+            ]
+        );
+        assert_eq!(
+            result.warnings()[1].to_string(),
+            "src/lib.rs:2:4: warning: module `alloc` shadows the `alloc` crate"
+        );
+    }
+
+    #[test]
+    fn max_output_items() {
+        let result = InlinerBuilder::default()
+            .max_output_items(3)
+            .parse_internal(Path::new("src/lib.rs"), &mut make_test_env())
+            .unwrap();
+
+        // `lib.rs` and `first/mod.rs` have one item each, but `second.rs` has two items plus
+        // the one in `third`.
+        let errors = result
+            .errors()
+            .iter()
+            .map(|error| (error.module_path().join("::"), error.kind().to_string()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            errors,
+            vec![(
+                "first::second".to_string(),
+                "output item limit reached".to_string()
+            )]
+        );
+
+        let result = InlinerBuilder::default()
+            .max_output_items(6)
+            .parse_internal(Path::new("src/lib.rs"), &mut make_test_env())
+            .unwrap();
+        assert!(!result.has_errors());
+    }
+
+    #[test]
+    fn expand_cfg_if() {
+        let mut env = TestResolver::default();
+        env.register(
+            "src/lib.rs",
+            "cfg_if::cfg_if! { if #[cfg(unix)] { mod unix; } else { mod other; } }",
+        );
+        env.register("src/unix.rs", "struct Unix;");
+        env.register("src/other.rs", "struct Other;");
+
+        let mut cfg = CfgOptions::new();
+        cfg.set("unix");
+        let result = InlinerBuilder::default()
+            .expand_cfg_if(true)
+            .cfg_options(cfg)
+            .parse_internal(Path::new("src/lib.rs"), &mut env)
+            .unwrap();
+        assert!(!result.has_errors());
+        assert_eq!(
+            result.output().to_token_stream().to_string(),
+            quote! { mod unix { struct Unix; } }.to_string()
+        );
+
+        let result = InlinerBuilder::default()
+            .expand_cfg_if(true)
+            .cfg_options(CfgOptions::new())
+            .parse_internal(Path::new("src/lib.rs"), &mut env)
+            .unwrap();
+        assert_eq!(
+            result.output().to_token_stream().to_string(),
+            quote! { mod other { struct Other; } }.to_string()
+        );
+    }
+
+    #[test]
+    fn diff_cfgs() {
+        let mut env = TestResolver::default();
+        env.register(
+            "src/lib.rs",
+            r#"
+            mod common;
+            #[cfg(unix)] mod unix { mod detail {} }
+            #[cfg(feature = "extra")] mod extra;
+            "#,
+        );
+        env.register("src/common.rs", "");
+        env.register("src/extra.rs", "");
+
+        let mut a = CfgOptions::new();
+        a.set("unix");
+        let mut b = CfgOptions::new();
+        b.set_value("feature", "extra");
+
+        let diff = InlinerBuilder::default()
+            .diff_cfgs_internal(Path::new("src/lib.rs"), a, b, &mut env)
+            .unwrap();
+        assert_eq!(diff.both(), &[vec!["common".to_string()]]);
+        assert_eq!(
+            diff.only_a(),
+            &[
+                vec!["unix".to_string()],
+                vec!["unix".to_string(), "detail".to_string()]
+            ]
+        );
+        assert_eq!(diff.only_b(), &[vec!["extra".to_string()]]);
+    }
+
+    #[test]
+    fn annotation_order() {
+        let mut env = TestResolver::default();
+        env.register(
+            "src/lib.rs",
+            r#"
+            /// Docs
+            #[cfg(unix)]
+            #[cfg_attr(unix, path = "foo.rs")]
+            #[allow(dead_code)]
+            mod foo;
+            "#,
+        );
+        env.register("src/foo.rs", "#![allow(unused)]\n#![doc = \"Inner\"]");
+
+        let result = InlinerBuilder::default()
+            .annotate_paths(true)
+            .annotate_module_paths(true)
+            .parse_internal(Path::new("src/lib.rs"), &mut env)
+            .unwrap();
+
+        let attrs = match &result.output().items[0] {
+            syn::Item::Mod(item) => item
+                .attrs
+                .iter()
+                .map(|attr| attr.to_token_stream().to_string())
+                .collect::<Vec<_>>(),
+            _ => panic!("expected a module"),
+        };
+        let expected = [
+            quote!(#[doc = " Docs"]),
+            quote!(#[cfg(unix)]),
+            quote!(#[cfg_attr(unix, path = "foo.rs")]),
+            quote!(#[allow(dead_code)]),
+            quote!(#[syn_inline_mod_path("src/foo.rs")]),
+            quote!(#[syn_inline_mod_module_path("foo")]),
+            quote!(#![allow(unused)]),
+            quote!(#![doc = "Inner"]),
+        ];
+        assert_eq!(
+            attrs,
+            expected.iter().map(ToString::to_string).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn cfg_gated_inline_module() {
+        let mut env = TestResolver::default();
+        env.register("src/lib.rs", "#[cfg(unix)] mod sys { mod detail; }");
+        env.register("src/sys/detail.rs", "struct Detail;");
+
+        let result = InlinerBuilder::default()
+            .parse_internal(Path::new("src/lib.rs"), &mut env)
+            .unwrap();
+        assert!(!result.has_errors());
+        assert_eq!(
+            result.module_index(),
+            vec![(
+                "sys::detail".to_string(),
+                PathBuf::from("src/sys/detail.rs")
+            )]
+        );
+
+        let mut cfg = CfgOptions::new();
+        cfg.set("unix");
+        let result = InlinerBuilder::default()
+            .cfg_options(cfg)
+            .parse_internal(Path::new("src/lib.rs"), &mut env)
+            .unwrap();
+        assert_eq!(result.module_index().len(), 1);
+
+        let result = InlinerBuilder::default()
+            .cfg_options(CfgOptions::new())
+            .parse_internal(Path::new("src/lib.rs"), &mut env)
+            .unwrap();
+        assert!(result.module_index().is_empty());
+        assert!(result.output().items.is_empty());
+    }
+
+    #[test]
+    fn absolute_paths() {
+        let mut env = TestResolver::default();
+        env.register("src/lib.rs", "mod a; mod missing;");
+        env.register("src/a.rs", "");
+
+        let result = InlinerBuilder::default()
+            .annotate_paths(true)
+            .absolute_paths(true)
+            .parse_internal(Path::new("src/lib.rs"), &mut env)
+            .unwrap();
+
+        let cwd = std::env::current_dir().unwrap();
+        assert_eq!(result.annotated_paths(), [cwd.join("src/a.rs")].into());
+        let error = &result.errors()[0];
+        assert_eq!(error.src_path(), cwd.join("src/lib.rs"));
+        assert_eq!(error.path(), cwd.join("src/missing/mod.rs"));
+    }
+
+    #[test]
+    fn resolve_and_trace() {
+        let mut env = make_test_env();
+        env.register("src/lib.rs", "mod first; mod missing;");
+
+        let (result, trace) = InlinerBuilder::default()
+            .trace_internal(Path::new("src/lib.rs"), &mut env)
+            .unwrap();
+        assert_eq!(result.errors().len(), 1);
+        assert_eq!(trace.modules().len(), 5);
+        assert_eq!(
+            trace.to_string(),
+            "\
+src/lib.rs
+  mod first: inlined from src/first/mod.rs
+    candidate src/first.rs (missing)
+    candidate src/first/mod.rs (found, chosen)
+    mod second: inlined from src/first/second.rs
+      candidate src/first/second.rs (found, chosen)
+      candidate src/first/second/mod.rs (missing)
+      mod third: inline
+        mod fourth: inlined from src/first/second/third/fourth.rs
+          candidate src/first/second/third/fourth.rs (found, chosen)
+          candidate src/first/second/third/fourth/mod.rs (missing)
+  mod missing: failed: path not in test resolver hashmap
+    candidate src/missing.rs (missing)
+    candidate src/missing/mod.rs (missing, chosen)
+"
+        );
+    }
+
+    #[test]
+    fn qualified_paths() {
+        let mut env = TestResolver::default();
+        env.register("src/lib.rs", "mod a; mod shared;");
+        env.register(
+            "src/a.rs",
+            r#"
+            #[path = "super::shared"] mod up;
+            #[path = "self::b"] mod b;
+            mod inner { #[path = "crate::shared"] mod root; }
+            #[path = "super::super::shared"] mod too_far;
+            "#,
+        );
+        env.register("src/a/b.rs", "struct B;");
+        env.register("src/shared/mod.rs", "struct Shared;");
+
+        let paths = |result: &InliningResult| {
+            result
+                .module_graph()
+                .modules()
+                .iter()
+                .filter_map(|module| Some(module.file()?.to_path_buf()))
+                .collect::<Vec<_>>()
+        };
+
+        let result = InlinerBuilder::default()
+            .qualified_paths(true)
+            .parse_internal(Path::new("src/lib.rs"), &mut env)
+            .unwrap();
+        assert_eq!(
+            paths(&result),
+            vec![
+                PathBuf::from("src/a.rs"),
+                "src/shared/mod.rs".into(),
+                "src/a/b.rs".into(),
+                "src/shared/mod.rs".into(),
+                "src/shared/mod.rs".into(),
+            ]
+        );
+        assert_eq!(result.errors().len(), 1);
+        assert_eq!(result.errors()[0].module_name(), "too_far");
+
+        // Without the option, the paths are file names that don't exist.
+        let result = InlinerBuilder::default()
+            .parse_internal(Path::new("src/lib.rs"), &mut env)
+            .unwrap();
+        assert_eq!(result.errors().len(), 4);
+    }
+
+    #[test]
+    fn retain_sources() {
+        let mut env = make_test_env();
+        let result = InlinerBuilder::default()
+            .retain_sources(true)
+            .parse_internal(Path::new("src/lib.rs"), &mut env)
+            .unwrap();
+        assert_eq!(
+            result.source_of(Path::new("src/lib.rs")),
+            Some("mod first;")
+        );
+        assert_eq!(
+            result.source_of(Path::new("src/first/mod.rs")),
+            Some("mod second;")
+        );
+        assert_eq!(result.source_of(Path::new("src/missing.rs")), None);
+
+        #[cfg(not(feature = "codespan"))]
+        {
+            let result = InlinerBuilder::default()
+                .parse_internal(Path::new("src/lib.rs"), &mut env)
+                .unwrap();
+            assert_eq!(result.source_of(Path::new("src/lib.rs")), None);
+        }
+    }
+
+    #[test]
+    fn warn_path_mismatch() {
+        let mut env = TestResolver::default();
+        env.register(
+            "src/lib.rs",
+            r#"
+            #[path = "old/a.rs"] mod a;
+            #[path = "b.rs"] mod b;
+            #[path = "old/c.rs"] mod c;
+            "#,
+        );
+        env.register("src/old/a.rs", "");
+        env.register("src/a.rs", "");
+        env.register("src/b.rs", "");
+        env.register("src/old/c.rs", "");
+
+        let result = InlinerBuilder::default()
+            .warn_path_mismatch(true)
+            .parse_internal(Path::new("src/lib.rs"), &mut env)
+            .unwrap();
+        assert!(!result.has_errors());
+        assert_eq!(result.warnings().len(), 1);
+        assert_eq!(
+            result.warnings()[0].kind(),
+            &WarningKind::PathMismatch {
+                path: "src/old/a.rs".into(),
+                ident_path: "src/a.rs".into(),
+            }
+        );
+
+        let result = InlinerBuilder::default()
+            .parse_internal(Path::new("src/lib.rs"), &mut env)
+            .unwrap();
+        assert!(result.warnings().is_empty());
+    }
+
+    #[test]
+    fn expand_includes() {
+        let mut env = TestResolver::default();
+        env.register(
+            "src/lib.rs",
+            r#"
+            mod keywords;
+            include!("missing.rs");
+            "#,
+        );
+        env.register("src/keywords.rs", r#"include!("gen/keywords.rs");"#);
+        env.register(
+            "src/gen/keywords.rs",
+            r#"
+            pub const KEYWORDS: &[&str] = &["fn"];
+            include!("more.rs");
+            "#,
+        );
+        env.register("src/gen/more.rs", r#"mod extra; include!("keywords.rs");"#);
+        env.register("src/keywords/extra.rs", "struct Extra;");
+
+        let result = InlinerBuilder::default()
+            .expand_includes(true)
+            .parse_internal(Path::new("src/lib.rs"), &mut env)
+            .unwrap();
+        assert!(!result.has_errors());
+        assert_eq!(
+            result.output.into_token_stream().to_string(),
+            quote! {
+                mod keywords {
+                    pub const KEYWORDS: &[&str] = &["fn"];
+                    mod extra {
+                        struct Extra;
+                    }
+                    include!("keywords.rs");
+                }
+                include!("missing.rs");
+            }
+            .to_string()
+        );
+
+        let warnings = result
+            .warnings
+            .iter()
+            .map(|warning| warning.kind().clone())
+            .collect::<Vec<_>>();
+        assert!(matches!(
+            &warnings[..],
+            [
+                WarningKind::UnresolvedInclude { path: a, .. },
+                WarningKind::UnresolvedInclude { path: b, .. },
+            ] if a == Path::new("src/missing.rs") && b == Path::new("src/gen/keywords.rs")
+        ));
+    }
+
+    #[test]
+    fn strip_resolved_cfg_attr() {
+        let mut env = TestResolver::default();
+        env.register(
+            "src/lib.rs",
+            r#"
+            #[cfg_attr(unix, path = "unix.rs")]
+            #[cfg_attr(windows, path = "windows.rs")]
+            mod sys;
+            "#,
+        );
+        env.register("src/unix.rs", "struct Unix;");
+        env.register("src/windows.rs", "struct Windows;");
+
+        let mut cfg = CfgOptions::new();
+        cfg.set("windows");
+        let result = InlinerBuilder::default()
+            .cfg_options(cfg)
+            .strip_resolved_cfg_attr(true)
+            .replace_resolved_cfg_attr(true)
+            .parse_internal(Path::new("src/lib.rs"), &mut env)
+            .unwrap();
+        assert!(!result.has_errors());
+        assert_eq!(
+            result.output.into_token_stream().to_string(),
+            quote! {
+                #[cfg_attr(unix, path = "unix.rs")]
+                #[cfg(windows)]
+                mod sys {
+                    struct Windows;
+                }
+            }
+            .to_string()
+        );
+    }
+
+    #[test]
+    fn custom_parser() {
+        /// Parses every file as though it declared one more item.
+        struct Marking(TestResolver);
+
+        impl FileResolver for Marking {
+            fn path_exists(&self, path: &Path) -> bool {
+                self.0.path_exists(path)
+            }
+
+            fn resolve(&mut self, path: &Path) -> Result<syn::File, Error> {
+                self.0.resolve(path)
+            }
+
+            fn load(&mut self, path: &Path) -> Option<Result<String, Error>> {
+                self.0.load(path)
+            }
+
+            fn parse(&self, src: &str) -> Result<syn::File, Error> {
+                parse_source(&format!("{} struct Parsed;", src))
+            }
+        }
+
+        let result = InlinerBuilder::default()
+            .parse_internal(Path::new("src/lib.rs"), &mut Marking(make_test_env()))
+            .unwrap();
+        let first = match &result.output().items[..] {
+            [syn::Item::Mod(first), syn::Item::Struct(_)] => first,
+            items => panic!("expected a module and a struct, found {}", items.len()),
+        };
+        assert!(matches!(
+            &first.content.as_ref().unwrap().1[..],
+            [syn::Item::Mod(_), syn::Item::Struct(_)]
+        ));
+    }
+
+    #[test]
+    fn use_declarations() {
+        let mut env = TestResolver::default();
+        env.register("src/lib.rs", "use std::fmt; mod a;");
+        env.register(
+            "src/a.rs",
+            "pub use self::b::B; mod b { use super::*; fn f() { use std::io; } }",
+        );
+
+        let result = InlinerBuilder::default()
+            .parse_internal(Path::new("src/lib.rs"), &mut env)
+            .unwrap();
+        let uses = result
+            .use_declarations()
+            .into_iter()
+            .map(|(module_path, item)| {
+                (
+                    module_path.join("::"),
+                    item.tree.into_token_stream().to_string(),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            uses,
+            vec![
+                ("".to_string(), "std :: fmt".to_string()),
+                ("a".into(), "self :: b :: B".into()),
+                ("a::b".into(), "super :: *".into()),
+                ("a::b".into(), "std :: io".into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn content_line() {
+        let mut env = TestResolver::default();
+        env.register(
+            "src/lib.rs",
+            "mod a;\n\nmod b {\n    mod c;\n}\nmod missing;",
+        );
+        env.register("src/a.rs", "\n\nmod d {}");
+        env.register("src/b/c.rs", "");
+
+        let result = InlinerBuilder::default()
+            .parse_internal(Path::new("src/lib.rs"), &mut env)
+            .unwrap();
+        let lines = result
+            .module_graph()
+            .modules()
+            .iter()
+            .map(|module| (module.module_path().join("::"), module.content_line()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            lines,
+            vec![
+                ("a".to_string(), Some(1)),
+                ("a::d".into(), Some(3)),
+                ("b".into(), Some(3)),
+                ("b::c".into(), Some(1)),
+                ("missing".into(), None),
+            ]
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn require_utf8_paths() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        let dir = Path::new(OsStr::from_bytes(b"src/\xff"));
+        let mut env = TestResolver::default();
+        env.register_path(dir.join("lib.rs"), "mod a;");
+        env.register_path(dir.join("a.rs"), "struct A;");
+
+        let mut options = InlinerBuilder::default();
+        options.annotate_paths(true);
+        let result = options
+            .parse_internal(&dir.join("lib.rs"), &mut env)
+            .unwrap();
+        assert!(!result.has_errors());
+        assert_eq!(result.annotated_paths(), HashSet::from([dir.join("a.rs")]));
+
+        let result = options
+            .require_utf8_paths(true)
+            .parse_internal(&dir.join("lib.rs"), &mut env)
+            .unwrap();
+        assert_eq!(result.errors().len(), 1);
+        assert_eq!(result.errors()[0].path(), dir.join("a.rs"));
+        assert!(result.errors()[0]
+            .kind()
+            .same_kind(&io::Error::from(io::ErrorKind::InvalidData).into()));
+
+        options.path_encoding(PathEncoding::Utf8Lossy);
+        assert_eq!(
+            options.require_utf8_paths(false).path_encoding,
+            PathEncoding::Utf8Lossy
+        );
+    }
+
+    #[test]
+    fn attribute_filter() {
+        let mut env = TestResolver::default();
+        env.register(
+            "src/lib.rs",
+            "#[rustfmt::skip] #[cfg(unix)] mod a; #[rustfmt::skip] mod b {}",
+        );
+        env.register(
+            "src/a.rs",
+            "#![rustfmt::skip] #![allow(dead_code)] struct A;",
+        );
+
+        let result = InlinerBuilder::default()
+            .annotate_module_paths(true)
+            .attribute_filter(|attr| !attr.path().segments.iter().any(|s| s.ident == "rustfmt"))
+            .parse_internal(Path::new("src/lib.rs"), &mut env)
+            .unwrap();
+        assert!(!result.has_errors());
+        assert_eq!(
+            result.output.into_token_stream().to_string(),
+            quote! {
+                #[cfg(unix)]
+                #[syn_inline_mod_module_path("a")]
+                mod a {
+                    #![allow(dead_code)]
+                    struct A;
+                }
+                #[rustfmt::skip]
+                mod b {}
+            }
+            .to_string()
+        );
+    }
+
+    #[test]
+    fn resolve_logical() {
+        let mut env = make_test_env();
+        env.register("src/first/second/third/fourth/broken.rs", "fn");
+        env.register(
+            "src/first/second/third/fourth.rs",
+            "mod broken; pub fn another_fn() -> bool { true }",
+        );
+        let options = InlinerBuilder::default();
+        let lookup = |module_path: &[&str], env: &mut TestResolver| {
+            options.resolve_logical_internal(Path::new("src/lib.rs"), module_path, env)
+        };
+
+        assert_eq!(lookup(&[], &mut env).unwrap(), Path::new("src/lib.rs"));
+        assert_eq!(
+            lookup(&["first", "second"], &mut env).unwrap(),
+            Path::new("src/first/second.rs")
+        );
+        assert_eq!(
+            lookup(&["first", "second", "third"], &mut env).unwrap(),
+            Path::new("src/first/second.rs")
+        );
+        // The broken module inside the target isn't loaded.
+        assert_eq!(
+            lookup(&["first", "second", "third", "fourth"], &mut env).unwrap(),
+            Path::new("src/first/second/third/fourth.rs")
+        );
+        assert!(matches!(
+            lookup(&["first", "nope"], &mut env),
+            Err(Error::ModuleNotFound)
+        ));
+        assert!(matches!(
+            lookup(&["first", "second", "third", "fourth", "broken"], &mut env),
+            Err(Error::Parse(_))
+        ));
+    }
+
+    #[test]
+    fn canonical_hash() {
+        let hash = |root: &str, env: &mut TestResolver| {
+            InlinerBuilder::default()
+                .parse_internal(Path::new(root), env)
+                .unwrap()
+                .canonical_hash()
+        };
+        let mut env = make_test_env();
+        let before = hash("src/lib.rs", &mut env);
+        assert_eq!(hash("src/lib.rs", &mut env), before);
+
+        env.register(
+            "src/first/second/third/fourth.rs",
+            "// Only comments and formatting changed\npub fn another_fn()->bool{true}",
+        );
+        assert_eq!(hash("src/lib.rs", &mut env), before);
+
+        env.register(
+            "src/first/second/third/fourth.rs",
+            "pub fn another_fn() -> bool { false }",
+        );
+        assert_ne!(hash("src/lib.rs", &mut env), before);
+
+        let mut here = TestResolver::default();
+        here.register("src/lib.rs", "mod a;");
+        here.register("src/a.rs", "struct A;");
+        let mut elsewhere = TestResolver::default();
+        elsewhere.register("/elsewhere/./src/lib.rs", "mod a;");
+        elsewhere.register("/elsewhere/./src/a.rs", "struct A;");
+        assert_eq!(
+            hash("src/lib.rs", &mut here),
+            hash("/elsewhere/./src/lib.rs", &mut elsewhere)
+        );
+    }
+
+    #[test]
+    fn plan() {
+        let mut env = make_test_env();
+        env.register("src/first/mod.rs", "mod second; mod missing;");
+        let plans = InlinerBuilder::default()
+            .plan_internal(Path::new("src/lib.rs"), &mut env)
+            .unwrap();
+
+        let summary = plans
+            .iter()
+            .map(|plan| {
+                (
+                    plan.module_path().join("::"),
+                    plan.declared_in(),
+                    plan.file(),
+                    plan.in_inline_module(),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            summary,
+            vec![
+                (
+                    "first".to_string(),
+                    Path::new("src/lib.rs"),
+                    Path::new("src/first/mod.rs"),
+                    false
+                ),
+                (
+                    "first::second".to_string(),
+                    Path::new("src/first/mod.rs"),
+                    Path::new("src/first/second.rs"),
+                    false
+                ),
+                (
+                    "first::second::third::fourth".to_string(),
+                    Path::new("src/first/second.rs"),
+                    Path::new("src/first/second/third/fourth.rs"),
+                    true
+                ),
+            ]
+        );
+        assert_eq!(plans[2].span().start().line, 4);
+    }
+
+    #[test]
+    fn extra_modules() {
+        let mut env = make_test_env();
+        env.register("gen/generated.rs", "pub struct Generated;");
+        env.register("gen/nested.rs", "pub struct Nested;");
+        env.register("gen/fourth.rs", "pub struct Replaced;");
+        let result = InlinerBuilder::default()
+            .extra_modules(vec![
+                (vec!["generated".into()], "gen/generated.rs".into()),
+                (
+                    vec![
+                        "first".into(),
+                        "second".into(),
+                        "third".into(),
+                        "nested".into(),
+                    ],
+                    "gen/nested.rs".into(),
+                ),
+                (
+                    vec![
+                        "first".into(),
+                        "second".into(),
+                        "third".into(),
+                        "fourth".into(),
+                    ],
+                    "gen/fourth.rs".into(),
+                ),
+                (
+                    vec!["missing".into(), "parent".into()],
+                    "gen/nope.rs".into(),
+                ),
+            ])
+            .parse_internal(Path::new("src/lib.rs"), &mut env)
+            .unwrap();
+
+        assert!(!result.has_errors());
+        assert_eq!(
+            result.output().into_token_stream().to_string(),
+            quote! {
+                mod first {
+                    mod second {
+                        #[doc = " Documentation"]
+                        mod third {
+                            mod fourth {
+                                pub struct Replaced;
+                            }
+                            mod nested {
+                                pub struct Nested;
+                            }
+                        }
+
+                        pub fn sample() -> usize { 4 }
+                    }
+                }
+                mod generated {
+                    pub struct Generated;
+                }
+            }
+            .to_string()
+        );
+    }
+
+    #[test]
+    fn resolution_kind() {
+        let mut env = TestResolver::default();
+        env.register(
+            "src/lib.rs",
+            r#"
+            mod a;
+            #[path = "other.rs"]
+            mod b;
+            #[allow(dead_code)]
+            #[cfg_attr(unix, path = "unix.rs")]
+            mod c;
+            mod d {}
+            "#,
+        );
+        env.register("src/a.rs", "");
+        env.register("src/other.rs", "");
+        env.register("src/unix.rs", "");
+
+        let result = InlinerBuilder::default()
+            .parse_internal(Path::new("src/lib.rs"), &mut env)
+            .unwrap();
+        let kinds = result
+            .module_graph()
+            .modules()
+            .iter()
+            .map(ModuleNode::resolution)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            kinds,
+            vec![
+                ResolutionKind::Ident,
+                ResolutionKind::Path,
+                ResolutionKind::CfgAttr,
+                ResolutionKind::Inline,
+            ]
+        );
+    }
+
+    #[test]
+    fn iter_modules() {
+        let mut env = make_test_env();
+        env.register("src/first/mod.rs", "mod second; mod broken; mod after;");
+        env.register("src/first/broken.rs", "fn");
+        env.register("src/first/after.rs", "struct After;");
+        let options = InlinerBuilder::default();
+        let mut modules = module_iter::ModuleIter::new(&options, Path::new("src/lib.rs"), env);
+
+        let mut next = || match modules.next() {
+            Some(Ok((context, file))) => {
+                let path = PathBuf::from(context.last().cloned().unwrap_or(ModSegment::path("")));
+                Ok((path, file.into_token_stream().to_string()))
+            }
+            Some(Err(err)) => Err(err),
+            None => panic!("iterator ended early"),
+        };
+
+        assert_eq!(
+            next().unwrap(),
+            (
+                "".into(),
+                quote!(
+                    mod first;
+                )
+                .to_string()
+            )
+        );
+        assert_eq!(
+            next().unwrap(),
+            (
+                "first".into(),
+                quote!(
+                    mod second;
+                    mod broken;
+                    mod after;
+                )
+                .to_string()
+            )
+        );
+        let (second, src) = next().unwrap();
+        assert_eq!(second, Path::new("second"));
+        assert!(src.contains("mod fourth ;"));
+        assert_eq!(
+            next().unwrap(),
+            (
+                "fourth".into(),
+                quote!(
+                    pub fn another_fn() -> bool {
+                        true
+                    }
+                )
+                .to_string()
+            )
+        );
+        assert!(matches!(next(), Err(Error::Parse(_))));
+        assert_eq!(
+            next().unwrap(),
+            (
+                "after".into(),
+                quote!(
+                    struct After;
+                )
+                .to_string()
+            )
+        );
+        assert!(modules.next().is_none());
+    }
+
+    #[test]
+    fn always_disabled_modules() {
+        let mut env = TestResolver::default();
+        env.register(
+            "src/lib.rs",
+            "#[cfg(any())] mod a; #[cfg(FALSE)] mod b; #[cfg(not(all()))] mod c { mod d; } mod e;",
+        );
+        env.register("src/e.rs", "");
+
+        let result = InlinerBuilder::default()
+            .parse_internal(Path::new("src/lib.rs"), &mut env)
+            .unwrap();
+        assert_eq!(result.errors().len(), 1);
+        assert_eq!(result.errors()[0].module_path(), ["b"]);
+
+        let result = InlinerBuilder::default()
+            .never_set_cfgs(["FALSE"])
+            .parse_internal(Path::new("src/lib.rs"), &mut env)
+            .unwrap();
+        assert!(!result.has_errors());
+        assert_eq!(result.module_graph().len(), 1);
+        assert_eq!(
+            result.output().into_token_stream().to_string(),
+            quote! {
+                mod e {}
+            }
+            .to_string()
+        );
+    }
+
+    #[test]
+    fn module_attrs() {
+        let result = InlinerBuilder::default()
+            .parse_internal(Path::new("src/lib.rs"), &mut make_test_env())
+            .unwrap();
+
+        let attrs = result.module_attrs(&["first", "second", "third"]).unwrap();
+        assert_eq!(attrs.len(), 1);
+        assert!(attrs[0].path().is_ident("doc"));
+        assert_eq!(result.module_attrs(&["first"]), Some(&[][..]));
+        assert_eq!(result.module_attrs(&[]), Some(&[][..]));
+        assert_eq!(result.module_attrs(&["first", "nope"]), None);
+    }
+
+    #[test]
+    fn raw_ident_modules() {
+        let mut env = TestResolver::default();
+        env.register(
+            "src/lib.rs",
+            r#"
+            mod r#match;
+            #[path = "r#match.rs"]
+            mod x;
+            "#,
+        );
+        env.register("src/match.rs", "struct Ident;");
+        env.register("src/r#match.rs", "struct Path;");
+
+        let result = InlinerBuilder::default()
+            .parse_internal(Path::new("src/lib.rs"), &mut env)
+            .unwrap();
+        assert!(!result.has_errors());
+        assert_eq!(
+            result.module_index(),
+            vec![
+                ("r#match".to_string(), PathBuf::from("src/match.rs")),
+                ("x".to_string(), PathBuf::from("src/r#match.rs")),
+            ]
+        );
+    }
+
+    #[test]
+    fn to_dot() {
+        let mut env = make_test_env();
+        env.register("src/first/mod.rs", "mod second; mod missing;");
+        let result = InlinerBuilder::default()
+            .parse_internal(Path::new("src/lib.rs"), &mut env)
+            .unwrap();
+
+        assert_eq!(
+            result.to_dot(),
+            r#"digraph modules {
+    root [label="crate\nsrc/lib.rs", shape=box];
+    m0 [label="crate::first\nsrc/first/mod.rs", shape=box];
+    m1 [label="crate::first::second\nsrc/first/second.rs", shape=box];
+    m2 [label="crate::first::second::third", shape=ellipse];
+    m3 [label="crate::first::second::third::fourth\nsrc/first/second/third/fourth.rs", shape=box];
+    m4 [label="crate::first::missing", shape=box, style=dashed, color=red];
+    root -> m0;
+    m0 -> m1;
+    m1 -> m2;
+    m2 -> m3;
+    m0 -> m4;
+}
+"#
+        );
+    }
+
+    #[test]
+    fn inline_from_str() {
+        let mut env = make_test_env();
+        env.register("src/first/mod.rs", "should not be read");
+        env.register("src/first/second.rs", "pub struct Second;");
+
+        for root in [true, false] {
+            let result = InlinerBuilder::default()
+                .root(root)
+                .inline_from_str_internal("mod second;", Path::new("src/first"), env.clone())
+                .unwrap();
+            assert!(!result.has_errors());
+            assert_eq!(
+                result.output().into_token_stream().to_string(),
+                quote!(
+                    mod second {
+                        pub struct Second;
+                    }
+                )
+                .to_string()
+            );
+            assert_eq!(result.module_graph().root_file(), Path::new("<memory>"));
+        }
+    }
+
+    /// A `#[path]` is relative to `base_dir` for a root file, but to its parent otherwise.
+    #[test]
+    fn inline_from_str_root() {
+        let mut env = make_test_env();
+        env.register("src/first/x.rs", "struct InFirst;");
+        env.register("src/x.rs", "struct InSrc;");
+        let src = r#"#[path = "x.rs"] mod x; mod missing;"#;
+        let inline = |root| {
+            InlinerBuilder::default()
+                .root(root)
+                .inline_from_str_internal(src, Path::new("src/first"), env.clone())
+                .unwrap()
+        };
+
+        let result = inline(true);
+        assert_eq!(
+            result.module_graph().get(0).unwrap().file(),
+            Some(Path::new("src/first/x.rs"))
+        );
+        assert_eq!(result.errors()[0].src_path(), Path::new("<memory>"));
+        assert_eq!(
+            result.errors()[0].path(),
+            Path::new("src/first/missing/mod.rs")
+        );
+
+        let result = inline(false);
+        assert_eq!(
+            result.module_graph().get(0).unwrap().file(),
+            Some(Path::new("src/x.rs"))
+        );
+        assert_eq!(result.errors()[0].src_path(), Path::new("<memory>"));
+        assert_eq!(
+            result.errors()[0].path(),
+            Path::new("src/first/missing/mod.rs")
+        );
+    }
+
+    #[test]
+    fn inlined_paths() {
+        let mut env = make_test_env();
+        env.register("src/first/mod.rs", "mod missing; mod second;");
+        let result = InlinerBuilder::default()
+            .parse_internal(Path::new("src/lib.rs"), &mut env)
+            .unwrap();
+
+        assert_eq!(
+            result.inlined_paths(),
+            [
+                Path::new("src/lib.rs"),
+                Path::new("src/first/mod.rs"),
+                Path::new("src/first/second.rs"),
+                Path::new("src/first/second/third/fourth.rs"),
+            ]
+        );
+    }
+
+    #[test]
+    fn cycle() {
+        let mut env = TestResolver::default();
+        env.register("src/lib.rs", "mod a;");
+        env.register("src/a.rs", r#"#[path = "b.rs"] mod b;"#);
+        env.register("src/b.rs", r#"#[path = "a.rs"] mod a; struct B;"#);
+
+        let result = InlinerBuilder::default()
+            .parse_internal(Path::new("src/lib.rs"), &mut env)
+            .unwrap();
+        assert_eq!(result.errors().len(), 1);
+        let error = &result.errors()[0];
+        assert_eq!(error.module_path(), ["a", "b", "a"]);
+        assert_eq!(error.src_path(), Path::new("src/b.rs"));
+        match error.kind() {
+            Error::Cycle(chain) => assert_eq!(
+                chain,
+                &[
+                    Path::new("src/a.rs"),
+                    Path::new("src/b.rs"),
+                    Path::new("src/a.rs")
+                ]
+            ),
+            other => panic!("expected a cycle, got {:?}", other),
+        }
+        assert_eq!(
+            error.kind().to_string(),
+            "module includes a file that is already being inlined: src/a.rs -> src/b.rs -> src/a.rs"
+        );
+
+        // The rest of the tree is still inlined.
+        assert_eq!(
+            result.output.into_token_stream().to_string(),
+            quote! {
+                mod a {
+                    #[path = "b.rs"]
+                    mod b {
+                        #[path = "a.rs"]
+                        mod a;
+                        struct B;
+                    }
+                }
+            }
+            .to_string()
+        );
+    }
+
+    #[test]
+    fn max_depth() {
+        let result = InlinerBuilder::default()
+            .max_depth(2)
+            .parse_internal(Path::new("src/lib.rs"), &mut make_test_env())
+            .unwrap();
+
+        assert_eq!(result.errors().len(), 1);
+        let error = &result.errors()[0];
+        assert_eq!(error.module_path(), ["first", "second", "third", "fourth"]);
+        assert_eq!(error.src_path(), Path::new("src/first/second.rs"));
+        assert_eq!(error.path(), Path::new("src/first/second/third/fourth.rs"));
+        assert_eq!(error.src_span().start().line, 4);
+        assert!(matches!(error.kind(), Error::DepthLimitExceeded));
+        assert_eq!(
+            result.output.into_token_stream().to_string(),
+            quote! {
+                mod first {
+                    mod second {
+                        #[doc = " Documentation"]
+                        mod third {
+                            mod fourth;
+                        }
+
+                        pub fn sample() -> usize { 4 }
+                    }
+                }
+            }
+            .to_string()
+        );
+
+        let result = InlinerBuilder::default()
+            .max_depth(4)
+            .parse_internal(Path::new("src/lib.rs"), &mut make_test_env())
+            .unwrap();
+        assert!(!result.has_errors());
     }
-}
 
-impl fmt::Display for InliningResult {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        writeln!(f, "Inlining partially completed before errors:")?;
-        for error in &self.errors {
-            writeln!(f, " * {}", error)?;
-        }
+    #[cfg(feature = "pretty")]
+    #[test]
+    fn to_pretty_string() {
+        let result = InlinerBuilder::default()
+            .parse_internal(Path::new("src/lib.rs"), &mut make_test_env())
+            .unwrap();
 
-        Ok(())
+        let pretty = result.to_pretty_string();
+        assert_eq!(
+            pretty,
+            "mod first {
+    mod second {
+        /// Documentation
+        mod third {
+            mod fourth {
+                pub fn another_fn() -> bool {
+                    true
+                }
+            }
+        }
+        pub fn sample() -> usize {
+            4
+        }
     }
 }
+"
+        );
+        assert_eq!(
+            syn::parse_file(&pretty)
+                .unwrap()
+                .into_token_stream()
+                .to_string(),
+            result.output.into_token_stream().to_string()
+        );
+    }
 
-/// An error that happened while attempting to inline a module.
-#[derive(Debug)]
-pub struct InlineError {
-    src_path: PathBuf,
-    module_name: String,
-    src_span: Span,
-    path: PathBuf,
-    kind: Error,
-}
+    #[test]
+    fn clone_result() {
+        let mut env = make_test_env();
+        env.register("src/lib.rs", "mod first; mod missing;");
+        let result = InlinerBuilder::default()
+            .parse_internal(Path::new("src/lib.rs"), &mut env)
+            .unwrap();
+        let copy = result.clone();
 
-impl InlineError {
-    pub(crate) fn new(
-        src_path: impl Into<PathBuf>,
-        item_mod: &ItemMod,
-        path: impl Into<PathBuf>,
-        kind: Error,
-    ) -> Self {
-        Self {
-            src_path: src_path.into(),
-            module_name: item_mod.ident.to_string(),
-            src_span: item_mod.span(),
-            path: path.into(),
-            kind,
+        assert_eq!(copy.output(), result.output());
+        assert_eq!(copy.errors().len(), 1);
+        assert_eq!(copy.errors()[0].to_string(), result.errors()[0].to_string());
+        match (result.errors()[0].kind(), copy.errors()[0].kind()) {
+            (Error::Io(original), Error::Io(copy)) => assert_eq!(original.kind(), copy.kind()),
+            _ => panic!("expected an io error"),
         }
     }
 
-    /// Returns the source path where the error originated.
-    ///
-    /// The file at this path parsed correctly, but it caused the file at `self.path()` to be read.
-    pub fn src_path(&self) -> &Path {
-        &self.src_path
-    }
-
-    /// Returns the name of the module that was attempted to be inlined.
-    pub fn module_name(&self) -> &str {
-        &self.module_name
-    }
+    #[test]
+    fn drop_test_modules() {
+        let mut env = make_test_env();
+        env.register(
+            "src/lib.rs",
+            "mod first; #[cfg(test)] mod tests; #[cfg(test)] mod inline_tests { mod missing; }",
+        );
 
-    /// Returns the `Span` (including line and column information) in the source path that caused
-    /// `self.path()` to be included.
-    pub fn src_span(&self) -> proc_macro2::Span {
-        self.src_span
-    }
+        let result = InlinerBuilder::default()
+            .drop_test_modules(true)
+            .parse_internal(Path::new("src/lib.rs"), &mut env)
+            .unwrap();
 
-    /// Returns the path where the error happened.
-    ///
-    /// Reading and parsing this file failed for the reason listed in `self.kind()`.
-    pub fn path(&self) -> &Path {
-        &self.path
+        assert!(!result.has_errors());
+        assert_eq!(result.output().items.len(), 1);
+        assert!(result
+            .module_graph()
+            .modules()
+            .iter()
+            .all(|m| m.module_path()[0] == "first"));
     }
 
-    /// Returns the reason for this error happening.
-    pub fn kind(&self) -> &Error {
-        &self.kind
-    }
-}
+    /// With recursion disabled, only the modules declared in the root file are inlined.
+    #[test]
+    fn non_recursive() {
+        let result = InlinerBuilder::default()
+            .recursive(false)
+            .parse_internal(Path::new("src/lib.rs"), &mut make_test_env())
+            .unwrap();
 
-impl fmt::Display for InlineError {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let start = self.src_span.start();
-        write!(
-            f,
-            "{}:{}:{}: error while including {}: {}",
-            self.src_path.display(),
-            start.line,
-            start.column,
-            self.path.display(),
-            self.kind
-        )
+        assert!(!result.has_errors());
+        assert_eq!(
+            result.output.into_token_stream().to_string(),
+            quote! {
+                mod first {
+                    mod second;
+                }
+            }
+            .to_string()
+        );
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use quote::{quote, ToTokens};
+    /// `#[path]` attributes starting with the sentinel resolve against the workspace root.
+    #[test]
+    fn base_dir() {
+        let mut env = TestResolver::default();
+        env.register("virtual/lib.rs", "mod a; #[path = \"b.rs\"] mod b;");
+        env.register("real/a.rs", "mod c;");
+        env.register("real/a/c.rs", "struct C;");
+        env.register("real/b.rs", "struct B;");
 
-    use super::*;
+        let result = InlinerBuilder::default()
+            .base_dir(Some("real".into()))
+            .parse_internal(Path::new("virtual/lib.rs"), &mut env)
+            .unwrap();
 
-    fn make_test_env() -> TestResolver {
-        let mut env = TestResolver::default();
-        env.register("src/lib.rs", "mod first;");
-        env.register("src/first/mod.rs", "mod second;");
-        env.register(
-            "src/first/second.rs",
-            r#"
-            #[doc = " Documentation"]
-            mod third {
-                mod fourth;
+        assert!(!result.has_errors());
+        assert_eq!(
+            result.output.into_token_stream().to_string(),
+            quote! {
+                mod a {
+                    mod c {
+                        struct C;
+                    }
+                }
+                #[path = "b.rs"]
+                mod b {
+                    struct B;
+                }
             }
-
-            pub fn sample() -> usize { 4 }
-            "#,
-        );
-        env.register(
-            "src/first/second/third/fourth.rs",
-            "pub fn another_fn() -> bool { true }",
+            .to_string()
         );
-        env
+
+        env.register("virtual/runner.rs", "mod d;");
+        env.register("real/runner/d.rs", "struct D;");
+        let result = InlinerBuilder::default()
+            .root(false)
+            .base_dir(Some("real".into()))
+            .parse_internal(Path::new("virtual/runner.rs"), &mut env)
+            .unwrap();
+        assert!(!result.has_errors());
     }
 
-    /// Run a full test, exercising the entirety of the functionality in this crate.
     #[test]
-    fn happy_path() {
+    fn workspace_root() {
+        let mut env = TestResolver::default();
+        env.register(
+            "crates/app/src/lib.rs",
+            r#"#[path = "//shared/util.rs"] mod util; #[path = "local.rs"] mod local;"#,
+        );
+        env.register("crates/app/src/local.rs", "struct Local;");
+        env.register("shared/util.rs", "struct Util;");
+
         let result = InlinerBuilder::default()
-            .parse_internal(Path::new("src/lib.rs"), &mut make_test_env())
-            .unwrap()
-            .output;
+            .workspace_root("")
+            .parse_internal(Path::new("crates/app/src/lib.rs"), &mut env)
+            .unwrap();
 
+        assert!(!result.has_errors());
         assert_eq!(
-            result.into_token_stream().to_string(),
+            result.output.into_token_stream().to_string(),
             quote! {
-                mod first {
-                    mod second {
-                        #[doc = " Documentation"]
-                        mod third {
-                            mod fourth {
-                                pub fn another_fn() -> bool {
-                                    true
-                                }
-                            }
-                        }
-
-                        pub fn sample() -> usize {
-                            4
-                        }
-                    }
+                #[path = "//shared/util.rs"]
+                mod util {
+                    struct Util;
+                }
+                #[path = "local.rs"]
+                mod local {
+                    struct Local;
                 }
             }
             .to_string()
         );
+
+        let result = InlinerBuilder::default()
+            .workspace_sentinel("//")
+            .parse_internal(Path::new("crates/app/src/lib.rs"), &mut env)
+            .unwrap();
+
+        assert_eq!(result.errors().len(), 1);
+        assert_eq!(result.errors()[0].module_name(), "util");
+        assert!(matches!(
+            result.errors()[0].kind(),
+            Error::MissingWorkspaceRoot
+        ));
     }
 
-    /// Test case involving missing and invalid modules
     #[test]
-    fn missing_module() {
+    fn cfg_attr_first_path() {
         let mut env = TestResolver::default();
-        env.register("src/lib.rs", "mod missing;\nmod invalid;");
-        env.register("src/invalid.rs", "this-is-not-valid-rust!");
-
-        let result = InlinerBuilder::default().parse_internal(Path::new("src/lib.rs"), &mut env);
+        env.register(
+            "src/lib.rs",
+            r#"
+            #[cfg_attr(feature = "m2", allow(dead_code), path = "m2.rs")]
+            #[cfg_attr(not(feature = "m2"), path = "empty.rs")]
+            mod placeholder;
 
-        if let Ok(r) = result {
-            let errors = &r.errors;
-            assert_eq!(errors.len(), 2, "expected 2 errors");
+            #[cfg_attr(unix, cfg_attr(target_os = "linux", path = "sys/linux.rs"))]
+            mod sys;
+            "#,
+        );
+        env.register("src/m2.rs", "struct M2;");
+        env.register("src/sys/linux.rs", "struct Linux;");
 
-            let error = &errors[0];
-            assert_eq!(
-                error.src_path(),
-                Path::new("src/lib.rs"),
-                "correct source path"
-            );
-            assert_eq!(error.module_name(), "missing");
-            assert_eq!(error.src_span().start().line, 1);
-            assert_eq!(error.src_span().start().column, 0);
-            assert_eq!(error.src_span().end().line, 1);
-            assert_eq!(error.src_span().end().column, 12);
-            assert_eq!(error.path(), Path::new("src/missing/mod.rs"));
-            let io_err = match error.kind() {
-                Error::Io(err) => err,
-                _ => panic!("expected ErrorKind::Io, found {}", error.kind()),
-            };
-            assert_eq!(io_err.kind(), io::ErrorKind::NotFound);
+        let result = InlinerBuilder::default()
+            .parse_internal(Path::new("src/lib.rs"), &mut env)
+            .unwrap();
+        assert!(!result.has_errors());
+        assert_eq!(
+            result.output.into_token_stream().to_string(),
+            quote! {
+                #[cfg_attr(feature = "m2", allow(dead_code), path = "m2.rs")]
+                #[cfg_attr(not(feature = "m2"), path = "empty.rs")]
+                mod placeholder {
+                    struct M2;
+                }
 
-            let error = &errors[1];
-            assert_eq!(
-                error.src_path(),
-                Path::new("src/lib.rs"),
-                "correct source path"
-            );
-            assert_eq!(error.module_name(), "invalid");
-            assert_eq!(error.src_span().start().line, 2);
-            assert_eq!(error.src_span().start().column, 0);
-            assert_eq!(error.src_span().end().line, 2);
-            assert_eq!(error.src_span().end().column, 12);
-            assert_eq!(error.path(), Path::new("src/invalid.rs"));
-            match error.kind() {
-                Error::Parse(_) => {}
-                Error::Io(_) => panic!("expected ErrorKind::Parse, found {}", error.kind()),
+                #[cfg_attr(unix, cfg_attr(target_os = "linux", path = "sys/linux.rs"))]
+                mod sys {
+                    struct Linux;
+                }
             }
-        } else {
-            unreachable!();
-        }
+            .to_string()
+        );
     }
 
     /// Test case involving `cfg_attr` from the original request for implementation.
@@ -526,4 +4752,101 @@ mod tests {
             .to_string()
         )
     }
+
+    /// A resolver that counts how many sources it parses.
+    struct CountingResolver {
+        inner: TestResolver,
+        parses: std::cell::Cell<usize>,
+    }
+
+    impl FileResolver for CountingResolver {
+        fn path_exists(&self, path: &Path) -> bool {
+            self.inner.path_exists(path)
+        }
+
+        fn resolve(&mut self, path: &Path) -> Result<syn::File, Error> {
+            let src = self.load(path).unwrap()?;
+            self.parse(&src)
+        }
+
+        fn load(&mut self, path: &Path) -> Option<Result<String, Error>> {
+            self.inner.load(path)
+        }
+
+        fn parse(&self, src: &str) -> Result<syn::File, Error> {
+            self.parses.set(self.parses.get() + 1);
+            self.inner.parse(src)
+        }
+    }
+
+    #[test]
+    fn inline_incremental() {
+        let mut env = TestResolver::default();
+        env.register("src/lib.rs", "mod a; mod b;");
+        env.register("src/a.rs", "mod c; fn a() {}");
+        env.register("src/a/c.rs", "fn c() {}");
+        env.register("src/b.rs", "fn b() {}");
+        let mut resolver = CountingResolver {
+            inner: env,
+            parses: Default::default(),
+        };
+
+        let options = InlinerBuilder::default();
+        let root = Path::new("src/lib.rs");
+        let prev = options.parse_internal(root, &mut resolver).unwrap();
+        assert_eq!(resolver.parses.replace(0), 4);
+
+        // Only the root file is read on the way to the changed file; `a` and `c` are reused.
+        resolver.inner.register("src/b.rs", "fn b2() {}");
+        let changed = HashSet::from([PathBuf::from("src/b.rs")]);
+        let result = options
+            .inline_incremental_internal(root, &changed, prev.module_graph(), &mut resolver)
+            .unwrap();
+        assert_eq!(resolver.parses.replace(0), 2);
+        assert!(!result.has_errors());
+        assert_eq!(
+            result.output().to_token_stream().to_string(),
+            quote! {
+                mod a {
+                    mod c { fn c() {} }
+                    fn a() {}
+                }
+                mod b { fn b2() {} }
+            }
+            .to_string()
+        );
+        let modules = result
+            .module_graph()
+            .modules()
+            .iter()
+            .map(|node| (node.module_path().join("::"), node.parent()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            modules,
+            vec![
+                ("a".to_string(), None),
+                ("a::c".to_string(), Some(0)),
+                ("b".to_string(), None)
+            ]
+        );
+
+        // A change to a nested file reads the files above it again, but `b` is still reused.
+        resolver.inner.register("src/a/c.rs", "fn c2() {}");
+        let changed = HashSet::from([PathBuf::from("src/a/c.rs")]);
+        let result = options
+            .inline_incremental_internal(root, &changed, result.module_graph(), &mut resolver)
+            .unwrap();
+        assert_eq!(resolver.parses.get(), 3);
+        assert_eq!(
+            result.output().to_token_stream().to_string(),
+            quote! {
+                mod a {
+                    mod c { fn c2() {} }
+                    fn a() {}
+                }
+                mod b { fn b2() {} }
+            }
+            .to_string()
+        );
+    }
 }
@@ -1,10 +1,17 @@
 //! Path context tracking and candidate path generation for inlining.
 
-use std::path::{Path, PathBuf};
-use syn::{Expr, ExprLit, Ident, ItemMod, Lit, Meta};
+use std::path::{Component, Path, PathBuf};
+use syn::ext::IdentExt;
+use syn::punctuated::Punctuated;
+use syn::{
+    parse_quote, Attribute, Expr, ExprLit, ExprMacro, Ident, ItemMod, Lit, LitStr, Meta, Token,
+};
+
+use crate::cfg::{eval_const, split_cfg_attr, CfgOptions};
+use crate::FileResolver;
 
 /// Extensions to the built-in `Path` type for the purpose of mod expansion.
-trait ModPath {
+pub(crate) trait ModPath {
     /// Check if the current file is a 2015-style mod file. If so, named mods should be
     /// resolved in the current directory. If not, we should check, in order:
     ///
@@ -19,37 +26,176 @@ impl ModPath for Path {
     }
 }
 
+/// Lexically normalize `path` by removing `.` components and resolving `..` components
+/// against the preceding component where possible, without touching the file system.
+pub(crate) fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => match normalized.components().next_back() {
+                Some(Component::Normal(_)) => {
+                    normalized.pop();
+                }
+                Some(Component::RootDir) | Some(Component::Prefix(_)) => {}
+                _ => normalized.push(".."),
+            },
+            other => normalized.push(other),
+        }
+    }
+    normalized
+}
+
+/// Look for an existing file with the same stem as the missing file at `path`, either in the
+/// same directory or in a directory next to it.
+///
+/// Directories and files are listed through `resolver`, so no suggestion is made by
+/// resolvers that can't list directories. If several files match, the first in lexicographic
+/// order is returned.
+pub(crate) fn suggest_path<R: FileResolver + ?Sized>(resolver: &R, path: &Path) -> Option<PathBuf> {
+    let stem = path.file_stem()?;
+    let dir = non_empty(path.parent()?);
+    let parent = non_empty(dir.parent().unwrap_or(Path::new("..")));
+    let is_dir = |entry: &PathBuf| resolver.read_dir(entry).is_ok();
+
+    let mut dirs = vec![dir.to_path_buf()];
+    dirs.extend(
+        resolver
+            .read_dir(parent)
+            .into_iter()
+            .flatten()
+            .filter(is_dir),
+    );
+
+    let mut matches = dirs
+        .iter()
+        .flat_map(|dir| resolver.read_dir(dir).into_iter().flatten())
+        .filter(|candidate| candidate.file_stem() == Some(stem) && !is_dir(candidate))
+        .filter(|candidate| normalize(candidate) != normalize(path))
+        .collect::<Vec<_>>();
+    matches.sort();
+    matches.into_iter().next()
+}
+
+/// A module path such as `super::shared` used as the value of a `#[path]` attribute, enabled
+/// by `InlinerBuilder::qualified_paths`.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct QualifiedPath {
+    /// The number of modules to go up before following `segments`, or `None` to start from
+    /// the crate root.
+    pub supers: Option<usize>,
+    /// The names of the modules to follow, ending with the module to load.
+    pub segments: Vec<Ident>,
+}
+
+impl QualifiedPath {
+    /// Parse `path` as a module path that starts with `self`, `super` or `crate`, returning
+    /// `None` if it is anything else, such as a file path.
+    pub fn parse(path: &Path) -> Option<Self> {
+        let path = syn::parse_str::<syn::Path>(path.to_str()?).ok()?;
+        if path.leading_colon.is_some() {
+            return None;
+        }
+
+        if path
+            .segments
+            .iter()
+            .any(|segment| !segment.arguments.is_none())
+        {
+            return None;
+        }
+
+        let names = path
+            .segments
+            .into_iter()
+            .map(|segment| segment.ident)
+            .collect::<Vec<_>>();
+        let is_keyword = |name: &Ident| name == "self" || name == "super" || name == "crate";
+        let (prefix, segments) = names.split_at(names.iter().take_while(|n| is_keyword(n)).count());
+        let supers = match prefix {
+            [first] if first == "crate" => None,
+            [first, rest @ ..]
+                if (first == "self" || first == "super") && rest.iter().all(|n| n == "super") =>
+            {
+                Some(prefix.iter().filter(|n| *n == "super").count())
+            }
+            _ => return None,
+        };
+
+        if segments.is_empty() || segments.iter().any(is_keyword) {
+            return None;
+        }
+
+        let segments = segments.to_vec();
+        Some(QualifiedPath { supers, segments })
+    }
+}
+
+/// Use `.` in place of an empty relative directory.
+fn non_empty(dir: &Path) -> &Path {
+    if dir.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        dir
+    }
+}
+
 /// The current mod path, including idents and explicit paths.
+///
+/// A context can be built from a list of segments to reproduce how the inliner finds the
+/// file for a module, e.g. `mod a { #[path = "b.rs"] mod c; }` is
+/// `ModContext::from(vec![ModSegment::ident("a")?, ModSegment::path("b.rs")])`.
 #[derive(Debug, Clone, Default)]
 pub struct ModContext(Vec<ModSegment>);
 
 impl ModContext {
+    /// Enter a module nested inside the innermost module of the context.
     pub fn push(&mut self, value: ModSegment) {
         self.0.push(value);
     }
 
+    /// Leave the innermost module of the context.
     pub fn pop(&mut self) -> Option<ModSegment> {
         self.0.pop()
     }
 
+    /// The innermost module of the context.
+    pub fn last(&self) -> Option<&ModSegment> {
+        self.0.last()
+    }
+
+    /// Get a context with the modules of `inner` nested inside the innermost module of this
+    /// context.
+    pub(crate) fn join(&self, inner: &ModContext) -> ModContext {
+        Self(self.0.iter().chain(&inner.0).cloned().collect())
+    }
+
     /// Get the list of places a module's source code may appear relative to the current file
     /// location.
+    ///
+    /// Modules are found in the directory of `base` if it is a root or `mod.rs` file, or if
+    /// the outermost module in the context has a `#[path]` attribute. Otherwise, they are found
+    /// in a directory named after the file stem of `base`.
+    ///
+    /// An absolute `#[path]`, such as `/gen/mod.rs` on Unix or `C:\gen\mod.rs` on Windows,
+    /// is used as-is rather than being joined to that directory.
     pub fn relative_to(&self, base: &Path, root: bool) -> Vec<PathBuf> {
         let mut parent = base.to_path_buf();
         parent.pop();
-        if root || base.is_mod_file() {
-            self.to_path_bufs()
-                .into_iter()
-                .map(|end| parent.clone().join(end))
-                .collect()
-        } else {
+        if !(root || base.is_mod_file() || self.is_first_path()) {
             parent = parent.join(base.file_stem().unwrap());
-
-            self.to_path_bufs()
-                .into_iter()
-                .map(|end| parent.clone().join(end))
-                .collect()
         }
+
+        self.to_path_bufs()
+            .into_iter()
+            .map(|end| {
+                if end.is_absolute() {
+                    end
+                } else {
+                    parent.join(end)
+                }
+            })
+            .collect()
     }
 
     fn to_path_bufs(&self) -> Vec<PathBuf> {
@@ -72,6 +218,12 @@ impl ModContext {
         vec![inline, buf.join("mod.rs")]
     }
 
+    /// Checks if the first term in the context was an explicit `path` attribute, which is
+    /// always relative to the directory of the current file.
+    fn is_first_path(&self) -> bool {
+        self.0.first().map(|seg| seg.is_path()).unwrap_or_default()
+    }
+
     /// Checks if the last term in the context was a module identifier, rather
     /// than an explicit `path` attribute.
     fn is_last_ident(&self) -> bool {
@@ -85,13 +237,28 @@ impl From<Vec<ModSegment>> for ModContext {
     }
 }
 
+/// A single module in a `ModContext`.
 #[derive(Debug, Clone)]
 pub enum ModSegment {
+    /// A module found using its name, such as `mod foo;`.
     Ident(Ident),
+    /// A module found using a `#[path]` attribute.
     Path(PathBuf),
 }
 
 impl ModSegment {
+    /// Create a segment for a module found using its name.
+    ///
+    /// Returns an error if `ident` is not a valid Rust identifier.
+    pub fn ident(ident: &str) -> Result<Self, syn::Error> {
+        syn::parse_str(ident).map(ModSegment::Ident)
+    }
+
+    /// Create a segment for a module found using a `#[path]` attribute.
+    pub fn path(path: impl Into<PathBuf>) -> Self {
+        ModSegment::Path(path.into())
+    }
+
     /// Checks if the `self` mod segment was taken from the module identifier.
     pub fn is_ident(&self) -> bool {
         match self {
@@ -100,39 +267,140 @@ impl ModSegment {
         }
     }
 
+    /// Checks if the `self` mod segment was taken from a `#[path]` attribute.
     pub fn is_path(&self) -> bool {
         !self.is_ident()
     }
 }
 
-#[cfg(test)]
 impl ModSegment {
-    pub(self) fn new_ident(ident: &'static str) -> Self {
-        ModSegment::Ident(syn::Ident::new(ident, proc_macro2::Span::call_site()))
-    }
-
-    pub(self) fn new_path(path: &'static str) -> Self {
-        ModSegment::Path(PathBuf::from(path))
+    /// Get the segment for a `mod` item from its first `#[path]` attribute, including those
+    /// inside `#[cfg_attr]`, or its ident if there is none.
+    ///
+    /// If `cfg` is `None`, every `cfg_attr` is treated as active, so the first path wins,
+    /// except those whose predicates are false in every configuration, such as `any()`.
+    /// Otherwise, only paths inside `cfg_attr`s whose predicates hold are considered.
+    pub(crate) fn from_item(item: &ItemMod, cfg: Option<&CfgOptions>) -> Self {
+        match find_path(item.attrs.iter().map(|attr| &attr.meta), cfg) {
+            Some(path) => ModSegment::Path(path),
+            None => ModSegment::Ident(item.ident.clone()),
+        }
     }
 }
 
-impl From<&ItemMod> for ModSegment {
-    fn from(v: &ItemMod) -> Self {
-        for attr in &v.attrs {
-            if let Meta::NameValue(ref name_value) = attr.meta {
-                if name_value.path.is_ident("path") {
-                    if let Expr::Lit(ExprLit {
-                        lit: Lit::Str(ref path_value),
-                        ..
-                    }) = name_value.value
-                    {
-                        return ModSegment::Path(path_value.value().into());
-                    }
+/// Find the value of the first `path = "..."` in `metas`, looking inside active `cfg_attr`s.
+fn find_path<'a>(
+    metas: impl IntoIterator<Item = &'a Meta>,
+    cfg: Option<&CfgOptions>,
+) -> Option<PathBuf> {
+    for meta in metas {
+        if let Meta::NameValue(name_value) = meta {
+            if name_value.path.is_ident("path") {
+                if let Some(path) = path_value(&name_value.value) {
+                    return Some(path.into());
+                }
+            }
+        } else if let Some((predicate, attrs)) = split_cfg_attr(meta) {
+            let active = match cfg {
+                Some(cfg) => cfg.eval(&predicate),
+                None => eval_const(&predicate, &[]).unwrap_or(true),
+            };
+            if active {
+                if let Some(path) = find_path(&attrs, cfg) {
+                    return Some(path);
                 }
             }
         }
+    }
+
+    None
+}
+
+/// Whether the path that `ModSegment::from_item` finds in `attrs` is inside a `cfg_attr`
+/// rather than a plain `#[path]` attribute. `false` if there is no path.
+pub(crate) fn is_cfg_attr_path(attrs: &[Attribute], cfg: Option<&CfgOptions>) -> bool {
+    attrs
+        .iter()
+        .find(|attr| find_path([&attr.meta], cfg).is_some())
+        .is_some_and(|attr| !matches!(attr.meta, Meta::NameValue(_)))
+}
 
-        ModSegment::Ident(v.ident.clone())
+/// Remove the `path` inside a `cfg_attr` that `ModSegment::from_item` used to find a module
+/// from `attrs`, and optionally put a `#[cfg]` of the predicates that selected it in its place.
+///
+/// Other attributes in the same `cfg_attr` are kept. Nothing is removed if the path came from
+/// a plain `#[path]` attribute.
+pub(crate) fn strip_cfg_attr_path(
+    attrs: &mut Vec<Attribute>,
+    cfg: Option<&CfgOptions>,
+    replace_with_cfg: bool,
+) {
+    let Some(index) = attrs
+        .iter()
+        .position(|attr| find_path([&attr.meta], cfg).is_some())
+    else {
+        return;
+    };
+    let Some((predicates, rest)) = remove_path(&attrs[index].meta, cfg) else {
+        return;
+    };
+
+    let mut replacement = Vec::with_capacity(2);
+    if replace_with_cfg {
+        let predicate: Meta = match &predicates[..] {
+            [predicate] => predicate.clone(),
+            _ => parse_quote!(all(#(#predicates),*)),
+        };
+        replacement.push(parse_quote!(#[cfg(#predicate)]));
+    }
+    if let Some(rest) = rest {
+        replacement.push(Attribute {
+            meta: rest,
+            ..attrs[index].clone()
+        });
+    }
+    attrs.splice(index..=index, replacement);
+}
+
+/// Take the `path` that `find_path` would use out of the `cfg_attr` in `meta`, returning the
+/// predicates of the `cfg_attr`s around it and what is left of `meta`, if anything.
+fn remove_path(meta: &Meta, cfg: Option<&CfgOptions>) -> Option<(Vec<Meta>, Option<Meta>)> {
+    let (predicate, mut rest) = split_cfg_attr(meta)?;
+    let position = rest
+        .iter()
+        .position(|meta| find_path([meta], cfg).is_some())?;
+    let found = rest.remove(position);
+
+    let mut predicates = vec![predicate.clone()];
+    if let Some((inner, inner_rest)) = remove_path(&found, cfg) {
+        predicates.extend(inner);
+        rest.splice(position..position, inner_rest);
+    }
+
+    let rest = (!rest.is_empty()).then(|| parse_quote!(cfg_attr(#predicate, #(#rest),*)));
+    Some((predicates, rest))
+}
+
+/// Evaluate the value of a `path` attribute, which is either a string literal or a `concat!`
+/// of string literals.
+pub(crate) fn path_value(value: &Expr) -> Option<String> {
+    match value {
+        Expr::Lit(ExprLit {
+            lit: Lit::Str(lit), ..
+        }) => Some(lit.value()),
+        Expr::Macro(ExprMacro { mac, .. }) if mac.path.is_ident("concat") => {
+            let parts = mac
+                .parse_body_with(Punctuated::<LitStr, Token![,]>::parse_terminated)
+                .ok()?;
+            Some(parts.iter().map(LitStr::value).collect())
+        }
+        _ => None,
+    }
+}
+
+impl From<&ItemMod> for ModSegment {
+    fn from(v: &ItemMod) -> Self {
+        ModSegment::from_item(v, None)
     }
 }
 
@@ -143,10 +411,12 @@ impl From<&mut ItemMod> for ModSegment {
 }
 
 impl From<ModSegment> for PathBuf {
+    /// Convert a segment to the path it names. Raw identifiers such as `r#match` name the file
+    /// without their `r#` prefix, as in `rustc`, while paths are used verbatim.
     fn from(seg: ModSegment) -> Self {
         match seg {
             ModSegment::Path(buf) => buf,
-            ModSegment::Ident(ident) => ident.to_string().into(),
+            ModSegment::Ident(ident) => ident.unraw().to_string().into(),
         }
     }
 }
@@ -156,15 +426,179 @@ mod tests {
     use super::*;
     use std::path::Path;
 
+    #[test]
+    fn normalize_paths() {
+        assert_eq!(
+            normalize(Path::new("src/./a/../lib.rs")),
+            Path::new("src/lib.rs")
+        );
+        assert_eq!(
+            normalize(Path::new("../src/lib.rs")),
+            Path::new("../src/lib.rs")
+        );
+        assert_eq!(normalize(Path::new("/../lib.rs")), Path::new("/lib.rs"));
+    }
+
+    #[test]
+    fn segment_constructors() {
+        assert!(ModSegment::ident("foo").unwrap().is_ident());
+        assert!(ModSegment::ident("r#type").is_ok());
+        assert!(ModSegment::ident("foo.rs").is_err());
+        assert!(ModSegment::path("foo.rs").is_path());
+    }
+
+    #[test]
+    fn raw_ident_segments() {
+        let ident = ModSegment::ident("r#match").unwrap();
+        assert_eq!(PathBuf::from(ident), Path::new("match"));
+
+        let path = ModSegment::path("r#match.rs");
+        assert_eq!(PathBuf::from(path), Path::new("r#match.rs"));
+
+        let ctx = ModContext::from(vec![
+            ModSegment::ident("r#async").unwrap(),
+            ModSegment::ident("r#match").unwrap(),
+        ]);
+        assert_eq!(
+            ctx.relative_to(Path::new("src/lib.rs"), true),
+            vec![
+                Path::new("src/async/match.rs"),
+                Path::new("src/async/match/mod.rs")
+            ]
+        );
+    }
+
+    #[test]
+    fn cfg_attr_path() {
+        let item: ItemMod = syn::parse_quote! {
+            #[cfg_attr(unix, path = "unix.rs")]
+            #[cfg_attr(windows, path = "windows.rs")]
+            mod sys;
+        };
+
+        let first = ModSegment::from(&item);
+        assert_eq!(PathBuf::from(first), Path::new("unix.rs"));
+
+        let mut cfg = CfgOptions::new();
+        cfg.set("windows");
+        let active = ModSegment::from_item(&item, Some(&cfg));
+        assert_eq!(PathBuf::from(active), Path::new("windows.rs"));
+
+        let none = ModSegment::from_item(&item, Some(&CfgOptions::new()));
+        assert!(none.is_ident());
+    }
+
+    #[test]
+    fn cfg_attr_const_path() {
+        let item: ItemMod = syn::parse_quote! {
+            #[cfg_attr(any(), path = "never.rs")]
+            #[cfg_attr(all(), path = "always.rs")]
+            mod sys;
+        };
+
+        let without_cfg = ModSegment::from(&item);
+        assert_eq!(PathBuf::from(without_cfg), Path::new("always.rs"));
+
+        let with_cfg = ModSegment::from_item(&item, Some(&CfgOptions::new()));
+        assert_eq!(PathBuf::from(with_cfg), Path::new("always.rs"));
+
+        let item: ItemMod = syn::parse_quote! {
+            #[cfg_attr(not(all()), path = "never.rs")]
+            mod sys;
+        };
+        assert!(ModSegment::from(&item).is_ident());
+    }
+
+    #[test]
+    fn concat_path() {
+        let item: ItemMod = syn::parse_quote! {
+            #[path = concat!("a/", "b.rs")]
+            mod m;
+        };
+        assert_eq!(PathBuf::from(ModSegment::from(&item)), Path::new("a/b.rs"));
+
+        let item: ItemMod = syn::parse_quote! {
+            #[path = concat!("a/", env!("OUT_DIR"))]
+            mod m;
+        };
+        assert!(ModSegment::from(&item).is_ident());
+    }
+
+    #[test]
+    fn qualified_path() {
+        let parse = |path: &str| {
+            QualifiedPath::parse(Path::new(path)).map(|qualified| {
+                let segments = qualified.segments.iter().map(Ident::to_string);
+                (qualified.supers, segments.collect::<Vec<_>>())
+            })
+        };
+
+        assert_eq!(
+            parse("super::shared"),
+            Some((Some(1), vec!["shared".into()]))
+        );
+        assert_eq!(
+            parse("self::super::super::a::b"),
+            Some((Some(2), vec!["a".into(), "b".into()]))
+        );
+        assert_eq!(parse("self::a"), Some((Some(0), vec!["a".into()])));
+        assert_eq!(parse("crate::a"), Some((None, vec!["a".into()])));
+        assert_eq!(parse("shared.rs"), None);
+        assert_eq!(parse("a::b"), None);
+        assert_eq!(parse("super"), None);
+        assert_eq!(parse("crate::super::a"), None);
+        assert_eq!(parse("super::a::super::b"), None);
+    }
+
+    #[test]
+    fn strip_cfg_attr_path() {
+        let strip = |attrs: Vec<Attribute>, cfg: Option<&CfgOptions>, replace_with_cfg| {
+            let mut attrs = attrs;
+            super::strip_cfg_attr_path(&mut attrs, cfg, replace_with_cfg);
+            attrs
+        };
+        let attrs: Vec<Attribute> = vec![
+            parse_quote!(#[cfg_attr(unix, path = "unix.rs", allow(dead_code))]),
+            parse_quote!(#[cfg_attr(windows, path = "windows.rs")]),
+        ];
+
+        assert_eq!(
+            strip(attrs.clone(), None, false),
+            vec![
+                parse_quote!(#[cfg_attr(unix, allow(dead_code))]),
+                attrs[1].clone(),
+            ] as Vec<Attribute>
+        );
+        let mut windows = CfgOptions::new();
+        windows.set("windows");
+        assert_eq!(
+            strip(attrs.clone(), Some(&windows), true),
+            vec![attrs[0].clone(), parse_quote!(#[cfg(windows)])] as Vec<Attribute>
+        );
+
+        let nested: Vec<Attribute> =
+            vec![parse_quote!(#[cfg_attr(unix, cfg_attr(test, path = "test.rs"), inline)])];
+        assert_eq!(
+            strip(nested, None, true),
+            vec![
+                parse_quote!(#[cfg(all(unix, test))]),
+                parse_quote!(#[cfg_attr(unix, inline)]),
+            ] as Vec<Attribute>
+        );
+
+        let plain: Vec<Attribute> = vec![parse_quote!(#[path = "a.rs"])];
+        assert_eq!(strip(plain.clone(), None, true), plain);
+    }
+
     #[test]
     fn relative_to_lib() {
         let ctx = ModContext::from(vec![
-            ModSegment::new_ident("threads"),
-            ModSegment::new_ident("local"),
+            ModSegment::ident("threads").unwrap(),
+            ModSegment::ident("local").unwrap(),
         ]);
 
         assert_eq!(
-            ctx.relative_to(&Path::new("/src/lib.rs"), true),
+            ctx.relative_to(Path::new("/src/lib.rs"), true),
             vec![
                 Path::new("/src/threads/local.rs"),
                 Path::new("/src/threads/local/mod.rs"),
@@ -175,12 +609,12 @@ mod tests {
     #[test]
     fn relative_to_mod() {
         let ctx = ModContext::from(vec![
-            ModSegment::new_ident("threads"),
-            ModSegment::new_ident("local"),
+            ModSegment::ident("threads").unwrap(),
+            ModSegment::ident("local").unwrap(),
         ]);
 
         assert_eq!(
-            ctx.relative_to(&Path::new("/src/runner/mod.rs"), false),
+            ctx.relative_to(Path::new("/src/runner/mod.rs"), false),
             vec![
                 Path::new("/src/runner/threads/local.rs"),
                 Path::new("/src/runner/threads/local/mod.rs"),
@@ -194,12 +628,12 @@ mod tests {
     #[test]
     fn relative_to_2018_mod() {
         let ctx = ModContext::from(vec![
-            ModSegment::new_ident("threads"),
-            ModSegment::new_ident("local"),
+            ModSegment::ident("threads").unwrap(),
+            ModSegment::ident("local").unwrap(),
         ]);
 
         assert_eq!(
-            ctx.relative_to(&Path::new("/src/runner.rs"), false),
+            ctx.relative_to(Path::new("/src/runner.rs"), false),
             vec![
                 Path::new("/src/runner/threads/local.rs"),
                 Path::new("/src/runner/threads/local/mod.rs"),
@@ -213,12 +647,12 @@ mod tests {
     #[test]
     fn relative_to_non_standard_root() {
         let ctx = ModContext::from(vec![
-            ModSegment::new_ident("threads"),
-            ModSegment::new_ident("local"),
+            ModSegment::ident("threads").unwrap(),
+            ModSegment::ident("local").unwrap(),
         ]);
 
         assert_eq!(
-            ctx.relative_to(&Path::new("/src/runner.rs"), true),
+            ctx.relative_to(Path::new("/src/runner.rs"), true),
             vec![
                 Path::new("/src/threads/local.rs"),
                 Path::new("/src/threads/local/mod.rs"),
@@ -226,35 +660,132 @@ mod tests {
         );
     }
 
+    /// Check that `main.rs` and `lib.rs` roots in the same directory produce the same candidates.
+    #[test]
+    fn relative_to_dual_roots() {
+        let ctx = ModContext::from(vec![ModSegment::ident("shared").unwrap()]);
+
+        assert_eq!(
+            ctx.relative_to(Path::new("/src/main.rs"), true),
+            ctx.relative_to(Path::new("/src/lib.rs"), true)
+        );
+    }
+
     /// Check that a full chain of explicit file names works produces exactly one candidate file with
     /// the correct absolute path.
     #[test]
     fn relative_to_paths() {
         let ctx = ModContext::from(vec![
-            ModSegment::new_path("threads"),
-            ModSegment::new_path("tls.rs"),
+            ModSegment::path("threads"),
+            ModSegment::path("tls.rs"),
         ]);
 
         assert_eq!(
-            ctx.relative_to(&Path::new("/src/lib.rs"), true),
+            ctx.relative_to(Path::new("/src/lib.rs"), true),
             vec![Path::new("/src/threads/tls.rs")]
         );
     }
 
+    /// Check that a `#[path]` in a non-`mod.rs` file is relative to the file's directory, but one
+    /// nested in an inline module starts from the directory named after the file.
+    #[test]
+    fn relative_to_path_in_non_mod_rs() {
+        let ctx = ModContext::from(vec![ModSegment::path("other.rs")]);
+
+        assert_eq!(
+            ctx.relative_to(Path::new("/src/a/b.rs"), false),
+            vec![Path::new("/src/a/other.rs")]
+        );
+
+        let ctx = ModContext::from(vec![
+            ModSegment::ident("inline").unwrap(),
+            ModSegment::path("other.rs"),
+        ]);
+
+        assert_eq!(
+            ctx.relative_to(Path::new("/src/a/b.rs"), false),
+            vec![Path::new("/src/a/b/inline/other.rs")]
+        );
+    }
+
+    /// Check that an absolute `#[path]` replaces the directory of the current file, including
+    /// when it is on an inline module containing the declaration.
+    #[cfg(unix)]
+    #[test]
+    fn relative_to_absolute_path() {
+        let ctx = ModContext::from(vec![ModSegment::path("/gen/mod.rs")]);
+        assert_eq!(
+            ctx.relative_to(Path::new("/src/runner.rs"), false),
+            vec![Path::new("/gen/mod.rs")]
+        );
+
+        let ctx = ModContext::from(vec![
+            ModSegment::ident("inline").unwrap(),
+            ModSegment::path("/gen/mod.rs"),
+        ]);
+        assert_eq!(
+            ctx.relative_to(Path::new("/src/lib.rs"), true),
+            vec![Path::new("/gen/mod.rs")]
+        );
+    }
+
+    /// Check that a drive-letter `#[path]` is used as-is on Windows.
+    #[cfg(windows)]
+    #[test]
+    fn relative_to_drive_letter_path() {
+        let ctx = ModContext::from(vec![ModSegment::path(r"C:\gen\mod.rs")]);
+        assert_eq!(
+            ctx.relative_to(Path::new(r"D:\src\lib.rs"), true),
+            vec![Path::new(r"C:\gen\mod.rs")]
+        );
+    }
+
     /// Check that a path is honored, but an inner ident still generates multiple possibilities.
     #[test]
     fn relative_to_path_around_ident() {
         let ctx = ModContext::from(vec![
-            ModSegment::new_path("threads"),
-            ModSegment::new_ident("tls"),
+            ModSegment::path("threads"),
+            ModSegment::ident("tls").unwrap(),
         ]);
 
         assert_eq!(
-            ctx.relative_to(&Path::new("/src/lib.rs"), true),
+            ctx.relative_to(Path::new("/src/lib.rs"), true),
             vec![
                 Path::new("/src/threads/tls.rs"),
                 Path::new("/src/threads/tls/mod.rs"),
             ]
         );
     }
+
+    /// Check that a `#[path]` on an inline module sets the directory of the modules declared
+    /// inside it, rather than the directory of the file containing it.
+    #[test]
+    fn relative_to_ident_in_path_module() {
+        let ctx = ModContext::from(vec![
+            ModSegment::path("custom"),
+            ModSegment::ident("inner").unwrap(),
+        ]);
+
+        assert_eq!(
+            ctx.relative_to(Path::new("/src/a/b.rs"), false),
+            vec![
+                Path::new("/src/a/custom/inner.rs"),
+                Path::new("/src/a/custom/inner/mod.rs"),
+            ]
+        );
+
+        let ctx = ModContext::from(vec![
+            ModSegment::ident("outer").unwrap(),
+            ModSegment::path("custom"),
+            ModSegment::ident("inner").unwrap(),
+        ]);
+
+        assert_eq!(
+            ctx.relative_to(Path::new("/src/lib.rs"), true),
+            vec![
+                Path::new("/src/outer/custom/inner.rs"),
+                Path::new("/src/outer/custom/inner/mod.rs"),
+            ]
+        );
+    }
 }
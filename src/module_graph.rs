@@ -0,0 +1,251 @@
+//! Metadata about the modules encountered while inlining.
+
+use std::fmt::{self, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::Duration;
+
+use proc_macro2::Span;
+
+/// The `mod` items encountered while inlining, in the order they were visited.
+///
+/// Modules are recorded depth-first in source order, so a module always appears before
+/// the modules declared inside it, and the `n`th node corresponds to the `n`th `mod` item
+/// found by walking the inlined output in the same order, unless `InlinerBuilder::sort_items`
+/// reordered the output afterwards.
+#[derive(Debug, Clone, Default)]
+pub struct ModuleGraph {
+    modules: Vec<ModuleNode>,
+    pub(crate) root_file: PathBuf,
+    pub(crate) root_line_count: Option<usize>,
+    pub(crate) root_duration: Option<Duration>,
+    pub(crate) root_is_mod_file: bool,
+    /// Each file expanded by `InlinerBuilder::expand_includes`, with the span of one of its
+    /// items. Spans from the same parse of a file can be joined, so this identifies which
+    /// items of the output came from the file.
+    pub(crate) includes: Vec<(PathBuf, Span)>,
+    /// The output the graph was built for, so `InlinerBuilder::inline_incremental` can reuse
+    /// the modules whose files haven't changed.
+    pub(crate) output: Option<Snapshot>,
+}
+
+/// A copy of the output of inlining, shared by the clones of its `ModuleGraph`.
+#[derive(Clone)]
+pub(crate) struct Snapshot(pub Rc<syn::File>);
+
+impl fmt::Debug for Snapshot {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Snapshot")
+    }
+}
+
+impl ModuleGraph {
+    /// All the modules in the graph, in visit order.
+    pub fn modules(&self) -> &[ModuleNode] {
+        &self.modules
+    }
+
+    /// Get the module at `index` in visit order.
+    pub fn get(&self, index: usize) -> Option<&ModuleNode> {
+        self.modules.get(index)
+    }
+
+    /// Get the modules declared directly inside the module at `index`, or the modules declared
+    /// at the top level of the root file if `index` is `None`.
+    pub fn children(&self, index: Option<usize>) -> impl Iterator<Item = &ModuleNode> {
+        self.modules.iter().filter(move |m| m.parent == index)
+    }
+
+    /// The root file that inlining started from.
+    pub fn root_file(&self) -> &Path {
+        &self.root_file
+    }
+
+    /// The number of lines in the root file, if the resolver provided its source.
+    pub fn root_line_count(&self) -> Option<usize> {
+        self.root_line_count
+    }
+
+    /// How long it took to load and parse the root file, if `InlinerBuilder::time` was
+    /// enabled.
+    pub fn root_duration(&self) -> Option<Duration> {
+        self.root_duration
+    }
+
+    /// The file expanded by `InlinerBuilder::expand_includes` that `span` came from, if any.
+    pub(crate) fn included_file(&self, span: Span) -> Option<&Path> {
+        self.includes
+            .iter()
+            .find(|(_, included)| included.join(span).is_some())
+            .map(|(path, _)| path.as_path())
+    }
+
+    /// The output the graph was built for, if it came from an `InliningResult`.
+    pub(crate) fn output(&self) -> Option<&syn::File> {
+        self.output.as_ref().map(|snapshot| &*snapshot.0)
+    }
+
+    /// Whether the graph contains any modules.
+    pub fn is_empty(&self) -> bool {
+        self.modules.is_empty()
+    }
+
+    /// The number of modules in the graph.
+    pub fn len(&self) -> usize {
+        self.modules.len()
+    }
+
+    /// Add a module to the graph, returning its index.
+    pub(crate) fn push(&mut self, node: ModuleNode) -> usize {
+        self.modules.push(node);
+        self.modules.len() - 1
+    }
+
+    pub(crate) fn get_mut(&mut self, index: usize) -> &mut ModuleNode {
+        &mut self.modules[index]
+    }
+
+    /// Render the graph in Graphviz DOT format, with an edge from each module to the modules
+    /// declared inside it.
+    ///
+    /// Each node is labeled with the module's logical path and, for modules loaded from a
+    /// file, the file's path. Modules loaded from files are drawn as boxes, inline modules as
+    /// ellipses, and modules that couldn't be inlined as dashed red boxes.
+    pub(crate) fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph modules {\n");
+        let _ = writeln!(
+            dot,
+            "    root [label=\"{}\", shape=box];",
+            dot_label("crate", Some(&self.root_file))
+        );
+
+        for (index, node) in self.modules.iter().enumerate() {
+            let name = format!("crate::{}", node.module_path.join("::"));
+            let attrs = match &node.file {
+                Some(_) => "shape=box",
+                None if node.inline => "shape=ellipse",
+                None => "shape=box, style=dashed, color=red",
+            };
+            let _ = writeln!(
+                dot,
+                "    m{} [label=\"{}\", {}];",
+                index,
+                dot_label(&name, node.file.as_deref()),
+                attrs
+            );
+        }
+
+        for (index, node) in self.modules.iter().enumerate() {
+            let _ = match node.parent {
+                Some(parent) => writeln!(dot, "    m{} -> m{};", parent, index),
+                None => writeln!(dot, "    root -> m{};", index),
+            };
+        }
+
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+/// The escaped text of a DOT label showing `name` and, on a second line, `file`.
+fn dot_label(name: &str, file: Option<&Path>) -> String {
+    let escape = |text: &str| text.replace('\\', "\\\\").replace('"', "\\\"");
+    match file {
+        Some(file) => format!("{}\\n{}", escape(name), escape(&file.display().to_string())),
+        None => escape(name),
+    }
+}
+
+/// A single `mod` item encountered while inlining.
+#[derive(Debug, Clone)]
+pub struct ModuleNode {
+    pub(crate) module_path: Vec<String>,
+    pub(crate) parent: Option<usize>,
+    pub(crate) declaration_index: usize,
+    pub(crate) declared_in: PathBuf,
+    pub(crate) inline: bool,
+    pub(crate) file: Option<PathBuf>,
+    pub(crate) line_count: Option<usize>,
+    pub(crate) duration: Option<Duration>,
+    pub(crate) content_line: Option<usize>,
+    pub(crate) span: Span,
+    pub(crate) resolution: ResolutionKind,
+}
+
+impl ModuleNode {
+    /// The logical path of the module relative to the crate root, e.g. `["foo", "bar"]` for
+    /// `crate::foo::bar`.
+    pub fn module_path(&self) -> &[String] {
+        &self.module_path
+    }
+
+    /// The index in the graph of the module this one was declared in, or `None` if it was
+    /// declared at the top level of the root file.
+    pub fn parent(&self) -> Option<usize> {
+        self.parent
+    }
+
+    /// The position of this module among the modules declared directly in its parent, in
+    /// source order.
+    pub fn declaration_index(&self) -> usize {
+        self.declaration_index
+    }
+
+    /// The file containing the `mod` item.
+    pub fn declared_in(&self) -> &Path {
+        &self.declared_in
+    }
+
+    /// Whether the module's content was written inline as `mod foo { ... }`.
+    pub fn is_inline(&self) -> bool {
+        self.inline
+    }
+
+    /// How the file for this module was found.
+    pub fn resolution(&self) -> ResolutionKind {
+        self.resolution
+    }
+
+    /// The file whose content was inlined into this module. `None` for inline modules and
+    /// for modules that could not be inlined.
+    pub fn file(&self) -> Option<&Path> {
+        self.file.as_deref()
+    }
+
+    /// The number of lines in `self.file()`, if the resolver provided its source. Lines
+    /// belonging to modules inlined into that file are not included.
+    pub fn line_count(&self) -> Option<usize> {
+        self.line_count
+    }
+
+    /// The line where the module's content begins in the file it was written in: 1 for a
+    /// module inlined from a file, or the line of the opening brace for an inline module.
+    ///
+    /// Together with the spans of the items in the output, this maps lines of the output
+    /// back to lines of the original files. `None` for modules that couldn't be inlined.
+    pub fn content_line(&self) -> Option<usize> {
+        self.content_line
+    }
+
+    /// How long it took to load and parse `self.file()`, if `InlinerBuilder::time` was
+    /// enabled. Time spent on modules inlined into that file is not included.
+    pub fn duration(&self) -> Option<Duration> {
+        self.duration
+    }
+}
+
+/// How the file for a module was found, as returned by `ModuleNode::resolution`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResolutionKind {
+    /// The module's content was written inline as `mod foo { ... }`, so no file was needed.
+    Inline,
+    /// The file was found from the module's name, such as `foo.rs` or `foo/mod.rs` for
+    /// `mod foo;`.
+    Ident,
+    /// The file was named by a `#[path = "..."]` attribute.
+    Path,
+    /// The file was named by a `path` inside a `#[cfg_attr(..., path = "...")]` attribute.
+    CfgAttr,
+    /// The file was given by `InlinerBuilder::extra_modules`.
+    Extra,
+}
@@ -0,0 +1,80 @@
+//! Loading the files of a crate one module at a time.
+
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+
+use crate::visitor::{DeferredModule, Log, Visitor};
+use crate::{Error, FileResolver, InlinerBuilder, ModContext};
+
+/// An iterator over the file of each module in a crate, as returned by
+/// `InlinerBuilder::iter_modules`.
+///
+/// Each file is loaded when the iterator reaches it, and the files of the modules it declares
+/// are found but not loaded until they are reached in turn.
+pub(crate) struct ModuleIter<'a, R> {
+    options: &'a InlinerBuilder,
+    resolver: R,
+    log: Log,
+    /// The root file, until it has been loaded.
+    root: Option<PathBuf>,
+    /// The modules still to be loaded, with the context of the module that declared each, in
+    /// reverse order.
+    pending: Vec<(ModContext, DeferredModule)>,
+    /// Errors found in the last file that was loaded, to be returned before the next file.
+    errors: VecDeque<Error>,
+}
+
+impl<'a, R: FileResolver> ModuleIter<'a, R> {
+    pub fn new(options: &'a InlinerBuilder, root: &Path, resolver: R) -> Self {
+        Self {
+            options,
+            resolver,
+            log: Log::default(),
+            root: Some(root.to_path_buf()),
+            pending: vec![],
+            errors: VecDeque::new(),
+        }
+    }
+}
+
+impl<R: FileResolver> Iterator for ModuleIter<'_, R> {
+    type Item = Result<(ModContext, syn::File), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(err) = self.errors.pop_front() {
+            return Some(Err(err));
+        }
+
+        let (context, file, deferred) = if let Some(root) = self.root.take() {
+            let mut visitor = Visitor::new(
+                &root,
+                self.options.root,
+                self.options,
+                &mut self.log,
+                &mut self.resolver,
+            );
+            visitor.deferred = Some(vec![]);
+            let file = visitor.visit();
+            (ModContext::default(), file, visitor.deferred)
+        } else {
+            let (parent, module) = self.pending.pop()?;
+            let mut visitor =
+                Visitor::for_deferred(&module, self.options, &mut self.log, &mut self.resolver);
+            let file = visitor.visit();
+            (parent.join(&module.context), file, visitor.deferred)
+        };
+
+        let file = match file {
+            Ok(file) => file,
+            Err(err) => return Some(Err(err)),
+        };
+
+        for module in deferred.unwrap_or_default().into_iter().rev() {
+            self.pending.push((context.clone(), module));
+        }
+        self.errors
+            .extend(self.log.errors.drain(..).map(|error| error.kind));
+
+        Some(Ok((context, file)))
+    }
+}
@@ -0,0 +1,66 @@
+//! A dry run of inlining that reports what would be expanded.
+
+use std::path::{Path, PathBuf};
+
+use proc_macro2::Span;
+
+use crate::ModuleGraph;
+
+/// A `mod` item that would be replaced by the content of a file, as returned by
+/// `InlinerBuilder::plan`.
+#[derive(Debug, Clone)]
+pub struct InlinePlan {
+    module_path: Vec<String>,
+    declared_in: PathBuf,
+    span: Span,
+    in_inline_module: bool,
+    file: PathBuf,
+}
+
+impl InlinePlan {
+    pub(crate) fn from_graph(graph: &ModuleGraph) -> Vec<Self> {
+        graph
+            .modules()
+            .iter()
+            .filter_map(|node| {
+                Some(InlinePlan {
+                    module_path: node.module_path().to_vec(),
+                    declared_in: node.declared_in().to_path_buf(),
+                    span: node.span,
+                    in_inline_module: node
+                        .parent()
+                        .and_then(|parent| graph.get(parent))
+                        .is_some_and(|parent| parent.is_inline()),
+                    file: node.file()?.to_path_buf(),
+                })
+            })
+            .collect()
+    }
+
+    /// The logical path of the module relative to the crate root, e.g. `["foo", "bar"]` for
+    /// `crate::foo::bar`.
+    pub fn module_path(&self) -> &[String] {
+        &self.module_path
+    }
+
+    /// The file containing the `mod` item.
+    pub fn declared_in(&self) -> &Path {
+        &self.declared_in
+    }
+
+    /// The `Span` of the `mod` item in `self.declared_in()`.
+    pub fn span(&self) -> Span {
+        self.span
+    }
+
+    /// Whether the `mod` item is inside a module written inline as `mod foo { ... }`, which
+    /// would be kept while the item itself is replaced.
+    pub fn in_inline_module(&self) -> bool {
+        self.in_inline_module
+    }
+
+    /// The file whose content would replace the `mod` item.
+    pub fn file(&self) -> &Path {
+        &self.file
+    }
+}
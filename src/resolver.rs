@@ -1,8 +1,25 @@
-use crate::Error;
-use std::path::Path;
+use crate::{normalize, Error};
+use std::path::{Path, PathBuf};
+use std::{fmt, io};
+
+// `parse_source` needs `syn`'s `full` and `parsing` features, which are enabled in this
+// crate's manifest. Naming `syn::parse_file` here fails the build early if they aren't.
+const _: fn(&str) -> syn::Result<syn::File> = syn::parse_file;
+
+/// Parse source code into a file, as the inliner does unless a resolver overrides
+/// `FileResolver::parse`.
+///
+/// This uses `syn::parse_file`, which requires `syn`'s `full` and `parsing` features. Custom
+/// resolvers can call this from `FileResolver::resolve` to parse files the same way.
+pub fn parse_source(src: &str) -> Result<syn::File, Error> {
+    Ok(syn::parse_file(src)?)
+}
 
 /// A resolver that can turn paths into `syn::File` instances.
-pub(crate) trait FileResolver {
+///
+/// Implement this to inline modules from somewhere other than the file system, such as
+/// memory or a version control system.
+pub trait FileResolver {
     /// Check if `path` exists in the backing data store.
     fn path_exists(&self, path: &Path) -> bool;
 
@@ -10,16 +27,260 @@ pub(crate) trait FileResolver {
     ///
     /// Returns an error if the file couldn't be loaded or parsed as valid Rust.
     fn resolve(&mut self, path: &Path) -> Result<syn::File, Error>;
+
+    /// Reads the source code at `path`, for resolvers that are backed by source text.
+    ///
+    /// When this returns `Some`, the inliner parses the source itself instead of calling
+    /// `resolve`, so it can record information about the source such as its line count.
+    fn load(&mut self, _path: &Path) -> Option<Result<String, Error>> {
+        None
+    }
+
+    /// Parses source code returned by `load` into a file.
+    ///
+    /// Every file the inliner parses itself, including files named by `include!`, goes
+    /// through this method, so overriding it replaces the parser for all of them. Defaults to
+    /// `parse_source`.
+    fn parse(&self, src: &str) -> Result<syn::File, Error> {
+        parse_source(src)
+    }
+
+    /// Gets the canonical form of `path`, so paths that lead to the same file compare equal.
+    ///
+    /// Used to detect modules that include themselves or each other. Defaults to removing
+    /// `.` and `..` components without consulting the backing data store, which can't see
+    /// through symbolic links.
+    fn canonicalize(&self, path: &Path) -> PathBuf {
+        normalize(path)
+    }
+
+    /// Lists the paths of the files and directories directly inside `dir`.
+    ///
+    /// Used by features that discover files rather than following `mod` items, such as
+    /// suggestions for missing modules. Returns an error if `dir` is not a directory, and by
+    /// default returns an `Unsupported` error for every directory.
+    fn read_dir(&self, dir: &Path) -> io::Result<Vec<PathBuf>> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!("cannot list {}", dir.display()),
+        ))
+    }
+}
+
+impl<R: FileResolver + ?Sized> FileResolver for Box<R> {
+    fn path_exists(&self, path: &Path) -> bool {
+        (**self).path_exists(path)
+    }
+
+    fn resolve(&mut self, path: &Path) -> Result<syn::File, Error> {
+        (**self).resolve(path)
+    }
+
+    fn load(&mut self, path: &Path) -> Option<Result<String, Error>> {
+        (**self).load(path)
+    }
+
+    fn parse(&self, src: &str) -> Result<syn::File, Error> {
+        (**self).parse(src)
+    }
+
+    fn canonicalize(&self, path: &Path) -> PathBuf {
+        (**self).canonicalize(path)
+    }
+
+    fn read_dir(&self, dir: &Path) -> io::Result<Vec<PathBuf>> {
+        (**self).read_dir(dir)
+    }
+}
+
+/// A resolver that consults several other resolvers in priority order.
+///
+/// Each path is loaded from the first resolver in which it exists, so earlier resolvers
+/// override later ones. Source is parsed by the resolver that loaded it most recently, or by
+/// the first resolver if nothing has been loaded yet.
+#[derive(Default)]
+pub struct ChainResolver {
+    resolvers: Vec<Box<dyn FileResolver>>,
+    /// The index of the resolver that loaded the last path.
+    served: usize,
+}
+
+impl ChainResolver {
+    /// Create a resolver that consults `resolvers` in order.
+    pub fn new(resolvers: Vec<Box<dyn FileResolver>>) -> Self {
+        Self {
+            resolvers,
+            served: 0,
+        }
+    }
+
+    /// Add a resolver with lower priority than all the resolvers added so far.
+    pub fn push(&mut self, resolver: impl FileResolver + 'static) -> &mut Self {
+        self.resolvers.push(Box::new(resolver));
+        self
+    }
+
+    /// Find the first resolver in which `path` exists, and remember it as the resolver serving
+    /// the source that is parsed next.
+    fn find(&mut self, path: &Path) -> Result<&mut Box<dyn FileResolver>, Error> {
+        let index = self
+            .resolvers
+            .iter()
+            .position(|resolver| resolver.path_exists(path))
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("{} not found in any resolver", path.display()),
+                )
+            })?;
+        self.served = index;
+        Ok(&mut self.resolvers[index])
+    }
+}
+
+impl fmt::Debug for ChainResolver {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ChainResolver")
+            .field("resolvers", &self.resolvers.len())
+            .finish()
+    }
+}
+
+impl FileResolver for ChainResolver {
+    fn path_exists(&self, path: &Path) -> bool {
+        self.resolvers
+            .iter()
+            .any(|resolver| resolver.path_exists(path))
+    }
+
+    fn resolve(&mut self, path: &Path) -> Result<syn::File, Error> {
+        self.find(path)?.resolve(path)
+    }
+
+    fn load(&mut self, path: &Path) -> Option<Result<String, Error>> {
+        match self.find(path) {
+            Ok(resolver) => resolver.load(path),
+            Err(err) => Some(Err(err)),
+        }
+    }
+
+    fn parse(&self, src: &str) -> Result<syn::File, Error> {
+        match self.resolvers.get(self.served) {
+            Some(resolver) => resolver.parse(src),
+            None => parse_source(src),
+        }
+    }
+
+    /// Uses the first resolver in which `path` exists.
+    fn canonicalize(&self, path: &Path) -> PathBuf {
+        match self
+            .resolvers
+            .iter()
+            .find(|resolver| resolver.path_exists(path))
+        {
+            Some(resolver) => resolver.canonicalize(path),
+            None => normalize(path),
+        }
+    }
+
+    /// Lists the entries of `dir` in every resolver that can list it, without duplicates.
+    fn read_dir(&self, dir: &Path) -> io::Result<Vec<PathBuf>> {
+        let mut entries = None::<Vec<PathBuf>>;
+        let mut last_err = io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("{} not found in any resolver", dir.display()),
+        );
+        for resolver in &self.resolvers {
+            match resolver.read_dir(dir) {
+                Ok(found) => {
+                    let entries = entries.get_or_insert_with(Vec::new);
+                    for entry in found {
+                        if !entries.contains(&entry) {
+                            entries.push(entry);
+                        }
+                    }
+                }
+                Err(err) => last_err = err,
+            }
+        }
+
+        entries.ok_or(last_err)
+    }
 }
 
 #[derive(Clone)]
 pub(crate) struct FsResolver<F> {
     on_load: F,
+    max_bytes: Option<usize>,
+    #[cfg(feature = "encoding_rs")]
+    encoding: Option<&'static encoding_rs::Encoding>,
 }
 
 impl<F> FsResolver<F> {
     pub(crate) fn new(on_load: F) -> Self {
-        Self { on_load }
+        Self {
+            on_load,
+            max_bytes: None,
+            #[cfg(feature = "encoding_rs")]
+            encoding: None,
+        }
+    }
+
+    /// Refuse to read files larger than `max_bytes`.
+    pub(crate) fn with_max_bytes(mut self, max_bytes: Option<usize>) -> Self {
+        self.max_bytes = max_bytes;
+        self
+    }
+
+    /// Check the size of the file at `path` against the limit before it is read.
+    fn check_size(&self, path: &Path) -> Result<(), Error> {
+        let max = match self.max_bytes {
+            Some(max) => max as u64,
+            None => return Ok(()),
+        };
+
+        let size = std::fs::metadata(path)?.len();
+        if size > max {
+            return Err(Error::FileTooLarge { size, max });
+        }
+
+        Ok(())
+    }
+
+    /// Decode files from `encoding` rather than UTF-8.
+    #[cfg(feature = "encoding_rs")]
+    pub(crate) fn with_encoding(
+        mut self,
+        encoding: Option<&'static encoding_rs::Encoding>,
+    ) -> Self {
+        self.encoding = encoding;
+        self
+    }
+
+    /// Read the file at `path` into a string.
+    #[cfg(not(feature = "encoding_rs"))]
+    fn read(&self, path: &Path) -> io::Result<String> {
+        std::fs::read_to_string(path)
+    }
+
+    /// Read the file at `path` into a string, decoding it if an encoding was set.
+    #[cfg(feature = "encoding_rs")]
+    fn read(&self, path: &Path) -> io::Result<String> {
+        let encoding = match self.encoding {
+            Some(encoding) => encoding,
+            None => return std::fs::read_to_string(path),
+        };
+
+        let bytes = std::fs::read(path)?;
+        encoding
+            .decode_without_bom_handling_and_without_replacement(&bytes)
+            .map(|src| src.into_owned())
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{} is not valid {}", path.display(), encoding.name()),
+                )
+            })
     }
 }
 
@@ -32,11 +293,87 @@ where
     }
 
     fn resolve(&mut self, path: &Path) -> Result<syn::File, Error> {
-        let src = std::fs::read_to_string(path)?;
-        let res = syn::parse_file(&src);
+        self.check_size(path)?;
+        let src = self.read(path)?;
+        let res = parse_source(&src);
         // Call the callback whether the file parsed successfully or not.
         (self.on_load)(path, src);
-        Ok(res?)
+        res
+    }
+
+    fn load(&mut self, path: &Path) -> Option<Result<String, Error>> {
+        if let Err(err) = self.check_size(path) {
+            return Some(Err(err));
+        }
+        let src = match self.read(path) {
+            Ok(src) => src,
+            Err(err) => return Some(Err(err.into())),
+        };
+        // Call the callback before the file is parsed, since it may not parse successfully.
+        (self.on_load)(path, src.clone());
+        Some(Ok(src))
+    }
+
+    /// Resolves symbolic links, falling back to `normalize` for paths that don't exist.
+    fn canonicalize(&self, path: &Path) -> PathBuf {
+        path.canonicalize().unwrap_or_else(|_| normalize(path))
+    }
+
+    fn read_dir(&self, dir: &Path) -> io::Result<Vec<PathBuf>> {
+        std::fs::read_dir(dir)?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect()
+    }
+}
+
+/// A resolver that serves `src` as the file at `path`, and every other file from `inner`.
+pub(crate) struct SourceOverlay<'a, R> {
+    path: PathBuf,
+    src: &'a str,
+    inner: R,
+}
+
+impl<'a, R> SourceOverlay<'a, R> {
+    pub(crate) fn new(path: PathBuf, src: &'a str, inner: R) -> Self {
+        Self { path, src, inner }
+    }
+}
+
+impl<R: FileResolver> FileResolver for SourceOverlay<'_, R> {
+    fn path_exists(&self, path: &Path) -> bool {
+        path == self.path || self.inner.path_exists(path)
+    }
+
+    fn resolve(&mut self, path: &Path) -> Result<syn::File, Error> {
+        if path == self.path {
+            self.inner.parse(self.src)
+        } else {
+            self.inner.resolve(path)
+        }
+    }
+
+    fn load(&mut self, path: &Path) -> Option<Result<String, Error>> {
+        if path == self.path {
+            Some(Ok(self.src.to_string()))
+        } else {
+            self.inner.load(path)
+        }
+    }
+
+    fn parse(&self, src: &str) -> Result<syn::File, Error> {
+        self.inner.parse(src)
+    }
+
+    fn canonicalize(&self, path: &Path) -> PathBuf {
+        if path == self.path {
+            normalize(path)
+        } else {
+            self.inner.canonicalize(path)
+        }
+    }
+
+    fn read_dir(&self, dir: &Path) -> io::Result<Vec<PathBuf>> {
+        self.inner.read_dir(dir)
     }
 }
 
@@ -45,30 +382,72 @@ where
 #[derive(Default, Clone)]
 pub(crate) struct TestResolver {
     files: std::collections::HashMap<std::path::PathBuf, String>,
+    dirs: bool,
 }
 
 #[cfg(test)]
 impl TestResolver {
+    /// Create a resolver where the directories containing registered files also exist.
+    pub fn with_dirs() -> Self {
+        Self {
+            dirs: true,
+            ..Self::default()
+        }
+    }
+
     pub fn register(&mut self, path: &'static str, contents: &'static str) {
-        self.files
-            .insert(Path::new(path).to_path_buf(), contents.into());
+        self.register_path(path, contents);
+    }
+
+    /// Register a file at a path that may not be valid UTF-8.
+    pub fn register_path(&mut self, path: impl Into<PathBuf>, contents: &'static str) {
+        self.files.insert(path.into(), contents.into());
     }
 }
 
 #[cfg(test)]
 impl FileResolver for TestResolver {
+    /// Registered files exist, as do the directories containing them if the resolver was
+    /// created with `with_dirs`.
     fn path_exists(&self, path: &Path) -> bool {
         self.files.contains_key(path)
+            || (self.dirs && self.files.keys().any(|file| file.starts_with(path)))
     }
 
     fn resolve(&mut self, path: &Path) -> Result<syn::File, Error> {
-        let src = self.files.get(path).ok_or_else(|| {
+        parse_source(&self.load(path).unwrap()?)
+    }
+
+    fn load(&mut self, path: &Path) -> Option<Result<String, Error>> {
+        Some(self.files.get(path).cloned().ok_or_else(|| {
             std::io::Error::new(
                 std::io::ErrorKind::NotFound,
                 "path not in test resolver hashmap",
             )
-        })?;
-        Ok(syn::parse_file(src)?)
+            .into()
+        }))
+    }
+
+    /// Lists the registered files and the directories containing them.
+    fn read_dir(&self, dir: &Path) -> io::Result<Vec<PathBuf>> {
+        let mut entries = self
+            .files
+            .keys()
+            .filter_map(|file| file.strip_prefix(dir).ok())
+            .filter_map(|rest| rest.components().next())
+            .map(|first| dir.join(first))
+            .collect::<Vec<_>>();
+        entries.sort();
+        entries.dedup();
+
+        if entries.is_empty() {
+            Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                "directory not in test resolver hashmap",
+            ))
+        } else {
+            Ok(entries)
+        }
     }
 }
 
@@ -90,3 +469,97 @@ impl FileResolver for PathCommentResolver {
         ))?)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use quote::{quote, ToTokens};
+    use std::path::Path;
+
+    use super::{parse_source, ChainResolver, FileResolver, TestResolver};
+    use crate::{Error, InlinerBuilder};
+
+    #[test]
+    fn chain_priority() {
+        let mut overlay = TestResolver::default();
+        overlay.register("src/lib.rs", "struct Overlay;");
+        let mut base = TestResolver::default();
+        base.register("src/lib.rs", "struct Base;");
+        base.register("src/util.rs", "struct Util;");
+
+        let mut chain = ChainResolver::default();
+        chain.push(overlay).push(base);
+
+        assert_eq!(
+            chain.load(Path::new("src/lib.rs")).unwrap().unwrap(),
+            "struct Overlay;"
+        );
+        assert!(chain.resolve(Path::new("src/util.rs")).is_ok());
+        assert!(!chain.path_exists(Path::new("src/missing.rs")));
+        assert!(chain.resolve(Path::new("src/missing.rs")).is_err());
+    }
+
+    #[test]
+    fn chain_read_dir() {
+        let mut overlay = TestResolver::default();
+        overlay.register("src/lib.rs", "mod a;");
+        let mut base = TestResolver::default();
+        base.register("src/lib.rs", "");
+        base.register("src/a/mod.rs", "");
+
+        let mut chain = ChainResolver::default();
+        chain.push(overlay).push(base);
+
+        assert_eq!(
+            chain.read_dir(Path::new("src")).unwrap(),
+            vec![Path::new("src/lib.rs"), Path::new("src/a")]
+        );
+        assert!(chain.read_dir(Path::new("src/lib.rs")).is_err());
+        assert!(ChainResolver::default().read_dir(Path::new("src")).is_err());
+    }
+
+    /// A resolver that parses every file as a single struct named after the file's source.
+    struct NamingResolver(TestResolver);
+
+    impl FileResolver for NamingResolver {
+        fn path_exists(&self, path: &Path) -> bool {
+            self.0.path_exists(path)
+        }
+
+        fn resolve(&mut self, path: &Path) -> Result<syn::File, Error> {
+            self.0.resolve(path)
+        }
+
+        fn load(&mut self, path: &Path) -> Option<Result<String, Error>> {
+            self.0.load(path)
+        }
+
+        fn parse(&self, src: &str) -> Result<syn::File, Error> {
+            parse_source(&format!("struct {};", src))
+        }
+    }
+
+    #[test]
+    fn chain_parse() {
+        let mut overlay = TestResolver::default();
+        overlay.register("src/lib.rs", "mod a;");
+        let mut base = TestResolver::default();
+        base.register("src/a.rs", "Named");
+
+        let mut chain = ChainResolver::default();
+        chain.push(overlay).push(NamingResolver(base));
+
+        let result = InlinerBuilder::default()
+            .parse_and_inline_modules_with_resolver(Path::new("src/lib.rs"), &mut chain)
+            .unwrap();
+        assert!(!result.has_errors());
+        assert_eq!(
+            result.output().to_token_stream().to_string(),
+            quote!(
+                mod a {
+                    struct Named;
+                }
+            )
+            .to_string()
+        );
+    }
+}
@@ -0,0 +1,374 @@
+//! Mapping items in the inlined output back to the files they came from.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use proc_macro2::{LineColumn, Span};
+use syn::spanned::Spanned;
+use syn::visit::Visit;
+use syn::{Block, Item, ItemMod};
+
+use crate::{ModuleGraph, ModuleNode};
+
+/// Get the span, origin file and byte offset within that file of every item declared
+/// directly in a module of `output`, in source order.
+pub(crate) fn offset_map(output: &syn::File, graph: &ModuleGraph) -> Vec<(Span, PathBuf, usize)> {
+    collect(output, graph)
+        .into_iter()
+        .map(|origin| {
+            let offset = origin.span.byte_range().start;
+            (origin.span, origin.file, offset)
+        })
+        .collect()
+}
+
+/// Get the file, module path and position of every item declared directly in a module of
+/// `output`, in source order.
+pub(crate) fn span_table(output: &syn::File, graph: &ModuleGraph) -> SpanTable {
+    let entries = collect(output, graph)
+        .into_iter()
+        .map(|origin| {
+            let start = origin.span.start();
+            SpanEntry {
+                file: origin.file,
+                module_path: origin.module_path,
+                line: start.line,
+                column: start.column,
+            }
+        })
+        .collect();
+    SpanTable { entries }
+}
+
+/// Get the origin of every item declared directly in a module of `output`, in source order.
+pub(crate) fn provenance_map(output: &syn::File, graph: &ModuleGraph) -> ProvenanceMap {
+    ProvenanceMap {
+        origins: collect(output, graph),
+    }
+}
+
+/// Get the origin of every item declared directly in a module of `output`, for translating
+/// positions within those items.
+pub(crate) fn source_map(output: &syn::File, graph: &ModuleGraph) -> SourceMap {
+    SourceMap {
+        provenance: provenance_map(output, graph),
+    }
+}
+
+fn collect(output: &syn::File, graph: &ModuleGraph) -> Vec<ItemOrigin> {
+    let mut collector = OffsetCollector {
+        graph,
+        files: vec![graph.root_file()],
+        module_path: vec![],
+        seen: HashMap::new(),
+        in_block: false,
+        origins: vec![],
+    };
+    collector.visit_file(output);
+    collector.origins
+}
+
+/// Where an item in the output came from.
+#[derive(Debug, Clone)]
+struct ItemOrigin {
+    span: Span,
+    file: PathBuf,
+    module_path: Vec<String>,
+}
+
+/// Walks the output in the same order as the inliner, tracking which file each module's
+/// content came from.
+struct OffsetCollector<'a> {
+    graph: &'a ModuleGraph,
+    /// The stack of files containing the module the collector is in.
+    files: Vec<&'a Path>,
+    /// The path of the module the collector is in.
+    module_path: Vec<String>,
+    /// The number of modules visited so far with each logical path, to find the node in
+    /// `graph` for the next module with that path.
+    seen: HashMap<Vec<String>, usize>,
+    /// Whether the collector is inside a block, where items are not recorded.
+    in_block: bool,
+    origins: Vec<ItemOrigin>,
+}
+
+impl<'a> OffsetCollector<'a> {
+    /// The file that the item with `span` was written in: the file it was included from, or
+    /// else the file of the module the collector is in.
+    fn file_of(&self, span: Span) -> &'a Path {
+        self.graph
+            .included_file(span)
+            .unwrap_or_else(|| self.files.last().expect("root file should be on the stack"))
+    }
+}
+
+impl<'ast> Visit<'ast> for OffsetCollector<'_> {
+    fn visit_item(&mut self, i: &'ast Item) {
+        if !self.in_block {
+            let file = self.file_of(i.span());
+            self.origins.push(ItemOrigin {
+                span: i.span(),
+                file: file.to_path_buf(),
+                module_path: self.module_path.clone(),
+            });
+        }
+
+        syn::visit::visit_item(self, i);
+    }
+
+    fn visit_item_mod(&mut self, i: &'ast ItemMod) {
+        let current = self.file_of(i.span());
+        self.module_path.push(i.ident.to_string());
+
+        // Modules are matched to nodes by path, as `InlinerBuilder::sort_items` may have moved
+        // them after they were visited. Modules sharing a path are matched in order.
+        let seen = self.seen.entry(self.module_path.clone()).or_default();
+        let nth = *seen;
+        *seen += 1;
+        let file = self
+            .graph
+            .modules()
+            .iter()
+            .filter(|node| node.module_path() == &self.module_path[..])
+            .nth(nth)
+            .and_then(ModuleNode::file)
+            .unwrap_or(current);
+
+        self.files.push(file);
+        let in_block = std::mem::replace(&mut self.in_block, false);
+        syn::visit::visit_item_mod(self, i);
+        self.in_block = in_block;
+        self.module_path.pop();
+        self.files.pop();
+    }
+
+    fn visit_block(&mut self, i: &'ast Block) {
+        let in_block = std::mem::replace(&mut self.in_block, true);
+        syn::visit::visit_block(self, i);
+        self.in_block = in_block;
+    }
+}
+
+/// The file and module that every item declared directly in a module of an inlined file came
+/// from.
+///
+/// Items spliced in by `InlinerBuilder::expand_includes` are attributed to the file they were
+/// included from, rather than to the file of their module.
+///
+/// Items are identified by their index in source order: the items of the root file in
+/// order, with the items of each module, inline or inlined from a file, placed directly after
+/// the module item itself. Items inside blocks, such as function bodies, aren't counted. This
+/// index stays valid when the output is moved or printed and parsed again, as long as its
+/// items aren't changed.
+///
+/// An item can also be found by a span from the output, which only works while the output
+/// keeps the spans it was parsed with.
+#[derive(Debug, Clone)]
+pub struct ProvenanceMap {
+    origins: Vec<ItemOrigin>,
+}
+
+impl ProvenanceMap {
+    /// The number of items in the map.
+    pub fn len(&self) -> usize {
+        self.origins.len()
+    }
+
+    /// Whether the output had no items.
+    pub fn is_empty(&self) -> bool {
+        self.origins.is_empty()
+    }
+
+    /// The file the item at `index` was declared in.
+    pub fn file_of(&self, index: usize) -> Option<&Path> {
+        self.origins.get(index).map(|origin| origin.file.as_path())
+    }
+
+    /// The path of the module containing the item at `index`, relative to the root file. This
+    /// is empty for items in the root file.
+    pub fn module_path_of(&self, index: usize) -> Option<&[String]> {
+        self.origins
+            .get(index)
+            .map(|origin| origin.module_path.as_slice())
+    }
+
+    /// The index of the innermost item whose span contains `span`.
+    ///
+    /// Spans from a file inlined into a module are only matched to the items of that file, not
+    /// to the module item itself. Returns `None` if `span` isn't inside any item, or wasn't
+    /// parsed from the same source as the output.
+    pub fn index_of(&self, span: Span) -> Option<usize> {
+        self.origins.iter().rposition(|origin| {
+            origin.span.join(span).is_some()
+                && origin.span.start() <= span.start()
+                && span.end() <= origin.span.end()
+        })
+    }
+}
+
+/// Translates positions in the output back to the files they were written in, using the
+/// origin of each item recorded by a `ProvenanceMap`.
+///
+/// Positions are the `LineColumn`s reported by the spans of the output's tokens. Each file is
+/// parsed separately, so a position is a line and column within the file its token came from,
+/// and translating it means finding that file: the file of the module, the file of an
+/// enclosing module for an inline module, or the file an item was included from. Positions
+/// from output that has been printed and parsed again don't point to the original files, and
+/// should be mapped with a `SpanTable` saved beforehand instead.
+#[derive(Debug, Clone)]
+pub struct SourceMap {
+    provenance: ProvenanceMap,
+}
+
+impl SourceMap {
+    /// Translate `position`, a position inside an item declared directly in the module at
+    /// `module_path`, to the file the item was written in and the position within that file.
+    /// An empty `module_path` is the root file.
+    ///
+    /// Returns `None` if `position` isn't inside any item of the module. If items from more
+    /// than one file contain it, as can happen when items were included with `include!`, the
+    /// last of those items is used.
+    pub fn translate(
+        &self,
+        module_path: &[String],
+        position: LineColumn,
+    ) -> Option<(PathBuf, LineColumn)> {
+        self.provenance
+            .origins
+            .iter()
+            .rev()
+            .find(|origin| {
+                origin.module_path == module_path
+                    && origin.span.start() <= position
+                    && position <= origin.span.end()
+            })
+            .map(|origin| (origin.file.clone(), position))
+    }
+}
+
+/// The origin of every item declared directly in a module of an inlined file, stored as
+/// plain data so it remains valid after the output is printed and parsed again.
+///
+/// Entries are in source order, so the `n`th entry describes the `n`th item reached by
+/// walking the items of the output and of each module in it, skipping items inside blocks.
+///
+/// The table can be written with `Display` and read back with `FromStr`. Each entry is
+/// written on its own line as `line:column`, the module path and the file, separated by tabs.
+/// File paths containing newlines can't be read back.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SpanTable {
+    entries: Vec<SpanEntry>,
+}
+
+impl SpanTable {
+    /// Every entry in the table, in source order.
+    pub fn entries(&self) -> &[SpanEntry] {
+        &self.entries
+    }
+
+    /// The entries for items that came from `file`, in source order.
+    pub fn for_file<'a>(&'a self, file: &'a Path) -> impl Iterator<Item = &'a SpanEntry> + 'a {
+        self.entries.iter().filter(move |entry| entry.file == file)
+    }
+}
+
+impl fmt::Display for SpanTable {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for entry in &self.entries {
+            writeln!(
+                f,
+                "{}:{}\t{}\t{}",
+                entry.line,
+                entry.column,
+                entry.module_path.join("::"),
+                entry.file.display()
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for SpanTable {
+    type Err = SpanTableError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let entries = s
+            .lines()
+            .enumerate()
+            .map(|(index, line)| SpanEntry::parse(line).ok_or(SpanTableError { line: index + 1 }))
+            .collect::<Result<_, _>>()?;
+        Ok(SpanTable { entries })
+    }
+}
+
+/// The origin of a single item in a `SpanTable`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SpanEntry {
+    file: PathBuf,
+    module_path: Vec<String>,
+    line: usize,
+    column: usize,
+}
+
+impl SpanEntry {
+    /// The file the item was declared in.
+    pub fn file(&self) -> &Path {
+        &self.file
+    }
+
+    /// The path of the module containing the item, relative to the root file. This is empty
+    /// for items in the root file.
+    pub fn module_path(&self) -> &[String] {
+        &self.module_path
+    }
+
+    /// The 1-indexed line in `file` where the item starts.
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    /// The 0-indexed column, in UTF-8 characters, in `file` where the item starts.
+    pub fn column(&self) -> usize {
+        self.column
+    }
+
+    fn parse(line: &str) -> Option<Self> {
+        let mut fields = line.splitn(3, '\t');
+        let (line, column) = fields.next()?.split_once(':')?;
+        let module_path = fields.next()?;
+        let file = fields.next()?;
+        Some(SpanEntry {
+            file: PathBuf::from(file),
+            module_path: module_path
+                .split("::")
+                .filter(|segment| !segment.is_empty())
+                .map(String::from)
+                .collect(),
+            line: line.parse().ok()?,
+            column: column.parse().ok()?,
+        })
+    }
+}
+
+/// An error returned when parsing a `SpanTable` from a string that isn't well-formed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SpanTableError {
+    line: usize,
+}
+
+impl SpanTableError {
+    /// The 1-indexed line of the malformed entry.
+    pub fn line(&self) -> usize {
+        self.line
+    }
+}
+
+impl fmt::Display for SpanTableError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "malformed span table entry on line {}", self.line)
+    }
+}
+
+impl std::error::Error for SpanTableError {}
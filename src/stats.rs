@@ -0,0 +1,83 @@
+//! Summary information about the files loaded while inlining.
+
+use std::cmp::Reverse;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::ModuleGraph;
+
+/// Information about each file loaded while inlining, including the root file.
+#[derive(Debug, Clone, Default)]
+pub struct InlineStats {
+    files: Vec<FileStats>,
+}
+
+impl InlineStats {
+    pub(crate) fn new(graph: &ModuleGraph) -> Self {
+        let root = FileStats {
+            path: graph.root_file().to_path_buf(),
+            line_count: graph.root_line_count(),
+            duration: graph.root_duration(),
+        };
+
+        let modules = graph.modules().iter().filter_map(|node| {
+            Some(FileStats {
+                path: node.file()?.to_path_buf(),
+                line_count: node.line_count(),
+                duration: node.duration(),
+            })
+        });
+
+        Self {
+            files: std::iter::once(root).chain(modules).collect(),
+        }
+    }
+
+    /// Every file that was loaded, starting with the root file, in the order they were loaded.
+    pub fn files(&self) -> &[FileStats] {
+        &self.files
+    }
+
+    /// The total number of lines in the files whose line counts are known.
+    pub fn total_line_count(&self) -> usize {
+        self.files.iter().filter_map(FileStats::line_count).sum()
+    }
+
+    /// The `n` files that took longest to load and parse, slowest first. Files without a
+    /// duration are excluded, so this is empty unless `InlinerBuilder::time` was enabled.
+    pub fn slowest(&self, n: usize) -> Vec<&FileStats> {
+        let mut timed = self
+            .files
+            .iter()
+            .filter(|file| file.duration.is_some())
+            .collect::<Vec<_>>();
+        timed.sort_by_key(|file| Reverse(file.duration));
+        timed.truncate(n);
+        timed
+    }
+}
+
+/// Information about a single file loaded while inlining.
+#[derive(Debug, Clone)]
+pub struct FileStats {
+    path: PathBuf,
+    line_count: Option<usize>,
+    duration: Option<Duration>,
+}
+
+impl FileStats {
+    /// The path of the file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The number of lines in the file, if the resolver provided its source.
+    pub fn line_count(&self) -> Option<usize> {
+        self.line_count
+    }
+
+    /// How long it took to load and parse the file, if `InlinerBuilder::time` was enabled.
+    pub fn duration(&self) -> Option<Duration> {
+        self.duration
+    }
+}
@@ -0,0 +1,174 @@
+//! A printable record of how each module was resolved.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use crate::InliningResult;
+
+/// The candidate files of a module, recorded while tracing.
+#[derive(Debug)]
+pub(crate) struct CandidateRecord {
+    /// The index of the module in the module graph.
+    pub index: usize,
+    /// Each candidate file, with whether it existed.
+    pub candidates: Vec<(PathBuf, bool)>,
+    /// The candidate that was chosen.
+    pub chosen: PathBuf,
+}
+
+/// How each module was resolved while inlining, as returned by
+/// `InlinerBuilder::resolve_and_trace`.
+///
+/// The `Display` implementation renders the trace as an indented tree, suitable for pasting
+/// into a bug report.
+#[derive(Debug, Clone)]
+pub struct ResolveTrace {
+    root: PathBuf,
+    modules: Vec<TraceNode>,
+}
+
+impl ResolveTrace {
+    /// Build the trace from the result of inlining and the candidates recorded for each
+    /// module, by the module's index in the module graph.
+    pub(crate) fn new(result: &InliningResult, candidates: Vec<CandidateRecord>) -> Self {
+        let mut candidates = candidates
+            .into_iter()
+            .map(|record| (record.index, (record.candidates, record.chosen)))
+            .collect::<HashMap<_, _>>();
+        let mut errors = result.errors().iter().collect::<Vec<_>>();
+
+        let graph = result.module_graph();
+        let modules = graph
+            .modules()
+            .iter()
+            .enumerate()
+            .map(|(index, node)| {
+                let (candidates, chosen) = candidates
+                    .remove(&index)
+                    .map_or((vec![], None), |(candidates, chosen)| {
+                        (candidates, Some(chosen))
+                    });
+
+                let error = errors
+                    .iter()
+                    .position(|error| error.module_path() == node.module_path())
+                    .filter(|_| !node.is_inline() && node.file().is_none())
+                    .map(|position| errors.remove(position));
+
+                let outcome = if node.is_inline() {
+                    TraceOutcome::Inline
+                } else if let Some(error) = error {
+                    TraceOutcome::Failed(error.kind().detail())
+                } else if let Some(file) = node.file() {
+                    TraceOutcome::Inlined(file.to_path_buf())
+                } else {
+                    TraceOutcome::Empty
+                };
+
+                TraceNode {
+                    module_path: node.module_path().to_vec(),
+                    candidates,
+                    chosen,
+                    outcome,
+                }
+            })
+            .collect();
+
+        ResolveTrace {
+            root: graph.root_file().to_path_buf(),
+            modules,
+        }
+    }
+
+    /// The root file that was inlined.
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Every module that was visited, in the order they were visited, so each module comes
+    /// after its parent.
+    pub fn modules(&self) -> &[TraceNode] {
+        &self.modules
+    }
+}
+
+impl fmt::Display for ResolveTrace {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{}", self.root.display())?;
+        for node in &self.modules {
+            let indent = "  ".repeat(node.module_path.len());
+            let name = node.module_path.last().map_or("", String::as_str);
+            writeln!(f, "{}mod {}: {}", indent, name, node.outcome)?;
+            for (candidate, exists) in &node.candidates {
+                let chosen = node.chosen.as_ref() == Some(candidate);
+                writeln!(
+                    f,
+                    "{}  candidate {} ({}{})",
+                    indent,
+                    candidate.display(),
+                    if *exists { "found" } else { "missing" },
+                    if chosen { ", chosen" } else { "" }
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// How a single module was resolved.
+#[derive(Debug, Clone)]
+pub struct TraceNode {
+    module_path: Vec<String>,
+    candidates: Vec<(PathBuf, bool)>,
+    chosen: Option<PathBuf>,
+    outcome: TraceOutcome,
+}
+
+impl TraceNode {
+    /// The logical path of the module, relative to the root file.
+    pub fn module_path(&self) -> &[String] {
+        &self.module_path
+    }
+
+    /// The files that were considered for the module, with whether each one existed. Empty
+    /// for inline modules.
+    pub fn candidates(&self) -> &[(PathBuf, bool)] {
+        &self.candidates
+    }
+
+    /// The candidate that was loaded, or that would have been loaded if it existed.
+    pub fn chosen(&self) -> Option<&Path> {
+        self.chosen.as_deref()
+    }
+
+    /// What happened to the module.
+    pub fn outcome(&self) -> &TraceOutcome {
+        &self.outcome
+    }
+}
+
+/// What happened to a module while inlining.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TraceOutcome {
+    /// The module was declared with its content, so no file was loaded.
+    Inline,
+    /// The module was inlined from a file.
+    Inlined(PathBuf),
+    /// The module was left empty without loading a file, such as by
+    /// `InlinerBuilder::implicit_dir_modules`.
+    Empty,
+    /// The module couldn't be inlined, for the given reason.
+    Failed(String),
+}
+
+impl fmt::Display for TraceOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TraceOutcome::Inline => write!(f, "inline"),
+            TraceOutcome::Inlined(path) => write!(f, "inlined from {}", path.display()),
+            TraceOutcome::Empty => write!(f, "empty"),
+            TraceOutcome::Failed(reason) => write!(f, "failed: {}", reason),
+        }
+    }
+}
@@ -0,0 +1,303 @@
+//! Checks that inlined modules match the files they were loaded from.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use quote::ToTokens;
+use syn::spanned::Spanned;
+use syn::visit::Visit;
+use syn::{Attribute, Item, ItemMod};
+
+use crate::visitor::include_path;
+use crate::{Error, FileResolver, InliningResult, ModuleGraph, ModuleNode, ResolutionKind};
+
+/// A difference between an inlined module and the file it was loaded from.
+#[derive(Debug)]
+pub struct VerificationError {
+    module_path: Vec<String>,
+    path: PathBuf,
+    kind: VerificationErrorKind,
+}
+
+impl VerificationError {
+    /// The logical path of the module that failed verification.
+    pub fn module_path(&self) -> &[String] {
+        &self.module_path
+    }
+
+    /// The file the module was loaded from.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The difference that was found.
+    pub fn kind(&self) -> &VerificationErrorKind {
+        &self.kind
+    }
+}
+
+impl fmt::Display for VerificationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "module `{}` does not match {}: {}",
+            self.module_path.join("::"),
+            self.path.display(),
+            self.kind
+        )
+    }
+}
+
+/// The ways an inlined module can differ from the file it was loaded from.
+#[derive(Debug)]
+pub enum VerificationErrorKind {
+    /// The file could not be loaded again.
+    Load(Error),
+
+    /// The module could not be found in the output.
+    Missing,
+
+    /// The file's inner attributes are not the last attributes on the module.
+    Attributes,
+
+    /// The module and the file have a different number of items.
+    ItemCount { expected: usize, found: usize },
+
+    /// The item at this index of the module differs from the item in the file.
+    Item(usize),
+}
+
+impl fmt::Display for VerificationErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            VerificationErrorKind::Load(err) => write!(f, "{}", err),
+            VerificationErrorKind::Missing => write!(f, "module missing from output"),
+            VerificationErrorKind::Attributes => write!(f, "attributes differ"),
+            VerificationErrorKind::ItemCount { expected, found } => {
+                write!(f, "expected {} items, found {}", expected, found)
+            }
+            VerificationErrorKind::Item(index) => write!(f, "item {} differs", index),
+        }
+    }
+}
+
+/// Collects every `mod` item in the output with its logical path.
+#[derive(Default)]
+pub(crate) struct ModCollector<'ast> {
+    module_path: Vec<String>,
+    pub mods: Vec<(Vec<String>, &'ast ItemMod)>,
+}
+
+impl<'ast> Visit<'ast> for ModCollector<'ast> {
+    fn visit_item_mod(&mut self, i: &'ast ItemMod) {
+        self.module_path.push(i.ident.to_string());
+        self.mods.push((self.module_path.clone(), i));
+        syn::visit::visit_item_mod(self, i);
+        self.module_path.pop();
+    }
+}
+
+/// Check every module that was loaded from a file in `result` against a fresh copy of that
+/// file from `resolver`.
+///
+/// Modules are matched to their `mod` items in the output by logical path, so modules added,
+/// removed or reordered after inlining don't cause the wrong item to be compared.
+pub(crate) fn verify<R: FileResolver>(
+    result: &InliningResult,
+    resolver: &mut R,
+) -> Result<(), Vec<VerificationError>> {
+    let mut collector = ModCollector::default();
+    collector.visit_file(result.output());
+
+    let graph = result.module_graph();
+    let added = Added {
+        graph,
+        extra_modules: graph
+            .modules()
+            .iter()
+            .filter(|node| node.resolution() == ResolutionKind::Extra)
+            .map(ModuleNode::module_path)
+            .collect(),
+    };
+
+    // Modules with the same path, such as alternatives selected by `cfg`, are matched in order.
+    let mut seen = HashMap::<&[String], usize>::new();
+    let mut errors = vec![];
+    for node in graph.modules() {
+        let occurrence = seen.entry(node.module_path()).or_default();
+        let nth = *occurrence;
+        *occurrence += 1;
+
+        let path = match node.file() {
+            Some(path) => path,
+            None => continue,
+        };
+
+        let item = collector
+            .mods
+            .iter()
+            .filter(|(module_path, _)| module_path == node.module_path())
+            .nth(nth);
+        let kind = match item {
+            Some((module_path, item)) => match resolver.resolve(path) {
+                Ok(file) => compare(item, &file, module_path, &added),
+                Err(err) => Some(VerificationErrorKind::Load(err)),
+            },
+            None => Some(VerificationErrorKind::Missing),
+        };
+
+        if let Some(kind) = kind {
+            errors.push(VerificationError {
+                module_path: node.module_path().to_vec(),
+                path: path.to_path_buf(),
+                kind,
+            });
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+/// Identifies items that the inliner added to a module rather than reading from its file.
+struct Added<'a> {
+    graph: &'a ModuleGraph,
+    /// The paths of the modules added by `InlinerBuilder::extra_modules`.
+    extra_modules: Vec<&'a [String]>,
+}
+
+impl Added<'_> {
+    /// Get the items of a module in the output that correspond to items of `original`,
+    /// leaving out `include!` invocations, the items they expanded to, and modules from
+    /// `InlinerBuilder::extra_modules` that `original` doesn't declare.
+    fn inlined<'i>(
+        &self,
+        items: &'i [Item],
+        original: &[Item],
+        module_path: &[String],
+    ) -> Vec<&'i Item> {
+        items
+            .iter()
+            .filter(|item| {
+                if include_path(item).is_some() || self.graph.included_file(item.span()).is_some() {
+                    return false;
+                }
+
+                match item {
+                    Item::Mod(item) => {
+                        let is_extra = self.extra_modules.iter().any(|extra| {
+                            extra.split_last().is_some_and(|(name, parent)| {
+                                parent == module_path && item.ident == name
+                            })
+                        });
+                        !is_extra
+                            || original
+                                .iter()
+                                .any(|original| matches!(original, Item::Mod(original) if original.ident == item.ident))
+                    }
+                    _ => true,
+                }
+            })
+            .collect()
+    }
+}
+
+/// Get the items of a file or inline module as they were written, leaving out `include!`
+/// invocations, which may have been expanded in the output.
+fn original(items: &[Item]) -> Vec<&Item> {
+    items
+        .iter()
+        .filter(|item| include_path(item).is_none())
+        .collect()
+}
+
+/// Compare an inlined module to the file it was loaded from.
+fn compare(
+    item: &ItemMod,
+    file: &syn::File,
+    module_path: &[String],
+    added: &Added,
+) -> Option<VerificationErrorKind> {
+    if item.attrs.len() < file.attrs.len()
+        || !same_attrs(
+            &item.attrs[item.attrs.len() - file.attrs.len()..],
+            &file.attrs,
+        )
+    {
+        return Some(VerificationErrorKind::Attributes);
+    }
+
+    let items = item
+        .content
+        .as_ref()
+        .map(|(_, items)| &items[..])
+        .unwrap_or_default();
+    let items = added.inlined(items, &file.items, module_path);
+    let original = original(&file.items);
+    if items.len() != original.len() {
+        return Some(VerificationErrorKind::ItemCount {
+            expected: original.len(),
+            found: items.len(),
+        });
+    }
+
+    let mut module_path = module_path.to_vec();
+    items
+        .iter()
+        .zip(&original)
+        .position(|(inlined, original)| !same_item(inlined, original, &mut module_path, added))
+        .map(VerificationErrorKind::Item)
+}
+
+/// Check if an item from the output matches the original item, allowing for `mod foo;`
+/// items, including those nested in inline modules, having been inlined.
+fn same_item(
+    inlined: &Item,
+    original: &Item,
+    module_path: &mut Vec<String>,
+    added: &Added,
+) -> bool {
+    if let (Item::Mod(inlined), Item::Mod(original)) = (inlined, original) {
+        let same_header = inlined.vis.to_token_stream().to_string()
+            == original.vis.to_token_stream().to_string()
+            && inlined.ident == original.ident;
+
+        match (&inlined.content, &original.content) {
+            (Some(_), None) => {
+                return same_header
+                    && inlined.attrs.len() >= original.attrs.len()
+                    && same_attrs(&inlined.attrs[..original.attrs.len()], &original.attrs);
+            }
+            (Some((_, inlined_items)), Some((_, original_items))) => {
+                if !(same_header && same_attrs(&inlined.attrs, &original.attrs)) {
+                    return false;
+                }
+
+                module_path.push(inlined.ident.to_string());
+                let inlined_items = added.inlined(inlined_items, original_items, module_path);
+                let original_items = self::original(original_items);
+                let same = inlined_items.len() == original_items.len()
+                    && inlined_items
+                        .iter()
+                        .zip(&original_items)
+                        .all(|(a, b)| same_item(a, b, module_path, added));
+                module_path.pop();
+                return same;
+            }
+            _ => {}
+        }
+    }
+
+    inlined.to_token_stream().to_string() == original.to_token_stream().to_string()
+}
+
+fn same_attrs(a: &[Attribute], b: &[Attribute]) -> bool {
+    a.len() == b.len()
+        && a.iter()
+            .zip(b)
+            .all(|(a, b)| a.to_token_stream().to_string() == b.to_token_stream().to_string())
+}
@@ -0,0 +1,126 @@
+//! A resolver backed by the `vfs` crate.
+
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+use vfs::error::VfsErrorKind;
+use vfs::{VfsError, VfsPath};
+
+use crate::{parse_source, Error, FileResolver};
+
+/// A resolver that reads files from a `vfs::VfsPath`.
+///
+/// Paths passed to the inliner are interpreted relative to the `VfsPath` the resolver was
+/// created with, including absolute paths. Paths with a Windows prefix, such as `C:`, can't be
+/// represented and are never found.
+#[derive(Debug, Clone)]
+pub struct VfsResolver {
+    root: VfsPath,
+}
+
+impl VfsResolver {
+    /// Create a resolver that reads files relative to `root`.
+    pub fn new(root: VfsPath) -> Self {
+        Self { root }
+    }
+
+    /// Translate a path from the inliner into a path in the virtual file system.
+    fn to_vfs_path(&self, path: &Path) -> Result<VfsPath, VfsError> {
+        let mut segments = vec![];
+        for component in path.components() {
+            match component {
+                Component::Normal(segment) => segments.push(
+                    segment
+                        .to_str()
+                        .ok_or_else(|| VfsError::from(VfsErrorKind::InvalidPath))?,
+                ),
+                Component::ParentDir => segments.push(".."),
+                Component::CurDir | Component::RootDir => {}
+                Component::Prefix(_) => return Err(VfsErrorKind::InvalidPath.into()),
+            }
+        }
+
+        self.root.join(segments.join("/"))
+    }
+}
+
+/// Convert an error from the virtual file system into an I/O error.
+fn to_io_error(err: VfsError) -> io::Error {
+    let kind = match err.kind() {
+        VfsErrorKind::FileNotFound => io::ErrorKind::NotFound,
+        _ => io::ErrorKind::Other,
+    };
+    io::Error::new(kind, err)
+}
+
+impl FileResolver for VfsResolver {
+    fn path_exists(&self, path: &Path) -> bool {
+        self.to_vfs_path(path)
+            .and_then(|path| path.exists())
+            .unwrap_or(false)
+    }
+
+    fn resolve(&mut self, path: &Path) -> Result<syn::File, Error> {
+        parse_source(&self.load(path).expect("load is implemented")?)
+    }
+
+    fn load(&mut self, path: &Path) -> Option<Result<String, Error>> {
+        Some(
+            self.to_vfs_path(path)
+                .and_then(|path| path.read_to_string())
+                .map_err(|err| Error::Io(to_io_error(err))),
+        )
+    }
+
+    fn read_dir(&self, dir: &Path) -> io::Result<Vec<PathBuf>> {
+        let entries = self
+            .to_vfs_path(dir)
+            .and_then(|path| path.read_dir())
+            .map_err(to_io_error)?;
+        Ok(entries.map(|entry| dir.join(entry.filename())).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use std::path::Path;
+
+    use vfs::{MemoryFS, VfsPath};
+
+    use super::VfsResolver;
+    use crate::{FileResolver, InlinerBuilder};
+
+    fn write(root: &VfsPath, path: &str, contents: &str) {
+        let path = root.join(path).unwrap();
+        path.parent().create_dir_all().unwrap();
+        path.create_file()
+            .unwrap()
+            .write_all(contents.as_bytes())
+            .unwrap();
+    }
+
+    #[test]
+    fn inline_from_memory() {
+        let root = VfsPath::new(MemoryFS::new());
+        write(&root, "src/lib.rs", "mod a;");
+        write(&root, "src/a/mod.rs", "mod b;");
+        write(&root, "src/a/b.rs", "struct B;");
+
+        let mut resolver = VfsResolver::new(root);
+        assert!(resolver.path_exists(Path::new("/src/a/../lib.rs")));
+        assert!(!resolver.path_exists(Path::new("src/missing.rs")));
+        let mut entries = resolver.read_dir(Path::new("src/a")).unwrap();
+        entries.sort();
+        assert_eq!(
+            entries,
+            vec![Path::new("src/a/b.rs"), Path::new("src/a/mod.rs")]
+        );
+
+        let result = InlinerBuilder::default()
+            .parse_internal(Path::new("src/lib.rs"), &mut resolver)
+            .unwrap();
+        assert!(!result.has_errors());
+        assert_eq!(result.module_graph().len(), 2);
+    }
+}
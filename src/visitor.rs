@@ -1,23 +1,106 @@
-use std::path::Path;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
 
+use proc_macro2::Span;
+use syn::spanned::Spanned;
 use syn::visit_mut::VisitMut;
-use syn::ItemMod;
+use syn::{parse_quote, Attribute, Ident, Item, ItemMacro, ItemMod, Macro};
 
-use crate::{Error, FileResolver, InlineError, ModContext};
+use crate::annotate::{module_path_annotation, path_annotation};
+use crate::cfg::{expand_cfg_if, is_always_disabled};
+use crate::incremental::Reuse;
+use crate::mod_path::{is_cfg_attr_path, path_value, strip_cfg_attr_path, ModPath, QualifiedPath};
+use crate::trace::CandidateRecord;
+use crate::{
+    normalize, suggest_path, Error, FileResolver, InlineError, InlineWarning, InlinerBuilder,
+    ModContext, ModSegment, ModuleGraph, ModuleNode, ResolutionKind, WarningKind,
+};
+
+/// Information gathered by all the visitors spawned while inlining a single root file.
+#[derive(Debug, Default)]
+pub(crate) struct Log {
+    /// Module items that weren't expanded.
+    pub errors: Vec<InlineError>,
+    /// Code that may have kept items from being inlined.
+    pub warnings: Vec<InlineWarning>,
+    /// Every module item that was visited.
+    pub modules: ModuleGraph,
+    /// The span of every macro invocation that was visited, by the file it appeared in.
+    pub macro_invocations: HashMap<PathBuf, Vec<Span>>,
+    /// The number of items in the files loaded so far, for `max_output_items`.
+    pub output_items: usize,
+    /// The candidate files of each module that was expanded while tracing, with whether each
+    /// existed, and the candidate that was chosen.
+    pub candidates: Vec<CandidateRecord>,
+    /// The source of every file that was loaded and parsed, if `retain_sources` is enabled or
+    /// sources are needed for reporting diagnostics.
+    pub sources: HashMap<PathBuf, String>,
+}
+
+/// A module whose file was found but not loaded, because the visitor is deferring modules.
+#[derive(Debug)]
+pub(crate) struct DeferredModule {
+    /// The file to load the module from.
+    pub path: PathBuf,
+    /// The `mod` items leading to the module from the top of the file that declared it.
+    pub context: ModContext,
+    /// The logical path of the module, relative to the crate root.
+    pub module_path: Vec<String>,
+    /// The index of the module in `log.modules`.
+    pub index: usize,
+    /// The files being inlined above the module, starting with the root file.
+    pub ancestors: Vec<PathBuf>,
+}
 
 pub(crate) struct Visitor<'a, R> {
     /// The current file's path.
     path: &'a Path,
     /// Whether this is the root file or not
     root: bool,
+    /// Whether this file was loaded using a `#[path]` attribute. `rustc` treats these files
+    /// as though they are `mod.rs` files, so their modules are resolved from their directory.
+    loaded_by_path: bool,
+    /// The options controlling how modules are resolved and inlined.
+    options: &'a InlinerBuilder,
     /// The stack of `mod` entries where the visitor is currently located. This is needed
     /// for cases where modules are declared inside inline modules.
     mod_context: ModContext,
+    /// The logical path of the module the visitor is currently in, relative to the crate root.
+    module_path: Vec<String>,
+    /// The files of the visitors that spawned this one, starting with the root file, so
+    /// cycles can be detected.
+    ancestors: Vec<PathBuf>,
+    /// The logical path of the only module to inline, if inlining should stop once it has
+    /// been reached. Modules that aren't on the way to the target are left alone.
+    pub target: Option<&'a [String]>,
+    /// Whether the modules declared inside the target are left alone too, so only the
+    /// modules on the way to the target are visited.
+    pub stop_at_target: bool,
+    /// Whether the content of loaded files should be dropped once their modules have been
+    /// visited, rather than inlined into the output.
+    pub discard_content: bool,
+    /// Whether the candidates of each module should be recorded in `log`.
+    pub trace: bool,
+    /// If set, modules declared without content are left as they are and their files are
+    /// added here to be loaded later, instead of being inlined.
+    pub deferred: Option<Vec<DeferredModule>>,
+    /// The path that the root file's modules are found relative to, in place of the root
+    /// file's own path, for source that wasn't read from a file.
+    pub root_base: Option<&'a Path>,
+    /// The modules of an earlier result that can be copied instead of being inlined again.
+    pub reuse: Option<&'a Reuse<'a>>,
+    /// The index in `log.modules` of the module the visitor is currently in.
+    parent: Option<usize>,
+    /// The number of modules visited so far directly inside `parent`.
+    siblings: usize,
     /// The resolver that can be used to turn paths into `syn::File` instances. This removes
     /// a direct file-system dependency so the expander can be tested.
     resolver: &'a mut R,
-    /// A log of module items that weren't expanded.
-    error_log: Option<&'a mut Vec<InlineError>>,
+    /// The information gathered so far across all visitors.
+    log: &'a mut Log,
 }
 
 impl<'a, R: FileResolver> Visitor<'a, R> {
@@ -26,74 +109,792 @@ impl<'a, R: FileResolver> Visitor<'a, R> {
     pub fn new(
         path: &'a Path,
         root: bool,
-        error_log: Option<&'a mut Vec<InlineError>>,
+        options: &'a InlinerBuilder,
+        log: &'a mut Log,
         resolver: &'a mut R,
     ) -> Self {
         Self {
             path,
             root,
+            loaded_by_path: false,
+            options,
             resolver,
-            error_log,
+            log,
             mod_context: Default::default(),
+            module_path: vec![],
+            ancestors: vec![],
+            target: None,
+            stop_at_target: false,
+            discard_content: false,
+            trace: false,
+            deferred: None,
+            root_base: None,
+            reuse: None,
+            parent: None,
+            siblings: 0,
         }
     }
 
+    /// Create a visitor for the file of a module deferred by another visitor, which defers
+    /// the modules declared in that file in turn.
+    pub fn for_deferred(
+        module: &'a DeferredModule,
+        options: &'a InlinerBuilder,
+        log: &'a mut Log,
+        resolver: &'a mut R,
+    ) -> Self {
+        let mut visitor = Self::new(&module.path, false, options, log, resolver);
+        visitor.loaded_by_path = module.context.last().is_some_and(ModSegment::is_path);
+        visitor.module_path = module.module_path.clone();
+        visitor.ancestors = module.ancestors.clone();
+        visitor.parent = Some(module.index);
+        visitor.deferred = Some(vec![]);
+        visitor
+    }
+
     pub fn visit(&mut self) -> Result<syn::File, Error> {
-        let mut syntax = self.resolver.resolve(self.path)?;
+        let mut syntax = self.load(self.path, self.parent)?;
         self.visit_file_mut(&mut syntax);
         Ok(syntax)
     }
-}
 
-impl<'a, R: FileResolver> VisitMut for Visitor<'a, R> {
-    fn visit_item_mod_mut(&mut self, i: &mut ItemMod) {
-        self.mod_context.push(i.into());
+    /// Load and parse the file at `path`, recording information about it against the module
+    /// at `index`, or the root file if `index` is `None`.
+    fn load(&mut self, path: &Path, index: Option<usize>) -> Result<syn::File, Error> {
+        let start = self.options.time.then(Instant::now);
+        let (file, line_count) = match self.resolver.load(path) {
+            Some(src) => {
+                let src = src?;
+                let file = match self.resolver.parse(&src) {
+                    Ok(file) => file,
+                    Err(Error::Parse(err)) if self.options.tolerant_parse => {
+                        self.parse_prefix(path, &src, &err).ok_or(err)?
+                    }
+                    Err(err) => return Err(err),
+                };
+                let line_count = src.lines().count();
+                if self.options.retain_sources || cfg!(feature = "codespan") {
+                    self.log.sources.insert(self.options.output_path(path), src);
+                }
+                (file, Some(line_count))
+            }
+            None => (self.resolver.resolve(path)?, None),
+        };
+        let duration = start.map(|start| start.elapsed());
 
-        if let Some((_, items)) = &mut i.content {
-            for item in items {
-                self.visit_item_mut(item);
+        match index {
+            Some(index) => {
+                let node = self.log.modules.get_mut(index);
+                node.line_count = line_count;
+                node.duration = duration;
             }
-        } else {
-            // If we find a path that points to a satisfactory file, expand it
-            // and replace the items with the file items. If something goes wrong,
-            // leave the file alone.
+            None => {
+                let graph = &mut self.log.modules;
+                graph.root_file = path.to_path_buf();
+                graph.root_line_count = line_count;
+                graph.root_duration = duration;
+                graph.root_is_mod_file = self.root || path.is_mod_file();
+            }
+        }
 
-            // candidates is guaranteed to be non-empty by ModContext::relative_to.
-            let candidates = self.mod_context.relative_to(self.path, self.root);
+        let items = count_items(&file.items);
+        if index.is_some()
+            && self
+                .options
+                .max_output_items
+                .is_some_and(|max| self.log.output_items + items > max)
+        {
+            return Err(Error::OutputLimit);
+        }
+        self.log.output_items += items;
 
-            // Look for the first candidate file that exists.
-            let first_candidate = candidates
+        Ok(file)
+    }
+
+    /// Parse the longest prefix of `src` that ends before the line where `err` happened and
+    /// is valid Rust, recording a warning if one is found.
+    fn parse_prefix(&mut self, path: &Path, src: &str, err: &syn::Error) -> Option<syn::File> {
+        let lines = src.split_inclusive('\n').collect::<Vec<_>>();
+        let error_line = err.span().start().line.max(1);
+
+        (0..error_line.min(lines.len() + 1)).rev().find_map(|kept| {
+            let file = self.resolver.parse(&lines[..kept].concat()).ok()?;
+            self.log.warnings.push(InlineWarning::new(
+                path,
+                &self.module_path,
+                err.span(),
+                WarningKind::TruncatedParse(kept),
+            ));
+            Some(file)
+        })
+    }
+
+    /// Find the file for a module declared without content and replace the module's items
+    /// with the file's items, returning the path and reason on failure.
+    fn expand(&mut self, i: &mut ItemMod, index: usize) -> Result<(), (PathBuf, Error)> {
+        let candidates = self.candidates()?;
+        let first_candidate = self.choose_candidate(&candidates);
+        if self.trace {
+            let checked = candidates
                 .iter()
-                .find(|p| self.resolver.path_exists(p))
-                .unwrap_or_else(|| {
-                    // If no candidate exists, use the last file (which will error out while
-                    // loading).
-                    candidates
-                        .iter()
-                        .last()
-                        .expect("candidates should be non-empty")
-                });
+                .map(|path| (path.clone(), self.resolver.path_exists(path)))
+                .collect();
+            self.log.candidates.push(CandidateRecord {
+                index,
+                candidates: checked,
+                chosen: first_candidate.clone(),
+            });
+        }
+
+        // Paths are compared in canonical form, so a symbolic link back to a directory that is
+        // already being inlined can't nest the same files forever.
+        let canonical = self.resolver.canonicalize(&first_candidate);
+        if canonical == self.resolver.canonicalize(self.path) {
+            return Err((first_candidate, Error::SelfInclude));
+        }
+
+        let mut ancestors = self.ancestors.clone();
+        ancestors.push(self.path.to_path_buf());
+        if let Some(start) = ancestors
+            .iter()
+            .position(|ancestor| self.resolver.canonicalize(ancestor) == canonical)
+        {
+            let mut chain = ancestors.split_off(start);
+            chain.push(first_candidate.clone());
+            return Err((first_candidate, Error::Cycle(chain)));
+        }
+
+        if self.options.warn_path_mismatch {
+            self.check_path_mismatch(i, &first_candidate);
+        }
+
+        if self.options.implicit_dir_modules && !self.resolver.path_exists(&first_candidate) {
+            if let Some(dir) = self.implicit_dir(&candidates) {
+                if self.resolver.path_exists(&dir) {
+                    i.content = Some((Default::default(), vec![]));
+                    return Ok(());
+                }
+            }
+        }
 
+        if let Some(reuse) = self.reuse {
+            if let Some(prev_index) = reuse.find(&self.module_path, &first_candidate) {
+                reuse.splice(prev_index, i, &mut self.log.modules, index);
+                if let Some((_, items)) = &i.content {
+                    self.log.output_items += count_items(items);
+                }
+                return Ok(());
+            }
+        }
+
+        if let Some(deferred) = &mut self.deferred {
+            deferred.push(DeferredModule {
+                path: first_candidate,
+                context: self.mod_context.clone(),
+                module_path: self.module_path.clone(),
+                index,
+                ancestors,
+            });
+            return Ok(());
+        }
+
+        let file = if self.options.recursive {
             let mut visitor = Visitor::new(
                 &first_candidate,
                 false,
-                self.error_log.as_mut().map(|v| &mut **v),
+                self.options,
+                self.log,
                 self.resolver,
             );
+            visitor.loaded_by_path = self.mod_context.last().is_some_and(ModSegment::is_path);
+            visitor.module_path = self.module_path.clone();
+            visitor.ancestors = ancestors;
+            visitor.target = self.target;
+            visitor.stop_at_target = self.stop_at_target;
+            visitor.discard_content = self.discard_content;
+            visitor.trace = self.trace;
+            visitor.reuse = self.reuse;
+            visitor.parent = Some(index);
+            visitor.visit()
+        } else {
+            self.load(&first_candidate, Some(index))
+        };
+
+        match file {
+            Ok(_) if self.discard_content => {
+                let node = self.log.modules.get_mut(index);
+                node.file = Some(first_candidate);
+                node.content_line = Some(1);
+                Ok(())
+            }
+            Ok(syn::File {
+                mut attrs,
+                mut items,
+                ..
+            }) => {
+                if let Some(filter) = &self.options.attribute_filter {
+                    i.attrs.retain(|attr| (filter.0)(attr));
+                    attrs.retain(|attr| (filter.0)(attr));
+                }
+                if let Some(transform) = &self.options.transform_child {
+                    (transform.0.lock().unwrap())(&first_candidate, &mut items);
+                }
+
+                if self.options.strip_resolved_cfg_attr {
+                    strip_cfg_attr_path(
+                        &mut i.attrs,
+                        self.options.cfg_options.as_ref(),
+                        self.options.replace_resolved_cfg_attr,
+                    );
+                }
+                if self.options.annotate_paths {
+                    let annotated = self.options.output_path(&first_candidate);
+                    match path_annotation(&annotated, self.options.path_encoding) {
+                        Some(annotation) => i.attrs.push(annotation),
+                        None => {
+                            let err = io::Error::new(
+                                io::ErrorKind::InvalidData,
+                                "path can't be annotated because it is not valid UTF-8",
+                            );
+                            return Err((first_candidate, err.into()));
+                        }
+                    }
+                }
+                if self.options.annotate_module_paths {
+                    i.attrs.push(module_path_annotation(&self.module_path));
+                }
+                // The file's inner attributes, such as `//!` docs, follow the declaration's outer
+                // attributes, so both keep their order and are printed on either side of the brace.
+                i.attrs.extend(attrs);
+                i.content = Some((Default::default(), items));
+                let node = self.log.modules.get_mut(index);
+                node.file = Some(first_candidate);
+                node.content_line = Some(1);
+                Ok(())
+            }
+            Err(kind) => Err((first_candidate, kind)),
+        }
+    }
+
+    /// Record a warning if the module at the top of `mod_context` was found using a `#[path]`,
+    /// but a different file exists where it would be found by its ident.
+    fn check_path_mismatch(&mut self, i: &ItemMod, path: &Path) {
+        if !self.mod_context.last().is_some_and(ModSegment::is_path) {
+            return;
+        }
+
+        let mut context = self.mod_context.clone();
+        context.pop();
+        context.push(ModSegment::Ident(i.ident.clone()));
+        let ident_path = context
+            .relative_to(&self.module_base(), self.root || self.loaded_by_path)
+            .into_iter()
+            .find(|candidate| self.resolver.path_exists(candidate));
+
+        if let Some(ident_path) = ident_path {
+            if normalize(&ident_path) != normalize(path) {
+                let options = self.options;
+                self.log.warnings.push(InlineWarning::new(
+                    self.path,
+                    &self.module_path[..self.module_path.len() - 1],
+                    i.ident.span(),
+                    WarningKind::PathMismatch {
+                        path: options.output_path(path),
+                        ident_path: options.output_path(&ident_path),
+                    },
+                ));
+            }
+        }
+    }
+
+    /// Append a `mod` item to `items` for each of `InlinerBuilder::extra_modules` that belongs
+    /// in the current module and isn't already declared there.
+    fn add_extra_modules(&self, items: &mut Vec<Item>) {
+        for (module_path, _) in &self.options.extra_modules {
+            let Some((name, parent)) = module_path.split_last() else {
+                continue;
+            };
+            if *parent != self.module_path[..] {
+                continue;
+            }
+            let Ok(ident) = syn::parse_str::<Ident>(name) else {
+                continue;
+            };
+            let declared = items
+                .iter()
+                .any(|item| matches!(item, Item::Mod(item) if item.ident == ident));
+            if !declared {
+                items.push(parse_quote!(mod #ident;));
+            }
+        }
+    }
+
+    /// Get the path that modules declared in the current file are found relative to: the
+    /// file's own path, unless it's the root file and `root_base` or `InlinerBuilder::base_dir`
+    /// is set.
+    fn module_base(&self) -> Cow<'a, Path> {
+        if let Some(base) = self.root_base.filter(|_| self.ancestors.is_empty()) {
+            return Cow::Borrowed(base);
+        }
+
+        match (&self.options.base_dir, self.path.file_name()) {
+            (Some(dir), Some(name)) if self.ancestors.is_empty() => Cow::Owned(dir.join(name)),
+            _ => Cow::Borrowed(self.path),
+        }
+    }
+
+    /// Get the non-empty list of places the source code of the module at the top of
+    /// `mod_context` may appear.
+    fn candidates(&self) -> Result<Vec<PathBuf>, (PathBuf, Error)> {
+        if let Some((_, path)) = self
+            .options
+            .extra_modules
+            .iter()
+            .find(|(module_path, _)| *module_path == self.module_path)
+        {
+            return Ok(vec![path.clone()]);
+        }
+
+        if let Some(ModSegment::Path(path)) = self.mod_context.last() {
+            if let Some(rest) = self.options.strip_workspace_sentinel(path) {
+                return match &self.options.workspace_root {
+                    Some(root) => Ok(vec![root.join(rest)]),
+                    None => Err((path.clone(), Error::MissingWorkspaceRoot)),
+                };
+            }
+        }
+
+        // candidates is guaranteed to be non-empty by ModContext::relative_to.
+        let mut candidates = match self.qualified_context()? {
+            Some((base, context)) => context.relative_to(&base, true),
+            None => self
+                .mod_context
+                .relative_to(&self.module_base(), self.root || self.loaded_by_path),
+        };
+
+        // Ident-based modules produce `[foo.rs, foo/mod.rs]`; swap them if `mod.rs` is preferred.
+        if self.options.prefer_mod_rs {
+            candidates.reverse();
+        }
+
+        let roots = &self.options.search_roots;
+        if !roots.is_empty() {
+            candidates = candidates
+                .iter()
+                .flat_map(|candidate| {
+                    let relative = roots
+                        .iter()
+                        .find_map(|root| candidate.strip_prefix(root).ok())
+                        .unwrap_or(candidate);
+                    roots.iter().map(move |root| root.join(relative))
+                })
+                .collect();
+        }
+
+        Ok(candidates)
+    }
+
+    /// If the module at the top of `mod_context` has a `#[path]` naming a module such as
+    /// `super::shared`, and `InlinerBuilder::qualified_paths` is enabled, get a file in the
+    /// directory that path starts from and the modules to follow from there.
+    fn qualified_context(&self) -> Result<Option<(PathBuf, ModContext)>, (PathBuf, Error)> {
+        let path = match self.mod_context.last() {
+            Some(ModSegment::Path(path)) if self.options.qualified_paths => path,
+            _ => return Ok(None),
+        };
+        let Some(qualified) = QualifiedPath::parse(path) else {
+            return Ok(None);
+        };
+
+        // Modules declared alongside this one by ident are found in the directory of the
+        // current module, so swap in a placeholder ident to find that directory.
+        let mut context = self.mod_context.clone();
+        context.pop();
+        context.push(ModSegment::Ident(Ident::new("sibling", Span::call_site())));
+        let sibling = context.relative_to(&self.module_base(), self.root || self.loaded_by_path);
+        let mut dir = sibling[0].parent().unwrap_or(Path::new("")).to_path_buf();
+
+        // `module_path` already includes the module being declared.
+        let depth = self.module_path.len() - 1;
+        let ups = qualified.supers.unwrap_or(depth);
+        if ups > depth {
+            let err = io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "`super` goes above the crate root",
+            );
+            return Err((path.clone(), err.into()));
+        }
+        for _ in 0..ups {
+            dir = normalize(&dir.join(".."));
+        }
+
+        let context = qualified
+            .segments
+            .into_iter()
+            .map(ModSegment::Ident)
+            .collect::<Vec<_>>();
+        Ok(Some((dir.join("mod.rs"), context.into())))
+    }
+
+    /// Get the directory named after a module declared by ident, which is the directory of its
+    /// `mod.rs` candidate.
+    fn implicit_dir(&self, candidates: &[PathBuf]) -> Option<PathBuf> {
+        if !self.mod_context.last()?.is_ident() {
+            return None;
+        }
+
+        candidates
+            .iter()
+            .find(|candidate| candidate.ends_with("mod.rs"))
+            .and_then(|candidate| candidate.parent())
+            .map(Path::to_path_buf)
+    }
+
+    /// Pick the file to load for a module from its non-empty list of candidate paths.
+    fn choose_candidate(&mut self, candidates: &[PathBuf]) -> PathBuf {
+        let selected = if let Some(selector) = &self.options.candidate_selector {
+            let existing = candidates
+                .iter()
+                .filter(|p| self.candidate_exists(p))
+                .cloned()
+                .collect::<Vec<_>>();
+
+            if existing.len() > 1 {
+                (selector.0)(&existing).or_else(|| existing.into_iter().next())
+            } else {
+                existing.into_iter().next()
+            }
+        } else {
+            // Look for the first candidate file that exists.
+            candidates
+                .iter()
+                .find(|p| self.candidate_exists(p))
+                .cloned()
+        };
+
+        selected.unwrap_or_else(|| {
+            // If no candidate exists, use the last file (which will error out while
+            // loading).
+            candidates
+                .last()
+                .expect("candidates should be non-empty")
+                .clone()
+        })
+    }
+
+    /// Expand, sort and visit each item in a file or inline module, removing those that
+    /// shouldn't appear in the output.
+    fn visit_items_mut(&mut self, items: &mut Vec<Item>) {
+        if self.options.expand_cfg_if {
+            expand_cfg_if(items, self.options.cfg_options.as_ref());
+        }
+
+        if self.options.expand_includes {
+            let dir = self.path.parent().unwrap_or(Path::new(""));
+            self.expand_includes(items, dir, &mut vec![self.path.to_path_buf()]);
+        }
+
+        self.add_extra_modules(items);
+
+        items.retain_mut(|item| {
+            if self.should_drop(item) {
+                return false;
+            }
+
+            self.visit_item_mut(item);
+            true
+        });
+
+        if let Some(order) = &self.options.sort_items {
+            items.sort_by(|a, b| (order.0)(a, b));
+        }
+    }
+
+    /// Replace each `include!` in `items` with the items of the file it names, relative to
+    /// `dir`. `stack` holds the files being included, so a file can't include itself.
+    fn expand_includes(&mut self, items: &mut Vec<Item>, dir: &Path, stack: &mut Vec<PathBuf>) {
+        for item in std::mem::take(items) {
+            let Some(path) = include_path(&item) else {
+                items.push(item);
+                continue;
+            };
 
-            match visitor.visit() {
-                Ok(syn::File { attrs, items, .. }) => {
-                    i.attrs.extend(attrs);
-                    i.content = Some((Default::default(), items));
+            let path = dir.join(path);
+            let included = if stack.iter().any(|file| normalize(file) == normalize(&path)) {
+                Err(Error::SelfInclude)
+            } else {
+                self.load_include(&path)
+            };
+
+            match included {
+                Ok(mut included) => {
+                    if let Some(first) = included.first() {
+                        self.log.modules.includes.push((path.clone(), first.span()));
+                    }
+                    stack.push(path.clone());
+                    let dir = path.parent().unwrap_or(Path::new(""));
+                    self.expand_includes(&mut included, dir, stack);
+                    stack.pop();
+                    items.extend(included);
+                }
+                Err(err) => {
+                    self.log.warnings.push(InlineWarning::new(
+                        self.path,
+                        &self.module_path,
+                        item.span(),
+                        WarningKind::UnresolvedInclude {
+                            path: self.options.output_path(&path),
+                            reason: err.detail(),
+                        },
+                    ));
+                    items.push(item);
                 }
-                Err(kind) => {
-                    if let Some(ref mut errors) = self.error_log {
-                        errors.push(InlineError::new(self.path, i, first_candidate, kind));
+            }
+        }
+    }
+
+    /// Load and parse the items of a file named by an `include!`.
+    fn load_include(&mut self, path: &Path) -> Result<Vec<Item>, Error> {
+        let file = match self.resolver.load(path) {
+            Some(src) => self.resolver.parse(&src?)?,
+            None => self.resolver.resolve(path)?,
+        };
+        Ok(file.items)
+    }
+
+    /// Check if an item should be removed from the output without being visited.
+    fn should_drop(&self, item: &Item) -> bool {
+        match item {
+            Item::Mod(item) => {
+                (self.options.drop_test_modules && is_cfg_test(&item.attrs))
+                    || match &self.options.cfg_options {
+                        Some(cfg) => !cfg.is_enabled(&item.attrs),
+                        None => is_always_disabled(&item.attrs, &self.options.never_set_cfgs),
                     }
+            }
+            _ => false,
+        }
+    }
+
+    /// Check if a candidate file exists, reporting the check to the `on_candidate` callback.
+    fn candidate_exists(&self, path: &Path) -> bool {
+        let exists = self.resolver.path_exists(path);
+        if let Some(on_candidate) = &self.options.on_candidate {
+            (on_candidate.0.lock().unwrap())(path, exists);
+        }
+        exists
+    }
+}
+
+/// Count the items declared directly in a module, including in inline modules nested in it.
+fn count_items(items: &[Item]) -> usize {
+    items
+        .iter()
+        .map(|item| match item {
+            Item::Mod(ItemMod {
+                content: Some((_, items)),
+                ..
+            }) => 1 + count_items(items),
+            _ => 1,
+        })
+        .sum()
+}
+
+/// The crates that are available in every crate without being declared as dependencies.
+const SHADOWABLE_CRATES: &[&str] = &["std", "core", "alloc", "proc_macro", "test"];
+
+/// Get the path named by an `include!` in item position.
+pub(crate) fn include_path(item: &Item) -> Option<String> {
+    match item {
+        Item::Macro(item) if item.ident.is_none() && item.mac.path.is_ident("include") => {
+            path_value(&item.mac.parse_body().ok()?)
+        }
+        _ => None,
+    }
+}
+
+/// Get the path of a macro as it's written, such as `cfg_if::cfg_if`.
+fn macro_name(path: &syn::Path) -> String {
+    let segments = path
+        .segments
+        .iter()
+        .map(|segment| segment.ident.to_string())
+        .collect::<Vec<_>>()
+        .join("::");
+    if path.leading_colon.is_some() {
+        format!("::{}", segments)
+    } else {
+        segments
+    }
+}
+
+/// Check if the attributes include `#[cfg(test)]`.
+fn is_cfg_test(attrs: &[Attribute]) -> bool {
+    attrs.iter().any(|attr| {
+        attr.path().is_ident("cfg")
+            && attr
+                .parse_args::<syn::Path>()
+                .is_ok_and(|path| path.is_ident("test"))
+    })
+}
+
+impl<'a, R: FileResolver> VisitMut for Visitor<'a, R> {
+    fn visit_file_mut(&mut self, i: &mut syn::File) {
+        self.visit_items_mut(&mut i.items);
+    }
+
+    fn visit_item_macro_mut(&mut self, i: &mut ItemMacro) {
+        // `macro_rules!` definitions aren't invocations, and can't declare modules until invoked.
+        if i.mac.path.is_ident("macro_rules") {
+            return;
+        }
+
+        if self.options.collect_warnings && i.ident.is_none() {
+            let name = macro_name(&i.mac.path);
+            self.log.warnings.push(InlineWarning::new(
+                self.path,
+                &self.module_path,
+                i.span(),
+                WarningKind::ItemMacro(name),
+            ));
+        }
+
+        syn::visit_mut::visit_item_macro_mut(self, i);
+    }
+
+    fn visit_macro_mut(&mut self, i: &mut Macro) {
+        self.log
+            .macro_invocations
+            .entry(self.path.to_path_buf())
+            .or_default()
+            .push(i.span());
+    }
+
+    fn visit_item_mod_mut(&mut self, i: &mut ItemMod) {
+        if let Some(target) = self.target {
+            let skip = match target.get(self.module_path.len()) {
+                Some(segment) => i.ident != segment,
+                None => self.stop_at_target,
+            };
+            if skip {
+                return;
+            }
+        }
+
+        // `rustc` treats a `#[path]` on an inline module as the directory for the modules
+        // declared inside it, but callers may opt to resolve those modules by ident instead.
+        if i.content.is_some() && self.options.ignore_path_on_inline_mods {
+            self.mod_context.push(ModSegment::Ident(i.ident.clone()));
+        } else {
+            self.mod_context
+                .push(ModSegment::from_item(i, self.options.cfg_options.as_ref()));
+        }
+        if self.options.warn_shadowing && self.module_path.is_empty() {
+            let name = i.ident.to_string();
+            if SHADOWABLE_CRATES.contains(&name.as_str()) {
+                self.log.warnings.push(InlineWarning::new(
+                    self.path,
+                    &self.module_path,
+                    i.ident.span(),
+                    WarningKind::ShadowedCrate(name),
+                ));
+            }
+        }
+        self.module_path.push(i.ident.to_string());
+
+        let resolution = if i.content.is_some() {
+            ResolutionKind::Inline
+        } else if self
+            .options
+            .extra_modules
+            .iter()
+            .any(|(module_path, _)| *module_path == self.module_path)
+        {
+            ResolutionKind::Extra
+        } else if self.mod_context.last().is_some_and(ModSegment::is_ident) {
+            ResolutionKind::Ident
+        } else if is_cfg_attr_path(&i.attrs, self.options.cfg_options.as_ref()) {
+            ResolutionKind::CfgAttr
+        } else {
+            ResolutionKind::Path
+        };
+        let index = self.log.modules.push(ModuleNode {
+            module_path: self.module_path.clone(),
+            parent: self.parent,
+            declaration_index: self.siblings,
+            declared_in: self.path.to_path_buf(),
+            inline: i.content.is_some(),
+            file: None,
+            line_count: None,
+            duration: None,
+            content_line: i
+                .content
+                .as_ref()
+                .map(|(brace, _)| brace.span.open().start().line),
+            span: i.span(),
+            resolution,
+        });
+        self.siblings += 1;
+
+        // Only files can nest modules without bound, so inline modules are always visited.
+        if i.content.is_none()
+            && self
+                .options
+                .max_depth
+                .is_some_and(|max| self.module_path.len() > max)
+        {
+            let path = match self.candidates() {
+                Ok(candidates) => self.choose_candidate(&candidates),
+                Err((path, _)) => path,
+            };
+            let options = self.options;
+            self.log.errors.push(InlineError::new(
+                options.output_path(self.path),
+                i,
+                &self.module_path,
+                options.output_path(&path),
+                Error::DepthLimitExceeded,
+            ));
+        } else if let Some((_, items)) = &mut i.content {
+            let parent = self.parent.replace(index);
+            let siblings = std::mem::take(&mut self.siblings);
+            self.visit_items_mut(items);
+            self.parent = parent;
+            self.siblings = siblings;
+        } else if let Err((path, kind)) = self.expand(i, index) {
+            let suggestion = match &kind {
+                Error::Io(err)
+                    if self.options.suggest_on_missing && err.kind() == io::ErrorKind::NotFound =>
+                {
+                    suggest_path(&*self.resolver, &path)
                 }
+                _ => None,
+            };
+
+            if self.options.error_stubs {
+                let message = format!("could not inline: {}: {}", path.display(), kind.detail());
+                i.content = Some((
+                    Default::default(),
+                    vec![parse_quote!(compile_error!(#message);)],
+                ));
+                i.semi = None;
             }
+
+            // If something goes wrong, leave the module alone unless it should be stubbed.
+            let options = self.options;
+            self.log.errors.push(
+                InlineError::new(
+                    options.output_path(self.path),
+                    i,
+                    &self.module_path,
+                    options.output_path(&path),
+                    kind,
+                )
+                .with_suggestion(suggestion.map(|path| options.output_path(&path))),
+            );
         }
 
+        self.module_path.pop();
         self.mod_context.pop();
     }
 }
@@ -104,14 +905,16 @@ mod tests {
     use std::path::Path;
     use syn::visit_mut::VisitMut;
 
-    use super::Visitor;
-    use crate::PathCommentResolver;
+    use super::{Log, Visitor};
+    use crate::{InlinerBuilder, PathCommentResolver};
 
     #[test]
     fn ident_in_lib() {
         let path = Path::new("./lib.rs");
-        let mut resolver = PathCommentResolver::default();
-        let mut visitor = Visitor::new(&path, true, None, &mut resolver);
+        let mut resolver = PathCommentResolver;
+        let options = InlinerBuilder::default();
+        let mut log = Log::default();
+        let mut visitor = Visitor::new(path, true, &options, &mut log, &mut resolver);
         let mut file = syn::parse_file("mod c;").unwrap();
         visitor.visit_file_mut(&mut file);
         assert_eq!(
@@ -128,8 +931,10 @@ mod tests {
     #[test]
     fn path_attr() {
         let path = std::path::Path::new("./lib.rs");
-        let mut resolver = PathCommentResolver::default();
-        let mut visitor = Visitor::new(&path, true, None, &mut resolver);
+        let mut resolver = PathCommentResolver;
+        let options = InlinerBuilder::default();
+        let mut log = Log::default();
+        let mut visitor = Visitor::new(path, true, &options, &mut log, &mut resolver);
         let mut file = syn::parse_file(r#"#[path = "foo/bar.rs"] mod c;"#).unwrap();
         visitor.visit_file_mut(&mut file);
         assert_eq!(
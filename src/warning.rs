@@ -0,0 +1,128 @@
+//! Problems that didn't stop inlining but may make the output incomplete.
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use proc_macro2::Span;
+
+/// Something found while inlining that may make the output incomplete.
+#[derive(Debug, Clone)]
+pub struct InlineWarning {
+    src_path: PathBuf,
+    module_path: Vec<String>,
+    span: Span,
+    kind: WarningKind,
+}
+
+impl InlineWarning {
+    pub(crate) fn new(
+        src_path: impl Into<PathBuf>,
+        module_path: &[String],
+        span: Span,
+        kind: WarningKind,
+    ) -> Self {
+        Self {
+            src_path: src_path.into(),
+            module_path: module_path.to_vec(),
+            span,
+            kind,
+        }
+    }
+
+    /// The file containing the code that caused the warning.
+    pub fn src_path(&self) -> &Path {
+        &self.src_path
+    }
+
+    /// The logical path of the module containing the code that caused the warning. Empty for
+    /// the crate root.
+    pub fn module_path(&self) -> &[String] {
+        &self.module_path
+    }
+
+    /// The `Span` of the code that caused the warning in `self.src_path()`.
+    pub fn span(&self) -> Span {
+        self.span
+    }
+
+    /// The reason for the warning.
+    pub fn kind(&self) -> &WarningKind {
+        &self.kind
+    }
+}
+
+impl fmt::Display for InlineWarning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let start = self.span.start();
+        write!(
+            f,
+            "{}:{}:{}: warning: {}",
+            self.src_path.display(),
+            start.line,
+            start.column,
+            self.kind
+        )
+    }
+}
+
+/// The reasons inlining may be incomplete.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WarningKind {
+    /// A macro was invoked where an item was expected, so it may expand to `mod` items that
+    /// can't be inlined. Contains the macro's path, e.g. `cfg_if::cfg_if`.
+    ItemMacro(String),
+
+    /// The file didn't parse, so only the valid Rust in its first `n` lines was inlined.
+    TruncatedParse(usize),
+
+    /// A module at the crate root has the same name as a crate that is always available, such
+    /// as `std` or `core`, so paths starting with that name refer to the module instead.
+    ShadowedCrate(String),
+
+    /// A module was loaded from the file named by its `#[path]` attribute, but a different
+    /// file exists where the module would be found by its name.
+    PathMismatch {
+        /// The file named by the `#[path]` attribute.
+        path: PathBuf,
+        /// The file that would be loaded without the `#[path]` attribute.
+        ident_path: PathBuf,
+    },
+
+    /// An `include!` in item position wasn't expanded because its file couldn't be read or
+    /// parsed.
+    UnresolvedInclude {
+        /// The file named by the `include!`.
+        path: PathBuf,
+        /// Why the file couldn't be included.
+        reason: String,
+    },
+}
+
+impl fmt::Display for WarningKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            WarningKind::ItemMacro(name) => {
+                write!(f, "`{}!` may declare modules that can't be inlined", name)
+            }
+            WarningKind::ShadowedCrate(name) => {
+                write!(f, "module `{}` shadows the `{}` crate", name, name)
+            }
+            WarningKind::PathMismatch { path, ident_path } => write!(
+                f,
+                "`#[path]` loads {}, but {} would be loaded without it",
+                path.display(),
+                ident_path.display()
+            ),
+            WarningKind::UnresolvedInclude { path, reason } => {
+                write!(f, "couldn't include {}: {}", path.display(), reason)
+            }
+            WarningKind::TruncatedParse(lines) => {
+                write!(
+                    f,
+                    "parse error; only the first {} lines were inlined",
+                    lines
+                )
+            }
+        }
+    }
+}
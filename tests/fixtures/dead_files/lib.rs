@@ -0,0 +1,3 @@
+//! A root file that only declares `used`.
+
+mod used;
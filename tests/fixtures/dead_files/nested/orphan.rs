@@ -0,0 +1 @@
+//! A file in a directory no module declares.
@@ -0,0 +1 @@
+//! A file no module declares.
@@ -0,0 +1 @@
+//! A module declared by the root.
@@ -0,0 +1,3 @@
+//! The library root of a crate that also has a binary root.
+
+pub mod shared;
@@ -0,0 +1,7 @@
+//! The binary root of a crate that also has a library root.
+
+mod shared;
+
+fn main() {
+    shared::greet();
+}
@@ -0,0 +1,3 @@
+//! A module declared by both crate roots.
+
+pub fn greet() {}
@@ -0,0 +1,3 @@
+// Generated by build.rs; do not edit.
+
+pub const KEYWORDS: &[&str] = &["fn", "mod", "struct"];
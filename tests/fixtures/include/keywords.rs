@@ -0,0 +1,3 @@
+//! The keywords recognized by the macro.
+
+include!("generated/keywords.rs");
@@ -0,0 +1,3 @@
+//! A proc-macro style crate that includes a generated keyword table.
+
+mod keywords;
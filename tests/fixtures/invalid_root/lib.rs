@@ -0,0 +1,3 @@
+mod child;
+
+this is not valid rust
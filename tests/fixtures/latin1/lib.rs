@@ -0,0 +1,3 @@
+//! A root file saved as Latin-1: caf.
+
+mod util;
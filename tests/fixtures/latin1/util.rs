@@ -0,0 +1,3 @@
+//! Also Latin-1: nave.
+
+pub fn util() {}
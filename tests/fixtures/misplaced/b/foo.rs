@@ -0,0 +1,3 @@
+//! The file that `lib.rs` meant to include.
+
+pub fn foo() {}
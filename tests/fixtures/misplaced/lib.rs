@@ -0,0 +1,4 @@
+//! A root file whose `#[path]` points at a directory that does not exist.
+
+#[path = "a/foo.rs"]
+mod foo;
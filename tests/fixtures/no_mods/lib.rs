@@ -0,0 +1,5 @@
+//! A root file without any modules.
+
+pub fn answer() -> usize {
+    42
+}
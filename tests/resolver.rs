@@ -1,7 +1,10 @@
 //! Test that syn-inline-mod can resolve this crate's lib.rs properly.
 
-use std::path::Path;
-use syn_inline_mod::InlinerBuilder;
+use quote::ToTokens;
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use syn_inline_mod::{parse_source, Error, FileResolver, InlinerBuilder};
 
 #[test]
 fn resolve_lib() {
@@ -35,9 +38,25 @@ fn resolve_lib() {
         file_list,
         vec![
             "src/lib.rs",
+            "src/annotate.rs",
+            "src/canonical.rs",
+            "src/cfg.rs",
+            "src/content_store.rs",
+            "src/diagnostics.rs",
+            "src/incremental.rs",
+            "src/inliner.rs",
             "src/mod_path.rs",
+            "src/module_graph.rs",
+            "src/module_iter.rs",
+            "src/plan.rs",
             "src/resolver.rs",
+            "src/source_map.rs",
+            "src/stats.rs",
+            "src/trace.rs",
+            "src/verify.rs",
+            "src/vfs_resolver.rs",
             "src/visitor.rs",
+            "src/warning.rs",
         ]
     );
 
@@ -46,3 +65,261 @@ fn resolve_lib() {
         assert_eq!(&disk_contents, contents, "file contents match");
     }
 }
+
+#[test]
+fn check_lib() {
+    let manifest_dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+
+    assert!(InlinerBuilder::new()
+        .check(&manifest_dir.join("src/lib.rs"))
+        .expect("src/lib.rs should parse successfully"));
+    assert!(!InlinerBuilder::new()
+        .check(&manifest_dir.join("tests/fixtures/misplaced/lib.rs"))
+        .expect("misplaced/lib.rs should parse successfully"));
+    assert!(InlinerBuilder::new()
+        .check(&manifest_dir.join("src/missing.rs"))
+        .is_err());
+}
+
+/// The callback receives the root file's source even if it declares no modules or fails to
+/// parse.
+#[test]
+fn callback_sees_root() {
+    let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+
+    for (root, parses) in &[("no_mods/lib.rs", true), ("invalid_root/lib.rs", false)] {
+        let root = fixtures.join(root);
+        let mut files_seen = vec![];
+
+        let res = InlinerBuilder::new().inline_with_callback(&root, |path, file| {
+            files_seen.push((path.to_path_buf(), file));
+        });
+        assert_eq!(res.is_ok(), *parses);
+
+        assert_eq!(
+            files_seen,
+            vec![(root.clone(), std::fs::read_to_string(&root).unwrap())]
+        );
+    }
+}
+
+#[test]
+fn suggest_on_missing() {
+    let manifest_dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let lib_rs = manifest_dir.join("tests/fixtures/misplaced/lib.rs");
+
+    let res = InlinerBuilder::new()
+        .suggest_on_missing(true)
+        .parse_and_inline_modules(&lib_rs)
+        .expect("lib.rs should parse successfully");
+
+    assert_eq!(res.errors().len(), 1);
+    assert_eq!(
+        res.errors()[0].suggestion(),
+        Some(
+            manifest_dir
+                .join("tests/fixtures/misplaced/b/foo.rs")
+                .as_path()
+        )
+    );
+
+    let res = InlinerBuilder::new()
+        .parse_and_inline_modules(&lib_rs)
+        .expect("lib.rs should parse successfully");
+    assert_eq!(res.errors()[0].suggestion(), None);
+}
+
+/// A crate with both `main.rs` and `lib.rs` roots resolves `mod shared;` in each to the same
+/// `src/shared.rs`.
+#[test]
+fn dual_root() {
+    let src = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/dual_root/src");
+
+    for root in &["main.rs", "lib.rs"] {
+        let res = InlinerBuilder::new()
+            .parse_and_inline_modules(&src.join(root))
+            .expect("root should parse successfully");
+
+        assert!(!res.has_errors(), "{} has no errors", root);
+        assert_eq!(
+            res.module_graph().modules()[0].file(),
+            Some(src.join("shared.rs").as_path()),
+            "{} resolves shared.rs",
+            root
+        );
+    }
+}
+
+#[test]
+fn write_to() {
+    let manifest_dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let result = InlinerBuilder::new()
+        .parse_and_inline_modules(&manifest_dir.join("tests/fixtures/dual_root/src/main.rs"))
+        .unwrap();
+
+    let out = Path::new(env!("CARGO_TARGET_TMPDIR")).join("write_to.rs");
+    result.write_to(&out).unwrap();
+
+    let written = syn::parse_file(&std::fs::read_to_string(&out).unwrap()).unwrap();
+    assert_eq!(
+        written.to_token_stream().to_string(),
+        result.output().to_token_stream().to_string()
+    );
+}
+
+#[cfg(feature = "encoding_rs")]
+#[test]
+fn latin1_encoding() {
+    let lib_rs = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/latin1/lib.rs");
+
+    let res = InlinerBuilder::new()
+        .encoding(encoding_rs::WINDOWS_1252)
+        .parse_and_inline_modules(&lib_rs)
+        .expect("lib.rs should decode and parse successfully");
+    assert!(!res.has_errors());
+
+    assert!(InlinerBuilder::new()
+        .parse_and_inline_modules(&lib_rs)
+        .is_err());
+}
+
+#[test]
+fn unreferenced_files() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/dead_files");
+
+    let unreferenced = InlinerBuilder::new()
+        .unreferenced_files(&dir.join("lib.rs"), &dir)
+        .expect("lib.rs and its directory should be readable");
+
+    assert_eq!(
+        unreferenced,
+        vec![dir.join("nested/orphan.rs"), dir.join("unused.rs")]
+    );
+}
+
+/// A symbolic link back to a parent directory is only searched once.
+#[cfg(unix)]
+#[test]
+fn unreferenced_files_symlink_cycle() {
+    let dir = std::env::temp_dir().join(format!("syn-inline-mod-cycle-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(dir.join("nested")).unwrap();
+    std::fs::write(dir.join("lib.rs"), "").unwrap();
+    std::fs::write(dir.join("nested/orphan.rs"), "").unwrap();
+    std::os::unix::fs::symlink(&dir, dir.join("nested/loop")).unwrap();
+
+    let unreferenced = InlinerBuilder::new().unreferenced_files(&dir.join("lib.rs"), &dir);
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(unreferenced.unwrap(), vec![dir.join("nested/orphan.rs")]);
+}
+
+/// Modules that reach each other through a symbolic link are reported as a cycle.
+#[cfg(unix)]
+#[test]
+fn symlink_cycle() {
+    let dir = std::env::temp_dir().join(format!("syn-inline-mod-link-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("lib.rs"), r#"#[path = "link/a.rs"] mod a;"#).unwrap();
+    std::fs::write(dir.join("a.rs"), r#"#[path = "b.rs"] mod b;"#).unwrap();
+    std::fs::write(dir.join("b.rs"), r#"#[path = "link/a.rs"] mod a;"#).unwrap();
+    std::os::unix::fs::symlink(".", dir.join("link")).unwrap();
+
+    let res = InlinerBuilder::new().parse_and_inline_modules(&dir.join("lib.rs"));
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    let res = res.expect("lib.rs should parse successfully");
+    assert_eq!(res.errors().len(), 1);
+    match res.errors()[0].kind() {
+        Error::Cycle(chain) => assert_eq!(
+            chain,
+            &[
+                dir.join("link/a.rs"),
+                dir.join("link/b.rs"),
+                dir.join("link/link/a.rs")
+            ]
+        ),
+        kind => panic!("expected a cycle, got {}", kind),
+    }
+}
+
+#[test]
+fn max_file_bytes() {
+    let lib_rs = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/dual_root/src/lib.rs");
+
+    let res = InlinerBuilder::new()
+        .max_file_bytes(100)
+        .parse_and_inline_modules(&lib_rs)
+        .expect("lib.rs and shared.rs are under the limit");
+    assert!(!res.has_errors());
+
+    let res = InlinerBuilder::new()
+        .max_file_bytes(64)
+        .parse_and_inline_modules(&lib_rs);
+    assert!(matches!(res, Err(Error::FileTooLarge { max: 64, .. })));
+}
+
+#[test]
+fn expand_includes() {
+    let lib_rs = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/include/lib.rs");
+
+    let res = InlinerBuilder::new()
+        .expand_includes(true)
+        .parse_and_inline_modules(&lib_rs)
+        .expect("lib.rs should parse successfully");
+    assert!(!res.has_errors());
+    assert!(res.warnings().is_empty());
+
+    let keywords = res
+        .output()
+        .items
+        .iter()
+        .find_map(|item| match item {
+            syn::Item::Mod(item) => item.content.as_ref(),
+            _ => None,
+        })
+        .expect("keywords should be inlined");
+    assert!(matches!(&keywords.1[..], [syn::Item::Const(item)] if item.ident == "KEYWORDS"));
+}
+
+/// A resolver outside the crate, serving files from memory.
+struct MemoryResolver(HashMap<PathBuf, &'static str>);
+
+impl FileResolver for MemoryResolver {
+    fn path_exists(&self, path: &Path) -> bool {
+        self.0.contains_key(path)
+    }
+
+    fn resolve(&mut self, path: &Path) -> Result<syn::File, Error> {
+        match self.0.get(path) {
+            Some(src) => parse_source(src),
+            None => Err(io::Error::from(io::ErrorKind::NotFound).into()),
+        }
+    }
+}
+
+#[test]
+fn custom_resolver() {
+    let mut resolver = MemoryResolver(HashMap::from([
+        (PathBuf::from("mem/lib.rs"), "mod a;"),
+        (PathBuf::from("mem/a/mod.rs"), "mod b;"),
+        (PathBuf::from("mem/a/b.rs"), "pub struct B;"),
+    ]));
+
+    let res = InlinerBuilder::new()
+        .parse_and_inline_modules_with_resolver(Path::new("mem/lib.rs"), &mut resolver)
+        .expect("lib.rs should parse successfully");
+    assert!(!res.has_errors());
+    assert_eq!(
+        res.output().to_token_stream().to_string(),
+        quote::quote!(
+            mod a {
+                mod b {
+                    pub struct B;
+                }
+            }
+        )
+        .to_string()
+    );
+}